@@ -0,0 +1,49 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pixstage::argb1555::{argb1555_slice_to_rgba8, argb1555_to_rgba8};
+use pixstage::rgb565::{rgb565_slice_to_rgba8, rgb565_to_rgba8};
+use std::hint::black_box;
+
+const SIZES: [(u32, u32); 2] = [(320, 240), (3840, 2160)];
+
+fn pixels(width: u32, height: u32) -> Vec<u16> {
+    (0..width * height)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 16) as u16)
+        .collect()
+}
+
+fn scalar(src: &[u16], dst: &mut [u8], convert: fn(u16) -> [u8; 4]) {
+    for (pixel, out) in src.iter().zip(dst.chunks_exact_mut(4)) {
+        out.copy_from_slice(&convert(*pixel));
+    }
+}
+
+fn bench_format(
+    c: &mut Criterion,
+    name: &str,
+    convert_pixel: fn(u16) -> [u8; 4],
+    convert_slice: fn(&[u16], &mut [u8]),
+) {
+    let mut group = c.benchmark_group(name);
+    for (width, height) in SIZES {
+        let src = pixels(width, height);
+        let mut dst = vec![0u8; src.len() * 4];
+
+        let size = format!("{width}x{height}");
+        group.throughput(Throughput::Elements(src.len() as u64));
+        group.bench_with_input(BenchmarkId::new("scalar", &size), &src, |b, src| {
+            b.iter(|| scalar(black_box(src), black_box(&mut dst), convert_pixel))
+        });
+        group.bench_with_input(BenchmarkId::new("chunked", &size), &src, |b, src| {
+            b.iter(|| convert_slice(black_box(src), black_box(&mut dst)))
+        });
+    }
+    group.finish();
+}
+
+fn converters(c: &mut Criterion) {
+    bench_format(c, "rgb565", rgb565_to_rgba8, rgb565_slice_to_rgba8);
+    bench_format(c, "argb1555", argb1555_to_rgba8, argb1555_slice_to_rgba8);
+}
+
+criterion_group!(benches, converters);
+criterion_main!(benches);
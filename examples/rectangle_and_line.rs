@@ -158,7 +158,9 @@ impl ApplicationHandler for App {
         {
             let size = window.inner_size();
             let surface_texture =
-                SurfaceTexture::new(size.width.max(1), size.height.max(1), window.clone()).unwrap();
+                SurfaceTexture::new(size.width.max(1), size.height.max(1), window.clone())
+                    .unwrap()
+                    .with_scale_factor(window.scale_factor());
             let stage = pollster::block_on(PixstageRgba::new_async(
                 buffer_width,
                 buffer_height,
@@ -177,7 +179,8 @@ impl ApplicationHandler for App {
                 let size = window.inner_size();
                 let surface_texture =
                     SurfaceTexture::new(size.width.max(1), size.height.max(1), window.clone())
-                        .unwrap();
+                        .unwrap()
+                        .with_scale_factor(window.scale_factor());
                 let stage = PixstageRgba::new_async(buffer_width, buffer_height, surface_texture)
                     .await
                     .unwrap();
@@ -209,6 +212,11 @@ impl ApplicationHandler for App {
                     },
                 ..
             } => event_loop.exit(),
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                if let Some(stage) = state.stage.as_mut() {
+                    stage.set_scale_factor(scale_factor);
+                }
+            }
             WindowEvent::Resized(physical_size) => {
                 if let Some(stage) = state.stage.as_mut() {
                     if physical_size.width > 0 && physical_size.height > 0 {
@@ -0,0 +1,391 @@
+use crate::image::RgbaImage;
+use crate::scaling::ScalingState;
+use wgpu::util::DeviceExt;
+
+/// What `render` draws behind the image, e.g. bezel artwork in the letterbox area.
+///
+/// Set it with a stage's `set_background` after construction. It covers the whole surface under
+/// the image (transparent pixels show it too) and replaces `clear_color` and the border color.
+/// Stages drawn with `render_to` or `draw` leave their surroundings alone and skip it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Background {
+    /// An RGBA image laid over the surface.
+    Image {
+        image: RgbaImage,
+        fit: BackgroundFit,
+    },
+    /// A blurred, dimmed copy of the frame covering the surface (ambient glow).
+    Glow {
+        /// Blur radius, in buffer pixels.
+        radius: f32,
+        /// Multiplier for the blurred colors (`1.0` keeps them as they are).
+        brightness: f32,
+    },
+}
+
+impl Background {
+    pub fn image(image: RgbaImage, fit: BackgroundFit) -> Self {
+        Background::Image { image, fit }
+    }
+
+    /// An ambient glow with a radius of 8 buffer pixels at half brightness.
+    pub fn glow() -> Self {
+        Background::Glow {
+            radius: 8.0,
+            brightness: 0.5,
+        }
+    }
+
+    /// Whether drawing it samples the stage's frame.
+    pub(crate) fn samples_frame(&self) -> bool {
+        matches!(self, Background::Glow { .. })
+    }
+}
+
+/// How a background image is laid over the surface.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum BackgroundFit {
+    /// Scale to cover the surface while preserving aspect ratio, cropping the overflow.
+    #[default]
+    Cover,
+    /// Scale to the surface size, ignoring aspect ratio.
+    Stretch,
+    /// Repeat at its own size from the top-left corner.
+    Tile,
+}
+
+/// The shader's `Params` uniform.
+type ParamsUniform = [f32; 12];
+
+/// GPU state for drawing a `Background` in a stage's render pass.
+#[derive(Debug)]
+pub(crate) struct BackgroundLayer {
+    kind: LayerKind,
+    label: String,
+    params_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    /// The image texture, or `None` for the glow (which samples the frame).
+    image_view: Option<wgpu::TextureView>,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum LayerKind {
+    Image {
+        fit: BackgroundFit,
+        size: (u32, u32),
+    },
+    Glow {
+        radius: f32,
+        brightness: f32,
+    },
+}
+
+impl BackgroundLayer {
+    /// `frame_view` is the stage's RGBA texture, sampled by the glow.
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        background: &Background,
+        format: wgpu::TextureFormat,
+        frame_view: &wgpu::TextureView,
+        label: &str,
+    ) -> Self {
+        let (kind, image_view) = match background {
+            Background::Image { image, fit } => {
+                let size = (image.width.max(1), image.height.max(1));
+                let mut pixels = image.pixels.clone();
+                if image.width == 0
+                    || image.height == 0
+                    || pixels.len() != image.width as usize * image.height as usize * 4
+                {
+                    log::warn!(
+                        "{label}: background image data does not match {}x{}; using black",
+                        image.width,
+                        image.height
+                    );
+                    pixels = vec![0; size.0 as usize * size.1 as usize * 4];
+                }
+                let texture = device.create_texture_with_data(
+                    queue,
+                    &wgpu::TextureDescriptor {
+                        label: Some(&format!("{label}_texture")),
+                        size: wgpu::Extent3d {
+                            width: size.0,
+                            height: size.1,
+                            depth_or_array_layers: 1,
+                        },
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: wgpu::TextureDimension::D2,
+                        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                        usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                        view_formats: &[],
+                    },
+                    wgpu::util::TextureDataOrder::LayerMajor,
+                    &pixels,
+                );
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                (LayerKind::Image { fit: *fit, size }, Some(view))
+            }
+            Background::Glow { radius, brightness } => (
+                LayerKind::Glow {
+                    radius: *radius,
+                    brightness: *brightness,
+                },
+                None,
+            ),
+        };
+
+        let address_mode = match kind {
+            LayerKind::Image {
+                fit: BackgroundFit::Tile,
+                ..
+            } => wgpu::AddressMode::Repeat,
+            _ => wgpu::AddressMode::ClampToEdge,
+        };
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(&format!("{label}_sampler")),
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{label}_params_buffer")),
+            size: std::mem::size_of::<ParamsUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(&format!("{label}_bind_group_layout")),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<ParamsUniform>() as u64,
+                        ),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline = create_pipeline(device, &bind_group_layout, format, label);
+        let bind_group = create_bind_group(
+            device,
+            &bind_group_layout,
+            image_view.as_ref().unwrap_or(frame_view),
+            &sampler,
+            &params_buffer,
+            label,
+        );
+
+        Self {
+            kind,
+            label: label.to_string(),
+            params_buffer,
+            sampler,
+            image_view,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    /// Whether drawing it samples the stage's frame.
+    pub(crate) fn samples_frame(&self) -> bool {
+        matches!(self.kind, LayerKind::Glow { .. })
+    }
+
+    /// Follow a replaced frame texture (after `resize_buffer`).
+    pub(crate) fn set_frame_view(&mut self, device: &wgpu::Device, frame_view: &wgpu::TextureView) {
+        if self.image_view.is_some() {
+            return;
+        }
+        self.bind_group = create_bind_group(
+            device,
+            &self.pipeline.get_bind_group_layout(0),
+            frame_view,
+            &self.sampler,
+            &self.params_buffer,
+            &self.label,
+        );
+    }
+
+    /// Lay the background out for `surface_size`; the glow follows the image's crop, rotation
+    /// and flips from `scaling_state`.
+    pub(crate) fn prepare(
+        &self,
+        queue: &wgpu::Queue,
+        surface_size: (u32, u32),
+        scaling_state: &ScalingState,
+    ) {
+        let params = params_bytes(self.kind, surface_size, scaling_state);
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+    }
+
+    /// Cover the pass's whole target; draw it before the image.
+    pub(crate) fn draw(&self, pass: &mut wgpu::RenderPass<'_>) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn params_bytes(
+    kind: LayerKind,
+    surface_size: (u32, u32),
+    scaling_state: &ScalingState,
+) -> ParamsUniform {
+    let surface_width = surface_size.0.max(1) as f32;
+    let surface_height = surface_size.1.max(1) as f32;
+    // Texture coordinates at the surface center, and per surface width / height.
+    let (center, axis_x, axis_y, blur, brightness, glow) = match kind {
+        LayerKind::Image { fit, size } => {
+            let (image_width, image_height) = (size.0 as f32, size.1 as f32);
+            let (span_x, span_y) = match fit {
+                BackgroundFit::Stretch => (1.0, 1.0),
+                BackgroundFit::Cover => {
+                    let scale = (surface_width / image_width).max(surface_height / image_height);
+                    (
+                        surface_width / (image_width * scale),
+                        surface_height / (image_height * scale),
+                    )
+                }
+                BackgroundFit::Tile => (surface_width / image_width, surface_height / image_height),
+            };
+            let center = match fit {
+                BackgroundFit::Tile => [span_x / 2.0, span_y / 2.0],
+                _ => [0.5, 0.5],
+            };
+            (center, [span_x, 0.0], [0.0, span_y], [0.0, 0.0], 1.0, 0.0)
+        }
+        LayerKind::Glow { radius, brightness } => {
+            // Cover the surface with the displayed (cropped, rotated) image.
+            let (center, [tex_x, tex_y]) = scaling_state.tex_transform();
+            let (displayed_width, displayed_height) = {
+                let crop = scaling_state.crop;
+                if scaling_state.rotation.swaps_axes() {
+                    (crop.height as f32, crop.width as f32)
+                } else {
+                    (crop.width as f32, crop.height as f32)
+                }
+            };
+            let scale = (surface_width / displayed_width).max(surface_height / displayed_height);
+            let span_x = surface_width / (displayed_width * scale);
+            let span_y = surface_height / (displayed_height * scale);
+            let (buffer_width, buffer_height) = scaling_state.buffer_size;
+            (
+                center,
+                [tex_x[0] * span_x, tex_x[1] * span_x],
+                [tex_y[0] * span_y, tex_y[1] * span_y],
+                [
+                    radius.max(0.0) / buffer_width.max(1) as f32,
+                    radius.max(0.0) / buffer_height.max(1) as f32,
+                ],
+                brightness,
+                1.0,
+            )
+        }
+    };
+    [
+        center[0], center[1], axis_x[0], axis_x[1], axis_y[0], axis_y[1], blur[0], blur[1],
+        brightness, glow, 0.0, 0.0,
+    ]
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    view: &wgpu::TextureView,
+    sampler: &wgpu::Sampler,
+    params_buffer: &wgpu::Buffer,
+    label: &str,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(&format!("{label}_bind_group")),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: params_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+    label: &str,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(&format!("{label}_shader")),
+        source: wgpu::ShaderSource::Wgsl(include_str!("shaders/background.wgsl").into()),
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(&format!("{label}_pipeline_layout")),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format!("{label}_pipeline")),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: Some("fs_main"),
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
@@ -0,0 +1,382 @@
+use crate::Rect;
+
+/// How source pixels are combined with destination pixels during a blit.
+///
+/// Only `PixstageRgba` blends; the packed and indexed stages always copy.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum BlendMode {
+    /// Overwrite the destination pixel.
+    #[default]
+    Copy,
+    /// Standard "source over" alpha blending.
+    Alpha,
+    /// Add the source color (weighted by source alpha), saturating at 255.
+    Additive,
+    /// Multiply the destination color by the source color (weighted by source alpha).
+    Multiply,
+}
+
+/// A read-only view of pixels to blit from.
+#[derive(Debug, Copy, Clone)]
+pub struct BlitSource<'a, P> {
+    pixels: &'a [P],
+    width: u32,
+    height: u32,
+}
+
+impl<'a, P> BlitSource<'a, P> {
+    /// Create a source view; returns `None` if `pixels` is shorter than `width * height`.
+    pub fn new(pixels: &'a [P], width: u32, height: u32) -> Option<Self> {
+        if pixels.len() < width as usize * height as usize {
+            return None;
+        }
+        Some(Self {
+            pixels,
+            width,
+            height,
+        })
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// The full source bounds, useful as `src_rect`.
+    pub fn rect(&self) -> Option<Rect> {
+        Rect::new(0, 0, self.width, self.height)
+    }
+}
+
+impl<'a> BlitSource<'a, [u8; 4]> {
+    /// Create a source view over tightly packed RGBA8 bytes (e.g. `PixstageRgba::frame()`).
+    pub fn from_rgba8(bytes: &'a [u8], width: u32, height: u32) -> Option<Self> {
+        if !bytes.len().is_multiple_of(4) {
+            return None;
+        }
+        Self::new(bytemuck::cast_slice(bytes), width, height)
+    }
+}
+
+/// Options for `blit` on the Pixstage variants.
+///
+/// `P` is the stage's pixel type: `[u8; 4]` for RGBA8, `u16` for RGB565/ARGB1555 and `u8` for
+/// Indexed8 (where the key is a palette index).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BlitOptions<P> {
+    /// Source pixels equal to this value are skipped.
+    pub key: Option<P>,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub blend: BlendMode,
+}
+
+impl<P> Default for BlitOptions<P> {
+    fn default() -> Self {
+        Self {
+            key: None,
+            flip_x: false,
+            flip_y: false,
+            blend: BlendMode::Copy,
+        }
+    }
+}
+
+impl<P> BlitOptions<P> {
+    pub fn with_key(mut self, key: P) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn with_flip_x(mut self, flip_x: bool) -> Self {
+        self.flip_x = flip_x;
+        self
+    }
+
+    pub fn with_flip_y(mut self, flip_y: bool) -> Self {
+        self.flip_y = flip_y;
+        self
+    }
+
+    pub fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+}
+
+/// Copy `src_rect` of `src` into `dst` at `(dst_x, dst_y)`, clipping against both buffers.
+///
+/// Returns the destination rect that was touched, if any.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn blit<P: Copy + PartialEq>(
+    dst: &mut [P],
+    dst_width: u32,
+    dst_height: u32,
+    src: &BlitSource<'_, P>,
+    src_rect: Rect,
+    dst_x: i32,
+    dst_y: i32,
+    options: &BlitOptions<P>,
+    mut combine: impl FnMut(P, P) -> P,
+) -> Option<Rect> {
+    let src_rect = src_rect.clamp_to(src.width, src.height)?;
+
+    let x0 = (dst_x as i64).max(0);
+    let y0 = (dst_y as i64).max(0);
+    let x1 = (dst_x as i64 + src_rect.width as i64).min(dst_width as i64);
+    let y1 = (dst_y as i64 + src_rect.height as i64).min(dst_height as i64);
+    if x0 >= x1 || y0 >= y1 {
+        return None;
+    }
+
+    for y in y0..y1 {
+        let local_y = (y - dst_y as i64) as u32;
+        let src_y = if options.flip_y {
+            src_rect.y + src_rect.height - 1 - local_y
+        } else {
+            src_rect.y + local_y
+        };
+        let src_row = src_y as usize * src.width as usize;
+        let dst_row = y as usize * dst_width as usize;
+
+        for x in x0..x1 {
+            let local_x = (x - dst_x as i64) as u32;
+            let src_x = if options.flip_x {
+                src_rect.x + src_rect.width - 1 - local_x
+            } else {
+                src_rect.x + local_x
+            };
+
+            let pixel = src.pixels[src_row + src_x as usize];
+            if options.key == Some(pixel) {
+                continue;
+            }
+            let target = &mut dst[dst_row + x as usize];
+            *target = combine(*target, pixel);
+        }
+    }
+
+    Rect::new(x0 as u32, y0 as u32, (x1 - x0) as u32, (y1 - y0) as u32)
+}
+
+/// Combine an RGBA8 source pixel into a destination pixel using `mode`.
+pub(crate) fn blend_rgba8(mode: BlendMode, dst: [u8; 4], src: [u8; 4]) -> [u8; 4] {
+    let sa = src[3] as u32;
+    match mode {
+        BlendMode::Copy => src,
+        BlendMode::Alpha => {
+            let inv = 255 - sa;
+            let mut out = [0u8; 4];
+            for c in 0..3 {
+                out[c] = div255(src[c] as u32 * sa + dst[c] as u32 * inv);
+            }
+            out[3] = div255(sa * 255 + dst[3] as u32 * inv);
+            out
+        }
+        BlendMode::Additive => {
+            let mut out = dst;
+            for c in 0..3 {
+                out[c] = (dst[c] as u32 + div255(src[c] as u32 * sa) as u32).min(255) as u8;
+            }
+            out
+        }
+        BlendMode::Multiply => {
+            let inv = 255 - sa;
+            let mut out = dst;
+            for c in 0..3 {
+                let multiplied = div255(dst[c] as u32 * src[c] as u32) as u32;
+                out[c] = div255(multiplied * sa + dst[c] as u32 * inv);
+            }
+            out
+        }
+    }
+}
+
+/// `value / 255` rounded to nearest, for `value <= 255 * 255`.
+fn div255(value: u32) -> u8 {
+    ((value + 128 + ((value + 128) >> 8)) >> 8) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `width` x `height` source whose pixels are numbered row by row from 1.
+    fn numbered(width: u32, height: u32) -> Vec<u8> {
+        (1..=(width * height) as u8).collect()
+    }
+
+    fn copy(
+        dst: &mut [u8],
+        dst_size: (u32, u32),
+        src: &BlitSource<'_, u8>,
+        at: (i32, i32),
+        options: &BlitOptions<u8>,
+    ) -> Option<Rect> {
+        blit(
+            dst,
+            dst_size.0,
+            dst_size.1,
+            src,
+            src.rect().unwrap(),
+            at.0,
+            at.1,
+            options,
+            |_, src| src,
+        )
+    }
+
+    #[test]
+    fn negative_destination_is_clipped() {
+        let pixels = numbered(3, 3);
+        let src = BlitSource::new(&pixels, 3, 3).unwrap();
+        let mut dst = [0u8; 16];
+        let touched = copy(&mut dst, (4, 4), &src, (-1, -2), &BlitOptions::default());
+        assert_eq!(touched, Rect::new(0, 0, 2, 1));
+        assert_eq!(&dst[..4], &[8, 9, 0, 0]);
+        assert!(dst[4..].iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn partially_off_screen_destination_is_clipped() {
+        let pixels = numbered(3, 3);
+        let src = BlitSource::new(&pixels, 3, 3).unwrap();
+        let mut dst = [0u8; 16];
+        let touched = copy(&mut dst, (4, 4), &src, (2, 3), &BlitOptions::default());
+        assert_eq!(touched, Rect::new(2, 3, 2, 1));
+        assert_eq!(&dst[12..], &[0, 0, 1, 2]);
+        assert!(dst[..12].iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn fully_off_screen_destination_touches_nothing() {
+        let pixels = numbered(3, 3);
+        let src = BlitSource::new(&pixels, 3, 3).unwrap();
+        let mut dst = [0u8; 16];
+        assert_eq!(
+            copy(&mut dst, (4, 4), &src, (4, 0), &BlitOptions::default()),
+            None
+        );
+        assert_eq!(
+            copy(&mut dst, (4, 4), &src, (-3, 0), &BlitOptions::default()),
+            None
+        );
+        assert_eq!(
+            copy(
+                &mut dst,
+                (4, 4),
+                &src,
+                (i32::MIN, i32::MAX),
+                &BlitOptions::default()
+            ),
+            None
+        );
+        assert!(dst.iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn source_rect_is_clamped_to_source() {
+        let pixels = numbered(3, 3);
+        let src = BlitSource::new(&pixels, 3, 3).unwrap();
+        let mut dst = [0u8; 4];
+        let touched = blit(
+            &mut dst,
+            2,
+            2,
+            &src,
+            Rect::new(2, 2, 5, 5).unwrap(),
+            0,
+            0,
+            &BlitOptions::default(),
+            |_, src| src,
+        );
+        assert_eq!(touched, Rect::new(0, 0, 1, 1));
+        assert_eq!(dst, [9, 0, 0, 0]);
+    }
+
+    #[test]
+    fn flips_apply_before_clipping() {
+        let pixels = numbered(3, 3);
+        let src = BlitSource::new(&pixels, 3, 3).unwrap();
+        let mut dst = [0u8; 4];
+        let options = BlitOptions::default().with_flip_x(true).with_flip_y(true);
+        // Only the top-left 2x2 of the flipped source lands in the destination.
+        let touched = copy(&mut dst, (2, 2), &src, (0, 0), &options);
+        assert_eq!(touched, Rect::new(0, 0, 2, 2));
+        assert_eq!(dst, [9, 8, 6, 5]);
+
+        // Clipped on the left: the flipped source's right column remains.
+        let mut dst = [0u8; 3];
+        let options = BlitOptions::default().with_flip_x(true);
+        copy(&mut dst, (1, 3), &src, (-2, 0), &options);
+        assert_eq!(dst, [1, 4, 7]);
+    }
+
+    #[test]
+    fn key_skips_pixels_before_combining() {
+        let pixels = [
+            [10, 20, 30, 255],
+            [0, 0, 0, 0],
+            [255, 0, 0, 128],
+            [1, 2, 3, 4],
+        ];
+        let src = BlitSource::new(&pixels, 2, 2).unwrap();
+        let mut dst = [[0, 0, 255, 255]; 4];
+        let options = BlitOptions::default()
+            .with_key([1, 2, 3, 4])
+            .with_blend(BlendMode::Alpha);
+        blit(
+            &mut dst,
+            2,
+            2,
+            &src,
+            src.rect().unwrap(),
+            0,
+            0,
+            &options,
+            |dst, src| blend_rgba8(options.blend, dst, src),
+        );
+        assert_eq!(dst[0], [10, 20, 30, 255]);
+        assert_eq!(dst[1], [0, 0, 255, 255]);
+        assert_eq!(dst[2], [128, 0, 127, 255]);
+        assert_eq!(dst[3], [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn blend_modes() {
+        let dst = [200, 100, 50, 255];
+        assert_eq!(
+            blend_rgba8(BlendMode::Copy, dst, [1, 2, 3, 4]),
+            [1, 2, 3, 4]
+        );
+        assert_eq!(blend_rgba8(BlendMode::Alpha, dst, [0, 0, 0, 0]), dst);
+        assert_eq!(
+            blend_rgba8(BlendMode::Alpha, dst, [9, 8, 7, 255]),
+            [9, 8, 7, 255]
+        );
+        assert_eq!(
+            blend_rgba8(BlendMode::Alpha, [0, 0, 0, 0], [255, 255, 255, 128]),
+            [128, 128, 128, 128]
+        );
+        assert_eq!(
+            blend_rgba8(BlendMode::Additive, dst, [100, 100, 100, 255]),
+            [255, 200, 150, 255]
+        );
+        assert_eq!(
+            blend_rgba8(BlendMode::Additive, dst, [100, 100, 100, 0]),
+            dst
+        );
+        assert_eq!(
+            blend_rgba8(BlendMode::Multiply, dst, [255, 128, 0, 255]),
+            [200, 50, 0, 255]
+        );
+        assert_eq!(blend_rgba8(BlendMode::Multiply, dst, [0, 0, 0, 0]), dst);
+    }
+
+    #[test]
+    fn div255_rounds_to_nearest() {
+        for value in 0..=255 * 255 {
+            let expected = (value as f64 / 255.0).round() as u32;
+            assert_eq!(div255(value) as u32, expected, "{value}");
+        }
+    }
+}
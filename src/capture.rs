@@ -0,0 +1,377 @@
+use crate::image::RgbaImage;
+use crate::upload::padded_bytes_per_row;
+use crate::{Error, FrameRecorder, FrameSink};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+/// What a stage passes to its `FrameRecorder` or `FrameSink` on each `render()`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum CaptureSource {
+    /// The stage's CPU-side buffer at its own size, before scaling.
+    #[default]
+    Buffer,
+    /// The rendered output at surface size, read back from the GPU: scaling, viewport, crop,
+    /// border, background and overlays included.
+    ///
+    /// Frames are read back without stalling the GPU, a frame or two late; `stop_recording`
+    /// and `set_frame_sink` wait for the ones still in flight. Only `render()` captures output, not `render_to` or
+    /// `draw`. Needs an 8-bit RGBA or BGRA surface format; otherwise the buffer is captured
+    /// instead.
+    Output,
+}
+
+const READBACK_PENDING: u8 = 0;
+const READBACK_READY: u8 = 1;
+const READBACK_FAILED: u8 = 2;
+
+/// Readback buffers in flight before frames are skipped.
+const SLOTS: usize = 3;
+
+#[derive(Debug)]
+struct Slot {
+    buffer: wgpu::Buffer,
+    readback: Arc<AtomicU8>,
+    /// Rendered frames this capture stands for (1 plus the frames skipped before it).
+    frames: u32,
+}
+
+/// Copies the stage's rendered output into mappable buffers and reads them back without
+/// blocking, with up to `SLOTS` frames in flight.
+#[derive(Debug)]
+pub(crate) struct OutputCapture {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    format: wgpu::TextureFormat,
+    size: (u32, u32),
+    padded_row: u32,
+    idle: Vec<Slot>,
+    /// The slot whose copy was recorded this frame but not yet submitted.
+    copied: Option<Slot>,
+    mapping: VecDeque<Slot>,
+    skipped: u32,
+    image: RgbaImage,
+}
+
+impl OutputCapture {
+    /// Whether output in `format` can be read back as RGBA8.
+    pub(crate) fn supports(format: wgpu::TextureFormat) -> bool {
+        matches!(
+            format,
+            wgpu::TextureFormat::Rgba8Unorm
+                | wgpu::TextureFormat::Rgba8UnormSrgb
+                | wgpu::TextureFormat::Bgra8Unorm
+                | wgpu::TextureFormat::Bgra8UnormSrgb
+        )
+    }
+
+    /// `format` must be `supports`ed.
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        size: (u32, u32),
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(&format!("{label}_texture")),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let padded_row = padded_bytes_per_row(size.0, 4);
+        let idle = (0..SLOTS)
+            .map(|index| Slot {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some(&format!("{label}_readback_buffer_{index}")),
+                    size: padded_row as u64 * size.1 as u64,
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                readback: Arc::new(AtomicU8::new(READBACK_PENDING)),
+                frames: 0,
+            })
+            .collect();
+        Self {
+            texture,
+            view,
+            format,
+            size,
+            padded_row,
+            idle,
+            copied: None,
+            mapping: VecDeque::with_capacity(SLOTS),
+            skipped: 0,
+            image: RgbaImage {
+                width: size.0,
+                height: size.1,
+                pixels: Vec::new(),
+            },
+        }
+    }
+
+    /// Whether this capture was made for a `format` surface of `size`.
+    pub(crate) fn matches(&self, format: wgpu::TextureFormat, size: (u32, u32)) -> bool {
+        self.format == format && self.size == size
+    }
+
+    /// Let `draw` render this frame's output into the capture texture and copy it to a readback
+    /// buffer. If every buffer is still in flight, nothing is drawn and the frame is counted
+    /// towards the next capture instead.
+    pub(crate) fn record(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        draw: impl FnOnce(&mut wgpu::CommandEncoder, &wgpu::TextureView),
+    ) {
+        let Some(mut slot) = self.idle.pop() else {
+            self.skipped += 1;
+            return;
+        };
+        draw(encoder, &self.view);
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &slot.buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_row),
+                    rows_per_image: Some(self.size.1),
+                },
+            },
+            wgpu::Extent3d {
+                width: self.size.0,
+                height: self.size.1,
+                depth_or_array_layers: 1,
+            },
+        );
+        slot.frames = 1 + std::mem::take(&mut self.skipped);
+        self.copied = Some(slot);
+    }
+
+    /// Start mapping this frame's copy; call after the frame's command buffer was submitted.
+    pub(crate) fn after_submit(&mut self) {
+        let Some(slot) = self.copied.take() else {
+            return;
+        };
+        let readback = Arc::clone(&slot.readback);
+        readback.store(READBACK_PENDING, Ordering::Release);
+        slot.buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let status = if result.is_ok() {
+                    READBACK_READY
+                } else {
+                    READBACK_FAILED
+                };
+                readback.store(status, Ordering::Release);
+            });
+        self.mapping.push_back(slot);
+    }
+
+    /// Hand every finished readback to `deliver` as RGBA8, with the number of rendered frames
+    /// it stands for, in render order. With `wait`, block until all frames in flight are done
+    /// and account for every rendered frame.
+    pub(crate) fn drain(
+        &mut self,
+        device: &wgpu::Device,
+        wait: bool,
+        mut deliver: impl FnMut(&RgbaImage, u32),
+    ) {
+        if self.mapping.is_empty() {
+            return;
+        }
+        let poll = if wait {
+            wgpu::PollType::wait_indefinitely()
+        } else {
+            wgpu::PollType::Poll
+        };
+        let _ = device.poll(poll);
+
+        while self
+            .mapping
+            .front()
+            .is_some_and(|slot| slot.readback.load(Ordering::Acquire) != READBACK_PENDING)
+        {
+            let Some(slot) = self.mapping.pop_front() else {
+                break;
+            };
+            if slot.readback.load(Ordering::Acquire) == READBACK_READY {
+                self.read(&slot);
+                slot.buffer.unmap();
+                deliver(&self.image, slot.frames);
+            } else {
+                self.skipped += slot.frames;
+            }
+            self.idle.push(slot);
+        }
+
+        // Frames rendered while every buffer was busy repeat the last one read back.
+        if wait && self.skipped > 0 && !self.image.pixels.is_empty() {
+            deliver(&self.image, std::mem::take(&mut self.skipped));
+        }
+    }
+
+    fn read(&mut self, slot: &Slot) {
+        let (width, height) = self.size;
+        let bgra = matches!(
+            self.format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        let pixels = &mut self.image.pixels;
+        pixels.clear();
+        pixels.reserve(width as usize * height as usize * 4);
+        let data = slot.buffer.slice(..).get_mapped_range();
+        for row in data.chunks_exact(self.padded_row as usize) {
+            // The surface is presented opaque, so record what is on screen.
+            for p in row[..width as usize * 4].chunks_exact(4) {
+                let rgba = if bgra {
+                    [p[2], p[1], p[0], 255]
+                } else {
+                    [p[0], p[1], p[2], 255]
+                };
+                pixels.extend_from_slice(&rgba);
+            }
+        }
+    }
+}
+
+/// Whether the recorder or the sink take the rendered output.
+pub(crate) fn wants_output(recorder: Option<&FrameRecorder>, sink: Option<&FrameSink>) -> bool {
+    recorder.is_some_and(|recorder| recorder.options().source == CaptureSource::Output)
+        || sink.is_some_and(|sink| sink.source() == CaptureSource::Output)
+}
+
+/// Pass finished readbacks of `capture` to whichever of `recorder` and `sink` take the rendered
+/// output; a merged readback is written to the sink once per frame it stands for.
+pub(crate) fn deliver_output(
+    capture: &mut OutputCapture,
+    device: &wgpu::Device,
+    wait: bool,
+    recorder: Option<&mut FrameRecorder>,
+    sink: &mut Option<FrameSink>,
+    sink_error: &mut Option<Error>,
+) {
+    let mut recorder =
+        recorder.filter(|recorder| recorder.options().source == CaptureSource::Output);
+    capture.drain(device, wait, |image, frames| {
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.capture_rgba8(image.width, image.height, frames, |pixels| {
+                pixels.extend_from_slice(&image.pixels)
+            });
+        }
+        for _ in 0..frames {
+            if sink
+                .as_ref()
+                .is_none_or(|sink| sink.source() != CaptureSource::Output)
+            {
+                break;
+            }
+            write_to_sink(sink, sink_error, |sink| sink.write_frame(image));
+        }
+    });
+}
+
+/// Run `write` on the sink; if it fails, log the error, detach the sink and keep the error for
+/// `take_sink_error` so rendering carries on.
+pub(crate) fn write_to_sink(
+    sink: &mut Option<FrameSink>,
+    sink_error: &mut Option<Error>,
+    write: impl FnOnce(&mut FrameSink) -> crate::Result<()>,
+) {
+    let Some(active) = sink.as_mut() else {
+        return;
+    };
+    if let Err(error) = write(active) {
+        log::error!("frame sink failed, detaching it: {error}");
+        *sink = None;
+        *sink_error = Some(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, Write};
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "encoder exited"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(width: u32, height: u32) -> RgbaImage {
+        RgbaImage {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    #[test]
+    fn failing_sink_is_detached_and_its_error_kept() {
+        let mut sink = Some(FrameSink::y4m(FailingWriter, (60, 1)));
+        let mut sink_error = None;
+
+        write_to_sink(&mut sink, &mut sink_error, |sink| {
+            sink.write_frame(&frame(2, 2))
+        });
+
+        assert!(sink.is_none());
+        assert!(matches!(sink_error, Some(Error::Io(_))));
+    }
+
+    #[test]
+    fn sink_keeps_writing_until_a_frame_fails() {
+        let mut sink = Some(FrameSink::y4m(Vec::new(), (60, 1)));
+        let mut sink_error = None;
+
+        write_to_sink(&mut sink, &mut sink_error, |sink| {
+            sink.write_frame(&frame(2, 2))
+        });
+        assert_eq!(sink.as_ref().map(FrameSink::frames_written), Some(1));
+        assert!(sink_error.is_none());
+
+        // Y4M streams cannot change size.
+        write_to_sink(&mut sink, &mut sink_error, |sink| {
+            sink.write_frame(&frame(4, 4))
+        });
+        assert!(sink.is_none());
+        assert!(matches!(sink_error, Some(Error::InvalidImage(_))));
+
+        // Nothing to write to any more.
+        write_to_sink(&mut sink, &mut sink_error, |_| unreachable!());
+    }
+
+    #[test]
+    fn output_is_wanted_only_by_output_consumers() {
+        let buffer_recorder = FrameRecorder::new(Default::default());
+        let output_recorder = FrameRecorder::new(
+            crate::RecorderOptions::default().with_source(CaptureSource::Output),
+        );
+        let output_sink = FrameSink::y4m(Vec::new(), (60, 1)).with_source(CaptureSource::Output);
+
+        assert!(!wants_output(None, None));
+        assert!(!wants_output(Some(&buffer_recorder), None));
+        assert!(wants_output(Some(&output_recorder), None));
+        assert!(wants_output(Some(&buffer_recorder), Some(&output_sink)));
+    }
+}
@@ -0,0 +1,79 @@
+use crate::{Error, PixstageOptions, Result};
+
+/// A wgpu instance, adapter, device and queue that several stages can share.
+///
+/// Pass one to a stage's `new_with_context` to render on an existing device (e.g. your own
+/// renderer's) instead of letting every stage create its own. The fields are cheap clones.
+#[derive(Debug, Clone)]
+pub struct PixstageContext {
+    pub instance: wgpu::Instance,
+    pub adapter: wgpu::Adapter,
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+}
+
+impl PixstageContext {
+    /// Wrap an existing wgpu setup.
+    pub fn from_parts(
+        instance: wgpu::Instance,
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+    ) -> Self {
+        Self {
+            instance,
+            adapter,
+            device,
+            queue,
+        }
+    }
+
+    /// Asynchronously create a context the way a stage does on its own: adapter from the
+    /// environment (or the default one), with the adapter's limits.
+    ///
+    /// `options.backends` selects the backends and `options.gpu_timing` requests timestamp
+    /// queries when supported.
+    pub async fn new_async(options: &PixstageOptions) -> Result<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: options.backends,
+            ..Default::default()
+        });
+        Self::with_instance(instance, None, options).await
+    }
+
+    /// Synchronously create a context (native only); see `new_async`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new(options: &PixstageOptions) -> Result<Self> {
+        pollster::block_on(Self::new_async(options))
+    }
+
+    /// Pick an adapter (compatible with `surface`, if given) from `instance` and create a device.
+    pub(crate) async fn with_instance(
+        instance: wgpu::Instance,
+        surface: Option<&wgpu::Surface<'_>>,
+        options: &PixstageOptions,
+    ) -> Result<Self> {
+        let adapter = wgpu::util::initialize_adapter_from_env_or_default(&instance, surface)
+            .await
+            .map_err(|_| Error::AdapterNotFound)?;
+
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                required_features: if options.gpu_timing {
+                    adapter.features() & wgpu::Features::TIMESTAMP_QUERY
+                } else {
+                    wgpu::Features::empty()
+                },
+                required_limits: adapter.limits(),
+                ..Default::default()
+            })
+            .await?;
+
+        Ok(Self {
+            instance,
+            adapter,
+            device,
+            queue,
+        })
+    }
+}
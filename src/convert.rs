@@ -0,0 +1,136 @@
+use crate::Rect;
+
+/// Expands a row of packed pixels into RGBA8 bytes.
+pub(crate) type ConvertRow<P> = fn(&[P], &mut [u8]);
+
+/// Regions with at least this many pixels are split across threads.
+#[cfg(feature = "parallel")]
+const PARALLEL_MIN_PIXELS: usize = 1 << 16;
+
+/// Expand `src` into RGBA8 `dst` (4 bytes per pixel) in fixed 8-pixel chunks, which lets the
+/// compiler vectorize `expand`.
+#[inline(always)]
+pub(crate) fn convert_chunked(src: &[u16], dst: &mut [u8], expand: impl Fn(u16) -> [u8; 4]) {
+    let mut src_chunks = src.chunks_exact(8);
+    let mut dst_chunks = dst.chunks_exact_mut(32);
+    for (src, dst) in (&mut src_chunks).zip(&mut dst_chunks) {
+        for (pixel, out) in src.iter().zip(dst.chunks_exact_mut(4)) {
+            out.copy_from_slice(&expand(*pixel));
+        }
+    }
+    let dst_rest = dst_chunks.into_remainder().chunks_exact_mut(4);
+    for (pixel, out) in src_chunks.remainder().iter().zip(dst_rest) {
+        out.copy_from_slice(&expand(*pixel));
+    }
+}
+
+/// Convert `region` of a `src_width`-wide packed buffer into `dst`, one row every `dst_pitch`
+/// bytes. With the `parallel` feature, large regions are split across the rayon thread pool.
+pub(crate) fn convert_region<P: Sync>(
+    src: &[P],
+    src_width: usize,
+    region: Rect,
+    dst: &mut [u8],
+    dst_pitch: usize,
+    convert: ConvertRow<P>,
+) {
+    let (x, y) = (region.x as usize, region.y as usize);
+    let (width, height) = (region.width as usize, region.height as usize);
+    let convert_row = |(row, dst_row): (usize, &mut [u8])| {
+        let start = (y + row) * src_width + x;
+        convert(&src[start..start + width], &mut dst_row[..width * 4]);
+    };
+
+    #[cfg(feature = "parallel")]
+    if width * height >= PARALLEL_MIN_PIXELS {
+        use rayon::prelude::*;
+        dst.par_chunks_mut(dst_pitch)
+            .take(height)
+            .enumerate()
+            .for_each(convert_row);
+        return;
+    }
+
+    dst.chunks_mut(dst_pitch)
+        .take(height)
+        .enumerate()
+        .for_each(convert_row);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::argb1555::{argb1555_slice_to_rgba8, argb1555_to_rgba8};
+    use crate::rgb565::{rgb565_slice_to_rgba8, rgb565_to_rgba8};
+
+    type Converters = (&'static str, fn(u16) -> [u8; 4], fn(&[u16], &mut [u8]));
+
+    const CONVERTERS: [Converters; 2] = [
+        ("rgb565", rgb565_to_rgba8, rgb565_slice_to_rgba8),
+        ("argb1555", argb1555_to_rgba8, argb1555_slice_to_rgba8),
+    ];
+
+    /// Every 16-bit input once, as a 256x256 buffer.
+    fn every_input() -> Vec<u16> {
+        (0..=u16::MAX).collect()
+    }
+
+    fn scalar(src: &[u16], convert: fn(u16) -> [u8; 4]) -> Vec<u8> {
+        src.iter().flat_map(|pixel| convert(*pixel)).collect()
+    }
+
+    #[test]
+    fn slice_converters_match_scalar_for_every_input() {
+        let src = every_input();
+        for (name, convert_pixel, convert_slice) in CONVERTERS {
+            let expected = scalar(&src, convert_pixel);
+            // Offsets leave 0-7 pixels for the remainder after the 8-pixel chunks.
+            for offset in 0..8 {
+                let mut dst = vec![0u8; (src.len() - offset) * 4];
+                convert_slice(&src[offset..], &mut dst);
+                assert!(
+                    dst == expected[offset * 4..],
+                    "{name} differs at offset {offset}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn convert_region_matches_scalar_for_every_input() {
+        let src = every_input();
+        let pitch = 256 * 4 + 60;
+        let full = Rect {
+            x: 0,
+            y: 0,
+            width: 256,
+            height: 256,
+        };
+        // The whole buffer takes the rayon path with `parallel`; each half stays serial.
+        let halves = [
+            Rect {
+                height: 128,
+                ..full
+            },
+            Rect {
+                y: 128,
+                height: 128,
+                ..full
+            },
+        ];
+        for (name, convert_pixel, convert_slice) in CONVERTERS {
+            let expected = scalar(&src, convert_pixel);
+            for regions in [&[full][..], &halves[..]] {
+                let mut dst = vec![0u8; pitch * 256];
+                for region in regions {
+                    let offset = region.y as usize * pitch;
+                    convert_region(&src, 256, *region, &mut dst[offset..], pitch, convert_slice);
+                }
+                for (row, expected_row) in expected.chunks_exact(256 * 4).enumerate() {
+                    let dst_row = &dst[row * pitch..row * pitch + 256 * 4];
+                    assert!(dst_row == expected_row, "{name} differs in row {row}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! The RGBA texture the direct-color stages (RGBA8, RGB565, ARGB1555) upload their buffer into.
+
+use crate::convert::ConvertRow;
+use crate::image::RgbaImage;
+use crate::stage::{
+    create_pipeline, sampler_entry, texture_entry, uniform_entries, StageFormat, StageGpu,
+};
+use crate::{FrameRecorder, FrameSink, Result, ScalingMode};
+use std::marker::PhantomData;
+
+const SHADER: &str = concat!(
+    include_str!("shaders/overlays.wgsl"),
+    include_str!("shaders/rgba.wgsl")
+);
+
+/// How a direct-color buffer stores its pixels.
+pub(crate) trait DirectColor: std::fmt::Debug {
+    type Element: bytemuck::Pod + Default + PartialEq + Send + Sync + std::fmt::Debug;
+    /// One pixel of the buffer, compared to find the changed tiles.
+    type Pixel: bytemuck::Pod + PartialEq;
+
+    /// Prefix of the stage's GPU labels.
+    const LABEL: &'static str;
+    const ELEMENTS_PER_PIXEL: usize;
+    /// Expands rows to RGBA8; `None` for buffers that hold RGBA8 already.
+    const CONVERT: Option<ConvertRow<Self::Element>>;
+
+    /// Append `pixels` to `dst` as RGBA8.
+    fn extend_rgba8(pixels: &[Self::Element], dst: &mut Vec<u8>) {
+        match Self::CONVERT {
+            Some(convert) => {
+                let start = dst.len();
+                dst.resize(start + pixels.len() * 4, 0);
+                convert(pixels, &mut dst[start..]);
+            }
+            None => dst.extend_from_slice(bytemuck::cast_slice(pixels)),
+        }
+    }
+
+    /// Copy, or convert, `pixels` into an `RgbaImage`.
+    fn to_image(pixels: &[Self::Element], width: u32, height: u32) -> RgbaImage {
+        let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+        Self::extend_rgba8(pixels, &mut rgba);
+        RgbaImage {
+            width,
+            height,
+            pixels: rgba,
+        }
+    }
+}
+
+/// `Rgba8UnormSrgb` texture of the buffer size, sampled with the nearest or linear filter
+/// depending on the `ScalingMode`.
+#[derive(Debug)]
+pub(crate) struct RgbaTexture<C> {
+    pub(crate) texture: wgpu::Texture,
+    pub(crate) view: wgpu::TextureView,
+    bind_group_nearest: wgpu::BindGroup,
+    bind_group_linear: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    color: PhantomData<C>,
+}
+
+impl<C: DirectColor> RgbaTexture<C> {
+    fn create_texture(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (wgpu::Texture, wgpu::TextureView) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(&format!("{}_texture", C::LABEL)),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (texture, view)
+    }
+
+    fn bind_group_with(
+        &self,
+        gpu: &StageGpu,
+        sampler: &wgpu::Sampler,
+        globals_buffer: &wgpu::Buffer,
+        label: &str,
+    ) -> wgpu::BindGroup {
+        create_bind_group(
+            gpu,
+            &self.pipeline.get_bind_group_layout(0),
+            &self.view,
+            sampler,
+            globals_buffer,
+            label,
+        )
+    }
+}
+
+impl<C: DirectColor> StageFormat for RgbaTexture<C> {
+    type Element = C::Element;
+    type Pixel = C::Pixel;
+
+    const LABEL: &'static str = C::LABEL;
+    const ELEMENTS_PER_PIXEL: usize = C::ELEMENTS_PER_PIXEL;
+    const TEXEL_BYTES: u32 = 4;
+    const CONVERT: Option<ConvertRow<Self::Element>> = C::CONVERT;
+
+    fn new(gpu: &StageGpu, width: u32, height: u32, target_format: wgpu::TextureFormat) -> Self {
+        let [globals_entry, overlays_entry] = uniform_entries(2);
+        let bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some(&format!("{}_bind_group_layout", C::LABEL)),
+                    entries: &[
+                        texture_entry(0),
+                        sampler_entry(1),
+                        globals_entry,
+                        overlays_entry,
+                    ],
+                });
+        let pipeline = create_pipeline(
+            &gpu.device,
+            &bind_group_layout,
+            C::LABEL,
+            SHADER,
+            target_format,
+            Some(wgpu::BlendState::ALPHA_BLENDING),
+        );
+
+        let (texture, view) = Self::create_texture(&gpu.device, width, height);
+        let bind_group_nearest = create_bind_group(
+            gpu,
+            &bind_group_layout,
+            &view,
+            &gpu.sampler_nearest,
+            &gpu.globals_buffer,
+            &format!("{}_bind_group_nearest", C::LABEL),
+        );
+        let bind_group_linear = create_bind_group(
+            gpu,
+            &bind_group_layout,
+            &view,
+            &gpu.sampler_linear,
+            &gpu.globals_buffer,
+            &format!("{}_bind_group_linear", C::LABEL),
+        );
+
+        Self {
+            texture,
+            view,
+            bind_group_nearest,
+            bind_group_linear,
+            pipeline,
+            color: PhantomData,
+        }
+    }
+
+    fn resize(&mut self, gpu: &StageGpu, width: u32, height: u32) {
+        (self.texture, self.view) = Self::create_texture(&gpu.device, width, height);
+        self.bind_group_nearest = self.bind_group_with(
+            gpu,
+            &gpu.sampler_nearest,
+            &gpu.globals_buffer,
+            &format!("{}_bind_group_nearest", C::LABEL),
+        );
+        self.bind_group_linear = self.bind_group_with(
+            gpu,
+            &gpu.sampler_linear,
+            &gpu.globals_buffer,
+            &format!("{}_bind_group_linear", C::LABEL),
+        );
+    }
+
+    fn upload_texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+
+    fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    fn bind_group(&self, scaling_mode: ScalingMode) -> &wgpu::BindGroup {
+        match scaling_mode {
+            ScalingMode::PixelPerfect => &self.bind_group_nearest,
+            ScalingMode::Fill => &self.bind_group_linear,
+        }
+    }
+
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        target_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        create_pipeline(
+            device,
+            &self.pipeline.get_bind_group_layout(0),
+            C::LABEL,
+            SHADER,
+            target_format,
+            Some(wgpu::BlendState::ALPHA_BLENDING),
+        )
+    }
+
+    fn create_bind_group(
+        &self,
+        gpu: &StageGpu,
+        scaling_mode: ScalingMode,
+        globals_buffer: &wgpu::Buffer,
+        label: &str,
+    ) -> wgpu::BindGroup {
+        self.bind_group_with(gpu, gpu.sampler(scaling_mode), globals_buffer, label)
+    }
+
+    fn frame_view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    fn write_frame(
+        &self,
+        sink: &mut FrameSink,
+        pixels: &[Self::Element],
+        width: u32,
+        height: u32,
+    ) -> Result<()> {
+        sink.write_frame(&C::to_image(pixels, width, height))
+    }
+
+    fn capture(
+        &self,
+        recorder: &mut FrameRecorder,
+        pixels: &[Self::Element],
+        width: u32,
+        height: u32,
+    ) {
+        recorder.capture_rgba8(width, height, 1, |buffer| C::extend_rgba8(pixels, buffer));
+    }
+}
+
+fn create_bind_group(
+    gpu: &StageGpu,
+    layout: &wgpu::BindGroupLayout,
+    texture_view: &wgpu::TextureView,
+    sampler: &wgpu::Sampler,
+    globals_buffer: &wgpu::Buffer,
+    label: &str,
+) -> wgpu::BindGroup {
+    gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(label),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: globals_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: gpu.overlays_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
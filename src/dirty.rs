@@ -1,7 +1,43 @@
 use crate::Rect;
+use std::time::Duration;
+
+/// How dirty tiles are combined into texture uploads (see `PixstageOptions::region_merge`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum RegionMerge {
+    /// Merge vertically adjacent tile runs, and collapse everything into one bounding rect once
+    /// there are more than `max_dirty_regions` regions.
+    #[default]
+    BoundingUnion,
+    /// Repeatedly merge the pair of regions whose union is cheapest, where every upload call
+    /// costs as much as `call_overhead` extra pixels, absorbing any region the union overlaps.
+    /// Stops once no merge saves anything and the region limit is met.
+    Greedy { call_overhead: u32 },
+    /// One region per dirty tile row, spanning its leftmost to rightmost dirty tile; rows with
+    /// the same span are joined.
+    RowBands,
+    /// `Greedy`, with the call overhead estimated from measured upload times. The estimate also
+    /// sets the tile size (a power of two from 8 to 128 whose tile holds about one call's worth
+    /// of pixels, starting from `dirty_tile_size`) and caps the region count at the number of
+    /// calls costing as much as a full upload (at most `max_dirty_regions`).
+    Adaptive,
+}
+
+/// Call overhead (in pixels) assumed by `RegionMerge::Adaptive` before anything is measured.
+const DEFAULT_CALL_OVERHEAD: u32 = 1024;
+
+/// Tile sizes `RegionMerge::Adaptive` picks from, as powers of two.
+const ADAPTIVE_TILE_SHIFTS: std::ops::RangeInclusive<u32> = 3..=7;
+
+/// Above this many regions greedy merging starts from row bands to bound its cost.
+const GREEDY_MAX_INPUT: usize = 128;
 
 #[derive(Debug, Clone)]
 pub(crate) struct DirtyTiles {
+    merge: RegionMerge,
+    max_regions: usize,
+    cost_model: UploadCostModel,
+    /// The configured tile size; `tile_size` differs only while `Adaptive` tunes it.
+    base_tile_size: u32,
     tile_size: u32,
     tiles_x: u32,
     tiles_y: u32,
@@ -17,8 +53,12 @@ impl DirtyTiles {
         let tile_size = tile_size.max(1);
         let (tiles_x, tiles_y) = tiles_dim(width, height, tile_size);
         let bit_len = (tiles_x as usize) * (tiles_y as usize);
-        let bits = vec![0u64; (bit_len + 63) / 64];
+        let bits = vec![0u64; bit_len.div_ceil(64)];
         Self {
+            merge: RegionMerge::default(),
+            max_regions: 64,
+            cost_model: UploadCostModel::default(),
+            base_tile_size: tile_size,
             tile_size,
             tiles_x,
             tiles_y,
@@ -33,16 +73,68 @@ impl DirtyTiles {
     pub(crate) fn resize(&mut self, width: u32, height: u32) {
         self.width = width;
         self.height = height;
-        let (tiles_x, tiles_y) = tiles_dim(width, height, self.tile_size);
-        self.tiles_x = tiles_x;
-        self.tiles_y = tiles_y;
-        let bit_len = (tiles_x as usize) * (tiles_y as usize);
-        self.bits.clear();
-        self.bits.resize((bit_len + 63) / 64, 0);
-        self.dirty_tiles = 0;
+        self.retile(self.tile_size);
         self.full = true;
     }
 
+    pub(crate) fn set_merge(&mut self, merge: RegionMerge, max_regions: usize) {
+        self.merge = merge;
+        self.max_regions = max_regions.max(1);
+        if merge != RegionMerge::Adaptive && self.tile_size != self.base_tile_size {
+            let pending = self.full || self.dirty_tiles > 0;
+            self.retile(self.base_tile_size);
+            self.full = pending;
+        }
+    }
+
+    /// Whether `regions` (as returned by `take_regions`) cover the whole buffer exactly once.
+    pub(crate) fn covers_buffer(&self, regions: &[Rect]) -> bool {
+        let inside = regions
+            .iter()
+            .all(|rect| rect.right() <= self.width && rect.bottom() <= self.height);
+        let disjoint = regions
+            .iter()
+            .enumerate()
+            .all(|(i, a)| regions[i + 1..].iter().all(|b| !overlaps(*a, *b)));
+        let area: u64 = regions
+            .iter()
+            .map(|rect| rect.width as u64 * rect.height as u64)
+            .sum();
+        inside && disjoint && area == self.width as u64 * self.height as u64
+    }
+
+    /// Whether `record_upload` is used; only `RegionMerge::Adaptive` needs upload timings.
+    pub(crate) fn wants_timing(&self) -> bool {
+        self.merge == RegionMerge::Adaptive
+    }
+
+    /// Feed the measured cost of one frame's uploads into the adaptive cost model, and retune
+    /// the tile size once it has an estimate. Call after `take_regions`.
+    pub(crate) fn record_upload(&mut self, calls: usize, pixels: u64, elapsed: Duration) {
+        if !self.wants_timing() {
+            return;
+        }
+        self.cost_model
+            .observe(calls as f64, pixels as f64, elapsed.as_secs_f64());
+        if let Some(call_overhead) = self.cost_model.call_overhead {
+            let tile_size = adaptive_tile_size(call_overhead);
+            if tile_size != self.tile_size {
+                let pending = self.full || self.dirty_tiles > 0;
+                self.retile(tile_size);
+                self.full = pending;
+            }
+        }
+    }
+
+    /// Tiles currently marked dirty (all of them after `mark_full`).
+    pub(crate) fn dirty_tile_count(&self) -> u32 {
+        if self.full {
+            self.tiles_x * self.tiles_y
+        } else {
+            self.dirty_tiles
+        }
+    }
+
     pub(crate) fn mark_full(&mut self) {
         self.full = true;
     }
@@ -77,7 +169,49 @@ impl DirtyTiles {
         self.set_tile(tx, ty);
     }
 
-    pub(crate) fn take_regions(&mut self, max_regions: usize) -> Vec<Rect> {
+    /// Mark every tile where `current` differs from `previous` (both `width * height` pixels).
+    ///
+    /// Returns the number of tiles whose contents changed.
+    pub(crate) fn mark_changed<P: PartialEq>(&mut self, current: &[P], previous: &[P]) -> u32 {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let tile = self.tile_size as usize;
+        if current.len() < width * height || previous.len() < width * height {
+            self.mark_full();
+            return self.tiles_x * self.tiles_y;
+        }
+
+        let mut changed = 0;
+        let mut band = vec![false; self.tiles_x as usize];
+        for ty in 0..self.tiles_y {
+            band.fill(false);
+            let y0 = ty as usize * tile;
+            let y1 = (y0 + tile).min(height);
+            // Compare whole row segments per tile so the slice comparison can use memcmp.
+            for y in y0..y1 {
+                let row = y * width;
+                for (tx, changed_tile) in band.iter_mut().enumerate() {
+                    if *changed_tile {
+                        continue;
+                    }
+                    let x0 = row + tx * tile;
+                    let x1 = row + ((tx + 1) * tile).min(width);
+                    *changed_tile = current[x0..x1] != previous[x0..x1];
+                }
+            }
+            for (tx, changed_tile) in band.iter().enumerate() {
+                if *changed_tile {
+                    changed += 1;
+                    if !self.full {
+                        self.set_tile(tx as u32, ty);
+                    }
+                }
+            }
+        }
+        changed
+    }
+
+    pub(crate) fn take_regions(&mut self) -> Vec<Rect> {
         if self.width == 0 || self.height == 0 {
             self.clear();
             return Vec::new();
@@ -97,6 +231,28 @@ impl DirtyTiles {
             return Vec::new();
         }
 
+        let out = match self.merge {
+            RegionMerge::BoundingUnion => union_over_limit(self.run_regions(), self.max_regions),
+            RegionMerge::RowBands => union_over_limit(self.row_bands(), self.max_regions),
+            RegionMerge::Greedy { call_overhead } => {
+                self.greedy_regions(call_overhead, self.max_regions)
+            }
+            RegionMerge::Adaptive => {
+                let call_overhead = self.cost_model.call_overhead();
+                // Past this many calls, their overhead alone costs as much as a full upload.
+                let frame_pixels = self.width as u64 * self.height as u64;
+                let max_regions = (frame_pixels / call_overhead.max(1) as u64)
+                    .clamp(1, self.max_regions as u64) as usize;
+                self.greedy_regions(call_overhead, max_regions)
+            }
+        };
+
+        self.clear();
+        out
+    }
+
+    /// Runs of dirty tiles per row, merged with identical runs in the rows below.
+    fn run_regions(&self) -> Vec<Rect> {
         let mut out: Vec<Rect> = Vec::new();
         let mut prev: Vec<Rect> = Vec::new();
 
@@ -161,31 +317,94 @@ impl DirtyTiles {
         }
 
         out.extend(prev);
+        clamp_regions(out, self.width, self.height)
+    }
 
-        for rect in out.iter_mut() {
-            if let Some(clamped) = rect.clamp_to(self.width, self.height) {
-                *rect = clamped;
-            } else {
-                rect.width = 0;
-                rect.height = 0;
+    /// One rect per dirty tile row; consecutive rows with the same span are joined.
+    fn row_bands(&self) -> Vec<Rect> {
+        let mut out: Vec<Rect> = Vec::new();
+        for ty in 0..self.tiles_y {
+            let mut span: Option<(u32, u32)> = None;
+            for tx in 0..self.tiles_x {
+                if self.is_tile_set(tx, ty) {
+                    span = Some(span.map_or((tx, tx), |(first, _)| (first, tx)));
+                }
             }
+            let Some((first, last)) = span else {
+                continue;
+            };
+
+            let rect = Rect {
+                x: first * self.tile_size,
+                y: ty * self.tile_size,
+                width: (last - first + 1) * self.tile_size,
+                height: self.tile_size,
+            };
+            match out.last_mut() {
+                Some(prev)
+                    if prev.x == rect.x && prev.width == rect.width && prev.bottom() == rect.y =>
+                {
+                    prev.height += self.tile_size;
+                }
+                _ => out.push(rect),
+            }
+        }
+        clamp_regions(out, self.width, self.height)
+    }
+
+    fn greedy_regions(&self, call_overhead: u32, max_regions: usize) -> Vec<Rect> {
+        let mut regions = self.run_regions();
+        if regions.len() > GREEDY_MAX_INPUT {
+            regions = self.row_bands();
         }
-        out.retain(|rect| rect.width > 0 && rect.height > 0);
+        let overhead = call_overhead as u64;
+        let area = |rect: Rect| rect.width as u64 * rect.height as u64;
 
-        if out.len() > max_regions {
-            let mut merged = out[0];
-            for rect in out.iter().skip(1) {
-                merged = merged.union(*rect);
+        while regions.len() > 1 {
+            // Find the pair whose union adds the fewest pixels relative to the call it saves.
+            let mut best: Option<(i64, usize, usize)> = None;
+            for i in 0..regions.len() {
+                for j in i + 1..regions.len() {
+                    let merged = area(regions[i].union(regions[j]));
+                    let separate = area(regions[i]) + area(regions[j]) + overhead;
+                    let delta = merged as i64 - separate as i64;
+                    if best.is_none_or(|(best_delta, _, _)| delta < best_delta) {
+                        best = Some((delta, i, j));
+                    }
+                }
             }
-            out.clear();
-            out.push(merged);
+            let Some((delta, i, j)) = best else {
+                break;
+            };
+            if delta > 0 && regions.len() <= max_regions {
+                break;
+            }
+
+            let second = regions.swap_remove(j);
+            let mut merged = regions.swap_remove(i).union(second);
+            // Absorb every region the union overlaps, so no pixel is uploaded twice.
+            while let Some(k) = regions.iter().position(|rect| overlaps(merged, *rect)) {
+                merged = merged.union(regions.swap_remove(k));
+            }
+            regions.push(merged);
         }
+        regions
+    }
 
-        self.clear();
-        out
+    /// Switch to `tile_size`, dropping the dirty bits (callers decide what stays pending).
+    fn retile(&mut self, tile_size: u32) {
+        self.tile_size = tile_size;
+        let (tiles_x, tiles_y) = tiles_dim(self.width, self.height, tile_size);
+        self.tiles_x = tiles_x;
+        self.tiles_y = tiles_y;
+        let bit_len = (tiles_x as usize) * (tiles_y as usize);
+        self.bits.clear();
+        self.bits.resize(bit_len.div_ceil(64), 0);
+        self.dirty_tiles = 0;
+        self.full = false;
     }
 
-    fn clear(&mut self) {
+    pub(crate) fn clear(&mut self) {
         self.bits.fill(0);
         self.dirty_tiles = 0;
         self.full = false;
@@ -214,7 +433,309 @@ impl DirtyTiles {
 }
 
 fn tiles_dim(width: u32, height: u32, tile_size: u32) -> (u32, u32) {
-    let tiles_x = (width + tile_size - 1) / tile_size;
-    let tiles_y = (height + tile_size - 1) / tile_size;
+    let tiles_x = width.div_ceil(tile_size);
+    let tiles_y = height.div_ceil(tile_size);
     (tiles_x.max(1), tiles_y.max(1))
 }
+
+fn clamp_regions(mut regions: Vec<Rect>, width: u32, height: u32) -> Vec<Rect> {
+    for rect in regions.iter_mut() {
+        if let Some(clamped) = rect.clamp_to(width, height) {
+            *rect = clamped;
+        } else {
+            rect.width = 0;
+            rect.height = 0;
+        }
+    }
+    regions.retain(|rect| rect.width > 0 && rect.height > 0);
+    regions
+}
+
+fn union_over_limit(regions: Vec<Rect>, max_regions: usize) -> Vec<Rect> {
+    if regions.len() <= max_regions {
+        return regions;
+    }
+    let mut merged = regions[0];
+    for rect in regions.iter().skip(1) {
+        merged = merged.union(*rect);
+    }
+    vec![merged]
+}
+
+fn overlaps(a: Rect, b: Rect) -> bool {
+    a.x < b.right() && b.x < a.right() && a.y < b.bottom() && b.y < a.bottom()
+}
+
+/// The power-of-two tile size whose area is closest to `call_overhead` pixels.
+fn adaptive_tile_size(call_overhead: u32) -> u32 {
+    let side = (call_overhead.max(1) as f64).sqrt();
+    let shift = (side.log2().round() as u32)
+        .clamp(*ADAPTIVE_TILE_SHIFTS.start(), *ADAPTIVE_TILE_SHIFTS.end());
+    1 << shift
+}
+
+/// Running least-squares fit of `seconds = per_call * calls + per_pixel * pixels`.
+///
+/// Older frames decay so the estimate follows changing conditions (e.g. driver or backend).
+#[derive(Debug, Clone, Default)]
+struct UploadCostModel {
+    calls_calls: f64,
+    calls_pixels: f64,
+    pixels_pixels: f64,
+    calls_seconds: f64,
+    pixels_seconds: f64,
+    call_overhead: Option<u32>,
+}
+
+impl UploadCostModel {
+    const DECAY: f64 = 0.95;
+
+    fn observe(&mut self, calls: f64, pixels: f64, seconds: f64) {
+        if calls <= 0.0 {
+            return;
+        }
+        self.calls_calls = self.calls_calls * Self::DECAY + calls * calls;
+        self.calls_pixels = self.calls_pixels * Self::DECAY + calls * pixels;
+        self.pixels_pixels = self.pixels_pixels * Self::DECAY + pixels * pixels;
+        self.calls_seconds = self.calls_seconds * Self::DECAY + calls * seconds;
+        self.pixels_seconds = self.pixels_seconds * Self::DECAY + pixels * seconds;
+
+        let determinant = self.calls_calls * self.pixels_pixels - self.calls_pixels.powi(2);
+        // Needs frames with differing call/pixel ratios before the fit means anything.
+        if determinant <= 1e-9 * self.calls_calls * self.pixels_pixels {
+            return;
+        }
+        let per_call = (self.calls_seconds * self.pixels_pixels
+            - self.pixels_seconds * self.calls_pixels)
+            / determinant;
+        let per_pixel = (self.pixels_seconds * self.calls_calls
+            - self.calls_seconds * self.calls_pixels)
+            / determinant;
+        if per_pixel > 0.0 {
+            let overhead = (per_call / per_pixel).clamp(0.0, (1u32 << 20) as f64);
+            self.call_overhead = Some(overhead as u32);
+        }
+    }
+
+    fn call_overhead(&self) -> u32 {
+        self.call_overhead.unwrap_or(DEFAULT_CALL_OVERHEAD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TILE: u32 = 8;
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// A 64x64 buffer of 8x8 tiles with the given tiles dirty.
+    fn dirty(merge: RegionMerge, max_regions: usize, tiles: &[(u32, u32)]) -> DirtyTiles {
+        let mut dirty = DirtyTiles::new(64, 64, TILE);
+        dirty.set_merge(merge, max_regions);
+        for &(tx, ty) in tiles {
+            dirty.mark_point(tx * TILE, ty * TILE);
+        }
+        dirty
+    }
+
+    fn contains(outer: Rect, inner: Rect) -> bool {
+        inner.x >= outer.x
+            && inner.y >= outer.y
+            && inner.right() <= outer.right()
+            && inner.bottom() <= outer.bottom()
+    }
+
+    fn sorted(mut regions: Vec<Rect>) -> Vec<Rect> {
+        regions.sort_by_key(|rect| (rect.y, rect.x));
+        regions
+    }
+
+    #[test]
+    fn bounding_union_joins_identical_runs() {
+        let mut tiles = dirty(
+            RegionMerge::BoundingUnion,
+            64,
+            &[(0, 0), (1, 0), (0, 1), (1, 1), (5, 3)],
+        );
+        assert_eq!(
+            sorted(tiles.take_regions()),
+            vec![rect(0, 0, 16, 16), rect(40, 24, 8, 8)]
+        );
+        assert!(tiles.take_regions().is_empty());
+    }
+
+    #[test]
+    fn bounding_union_collapses_over_the_limit() {
+        let mut tiles = dirty(RegionMerge::BoundingUnion, 1, &[(0, 0), (7, 7)]);
+        let regions = tiles.take_regions();
+        assert_eq!(regions, vec![rect(0, 0, 64, 64)]);
+        assert!(tiles.covers_buffer(&regions));
+    }
+
+    #[test]
+    fn row_bands_span_each_row() {
+        let mut tiles = dirty(
+            RegionMerge::RowBands,
+            64,
+            &[(1, 0), (4, 0), (1, 1), (4, 1), (2, 3)],
+        );
+        assert_eq!(
+            sorted(tiles.take_regions()),
+            vec![rect(8, 0, 32, 16), rect(16, 24, 8, 8)]
+        );
+    }
+
+    #[test]
+    fn greedy_weighs_call_overhead_against_pixels() {
+        let pattern = [(0, 0), (2, 0)];
+
+        // Merging would upload a 64-pixel gap to save a free call.
+        let mut cheap_calls = dirty(RegionMerge::Greedy { call_overhead: 0 }, 64, &pattern);
+        assert_eq!(
+            sorted(cheap_calls.take_regions()),
+            vec![rect(0, 0, 8, 8), rect(16, 0, 8, 8)]
+        );
+
+        // A call costs more than the gap.
+        let mut dear_calls = dirty(RegionMerge::Greedy { call_overhead: 100 }, 64, &pattern);
+        assert_eq!(dear_calls.take_regions(), vec![rect(0, 0, 24, 8)]);
+
+        // The region limit forces merges even when they do not pay off.
+        let mut limited = dirty(
+            RegionMerge::Greedy { call_overhead: 0 },
+            1,
+            &[(0, 0), (7, 0), (0, 7)],
+        );
+        assert_eq!(limited.take_regions(), vec![rect(0, 0, 64, 64)]);
+    }
+
+    #[test]
+    fn greedy_absorbs_partially_overlapped_regions() {
+        // A plus sign: its column and the bar crossing it used to come out as two rects
+        // sharing the center tile.
+        let mut tiles = dirty(
+            RegionMerge::Greedy { call_overhead: 64 },
+            64,
+            &[(0, 0), (2, 1), (1, 2), (3, 2), (2, 3)],
+        );
+        assert_eq!(
+            sorted(tiles.take_regions()),
+            vec![rect(0, 0, 8, 8), rect(8, 8, 24, 24)]
+        );
+    }
+
+    #[test]
+    fn every_strategy_covers_dirty_tiles_without_overlap() {
+        let strategies = [
+            RegionMerge::BoundingUnion,
+            RegionMerge::RowBands,
+            RegionMerge::Greedy { call_overhead: 0 },
+            RegionMerge::Greedy { call_overhead: 64 },
+            RegionMerge::Greedy {
+                call_overhead: 4096,
+            },
+            RegionMerge::Adaptive,
+        ];
+        let mut seed = 0x2545_f491_u32;
+        for _ in 0..200 {
+            let mut pattern = Vec::new();
+            for ty in 0..8 {
+                for tx in 0..8 {
+                    seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    if seed >> 29 == 0 {
+                        pattern.push((tx, ty));
+                    }
+                }
+            }
+            for merge in strategies {
+                for max_regions in [1, 3, 64] {
+                    let mut tiles = dirty(merge, max_regions, &pattern);
+                    let regions = tiles.take_regions();
+                    assert!(regions.len() <= max_regions, "{merge:?}: {regions:?}");
+                    for (i, a) in regions.iter().enumerate() {
+                        assert!(a.right() <= 64 && a.bottom() <= 64);
+                        for b in &regions[i + 1..] {
+                            assert!(!overlaps(*a, *b), "{merge:?}: {a:?} overlaps {b:?}");
+                        }
+                    }
+                    for &(tx, ty) in &pattern {
+                        let tile = rect(tx * TILE, ty * TILE, TILE, TILE);
+                        assert!(
+                            regions.iter().any(|region| contains(*region, tile)),
+                            "{merge:?}: {tile:?} not uploaded in {regions:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn covers_buffer_needs_disjoint_full_coverage() {
+        let tiles = DirtyTiles::new(64, 64, TILE);
+        assert!(tiles.covers_buffer(&[rect(0, 0, 64, 64)]));
+        assert!(tiles.covers_buffer(&[rect(0, 0, 64, 32), rect(0, 32, 64, 32)]));
+        assert!(!tiles.covers_buffer(&[rect(0, 0, 64, 32)]));
+        // Same total area, but the overlap leaves the bottom rows out.
+        assert!(!tiles.covers_buffer(&[rect(0, 0, 64, 40), rect(0, 8, 64, 24)]));
+        assert!(!tiles.covers_buffer(&[]));
+    }
+
+    #[test]
+    fn adaptive_tunes_tile_size_and_region_limit() {
+        let mut tiles = DirtyTiles::new(1024, 1024, 32);
+        tiles.set_merge(RegionMerge::Adaptive, 64);
+        assert!(tiles.wants_timing());
+
+        // Uploads cost 10 µs per call and 1 ns per pixel: a call is worth 10000 pixels.
+        let cost = |calls: usize, pixels: u64| {
+            Duration::from_secs_f64(calls as f64 * 1e-5 + pixels as f64 * 1e-9)
+        };
+        for (calls, pixels) in [(1, 1_000), (8, 4_000), (2, 200_000), (16, 65_536)] {
+            tiles.record_upload(calls, pixels, cost(calls, pixels));
+        }
+        // The tile closest to 10000 pixels is 128x128 (sqrt is 100; 2^7 beats 2^6).
+        assert_eq!(tiles.tile_size, 128);
+        tiles.mark_point(0, 0);
+        tiles.mark_point(1023, 1023);
+        assert_eq!(
+            sorted(tiles.take_regions()),
+            vec![rect(0, 0, 128, 128), rect(896, 896, 128, 128)]
+        );
+
+        // At 1 ms per call, two calls cost more than a full upload.
+        let cost = |calls: usize, pixels: u64| {
+            Duration::from_secs_f64(calls as f64 * 1e-3 + pixels as f64 * 1e-9)
+        };
+        for _ in 0..200 {
+            for (calls, pixels) in [(1, 1_000), (8, 4_000), (2, 200_000), (16, 65_536)] {
+                tiles.record_upload(calls, pixels, cost(calls, pixels));
+            }
+        }
+        assert_eq!(tiles.tile_size, 128);
+        tiles.mark_point(0, 0);
+        tiles.mark_point(1023, 1023);
+        assert_eq!(tiles.take_regions(), vec![rect(0, 0, 1024, 1024)]);
+
+        // Leaving adaptive mode restores the configured tile size.
+        tiles.set_merge(RegionMerge::BoundingUnion, 64);
+        assert_eq!(tiles.tile_size, 32);
+    }
+
+    #[test]
+    fn adaptive_keeps_the_configured_tile_size_until_measured() {
+        let mut tiles = DirtyTiles::new(256, 256, 16);
+        tiles.set_merge(RegionMerge::Adaptive, 64);
+        tiles.record_upload(1, 256, Duration::from_micros(5));
+        assert_eq!(tiles.tile_size, 16);
+    }
+}
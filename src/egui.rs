@@ -0,0 +1,317 @@
+//! Show stages inside egui UIs (`egui` feature).
+//!
+//! A [`StageCanvas`] registers a stage's texture with an `egui_wgpu::Renderer` and lays it out
+//! in a widget using the stage's `ScalingMode`, with pointer positions mapped to buffer pixels
+//! the same way as `window_pos_to_pixel`. The stage must live on the renderer's device (see
+//! `new_headless`); indexed stages are shown through their palette-resolved texture.
+
+use crate::scaling::{compute_scaling, pos_to_pixel, ScalingState};
+use crate::{
+    PixelUnits, PixstageArgb1555, PixstageIndexed, PixstageRgb565, PixstageRgba, Rect, Result,
+    ScalingMode, Viewport,
+};
+
+/// A stage that can be shown by [`StageCanvas`].
+pub trait EguiStage {
+    fn buffer_size(&self) -> (u32, u32);
+
+    fn scaling_mode(&self) -> ScalingMode;
+
+    /// Whether `PixelPerfect` counts physical pixels or egui points.
+    fn pixel_units(&self) -> PixelUnits;
+
+    /// The displayed part of the buffer (see `set_crop`).
+    fn crop(&self) -> Option<Rect>;
+
+    /// Upload pending changes, recording the copies into `encoder`.
+    fn upload(&mut self, encoder: &mut wgpu::CommandEncoder) -> Result<()>;
+
+    /// The RGBA texture view egui should sample.
+    fn egui_view(&mut self) -> &wgpu::TextureView;
+}
+
+macro_rules! impl_egui_stage {
+    ($stage:ident, |$this:ident| $view:expr) => {
+        impl EguiStage for $stage<'_> {
+            fn buffer_size(&self) -> (u32, u32) {
+                $stage::buffer_size(self)
+            }
+
+            fn scaling_mode(&self) -> ScalingMode {
+                $stage::scaling_mode(self)
+            }
+
+            fn pixel_units(&self) -> PixelUnits {
+                $stage::pixel_units(self)
+            }
+
+            fn crop(&self) -> Option<Rect> {
+                $stage::crop(self)
+            }
+
+            fn upload(&mut self, encoder: &mut wgpu::CommandEncoder) -> Result<()> {
+                $stage::upload(self, encoder)
+            }
+
+            fn egui_view(&mut self) -> &wgpu::TextureView {
+                let $this = self;
+                $view
+            }
+        }
+    };
+}
+
+impl_egui_stage!(PixstageRgba, |stage| stage.texture_view());
+impl_egui_stage!(PixstageRgb565, |stage| stage.texture_view());
+impl_egui_stage!(PixstageArgb1555, |stage| stage.texture_view());
+impl_egui_stage!(PixstageIndexed, |stage| {
+    stage.set_palette_resolve(true);
+    stage
+        .resolved_view()
+        .expect("palette resolve was just enabled")
+});
+
+/// A stage texture registered with an `egui_wgpu::Renderer`, and the widget that shows it.
+///
+/// Call `update` once per frame (before the egui pass is rendered) to upload the stage and
+/// follow texture replacements from `resize_buffer`, then `show` it in a `Ui`.
+///
+/// The canvas has a [`Viewport`] of its own (pan in physical pixels of the canvas), separate
+/// from the stage's.
+#[derive(Debug)]
+pub struct StageCanvas {
+    texture_id: egui::TextureId,
+    view: wgpu::TextureView,
+    filter: wgpu::FilterMode,
+    buffer_size: (u32, u32),
+    crop: Option<Rect>,
+    scaling_mode: ScalingMode,
+    pixel_units: PixelUnits,
+    viewport: Viewport,
+    layout: Option<CanvasLayout>,
+}
+
+/// Where the last `show` put the canvas, in physical pixels relative to `origin`.
+#[derive(Debug, Copy, Clone)]
+struct CanvasLayout {
+    origin: egui::Pos2,
+    pixels_per_point: f32,
+    physical_size: (u32, u32),
+    scaling_state: ScalingState,
+}
+
+/// What `StageCanvas::show` returns.
+#[derive(Debug)]
+pub struct CanvasResponse {
+    /// Response of the whole area allocated for the canvas (clicks and drags are sensed).
+    pub response: egui::Response,
+    /// Buffer pixel under the pointer, if any.
+    pub hovered_pixel: Option<(usize, usize)>,
+}
+
+impl StageCanvas {
+    /// Register `stage`'s texture with `renderer`.
+    pub fn new(
+        renderer: &mut egui_wgpu::Renderer,
+        device: &wgpu::Device,
+        stage: &mut impl EguiStage,
+    ) -> Self {
+        let scaling_mode = stage.scaling_mode();
+        let pixel_units = stage.pixel_units();
+        let buffer_size = stage.buffer_size();
+        let crop = stage.crop();
+        let filter = filter_for(scaling_mode);
+        let view = stage.egui_view().clone();
+        let texture_id = renderer.register_native_texture(device, &view, filter);
+        Self {
+            texture_id,
+            view,
+            filter,
+            buffer_size,
+            crop,
+            scaling_mode,
+            pixel_units,
+            viewport: Viewport::default(),
+            layout: None,
+        }
+    }
+
+    /// Upload `stage`'s pending changes (submitted on `queue` right away) and re-register its
+    /// texture if it was replaced or the scaling mode changed the filter.
+    pub fn update(
+        &mut self,
+        renderer: &mut egui_wgpu::Renderer,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        stage: &mut impl EguiStage,
+    ) -> Result<()> {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("pixstage_egui_upload_encoder"),
+        });
+        stage.upload(&mut encoder)?;
+        queue.submit(Some(encoder.finish()));
+
+        self.scaling_mode = stage.scaling_mode();
+        self.pixel_units = stage.pixel_units();
+        self.buffer_size = stage.buffer_size();
+        self.crop = stage.crop();
+        let filter = filter_for(self.scaling_mode);
+        let view = stage.egui_view();
+        if *view != self.view || filter != self.filter {
+            self.view = view.clone();
+            self.filter = filter;
+            renderer.update_egui_texture_from_wgpu_texture(
+                device,
+                &self.view,
+                filter,
+                self.texture_id,
+            );
+        }
+        Ok(())
+    }
+
+    /// Unregister the texture from `renderer`.
+    pub fn free(self, renderer: &mut egui_wgpu::Renderer) {
+        renderer.free_texture(&self.texture_id);
+    }
+
+    pub fn texture_id(&self) -> egui::TextureId {
+        self.texture_id
+    }
+
+    /// Fill the available space of `ui` with the canvas, scaled by the stage's `ScalingMode`
+    /// (`PixelPerfect` factors count physical pixels or points, per the stage's `PixelUnits`).
+    pub fn show(&mut self, ui: &mut egui::Ui) -> CanvasResponse {
+        let (rect, response) =
+            ui.allocate_exact_size(ui.available_size(), egui::Sense::click_and_drag());
+        let pixels_per_point = ui.ctx().pixels_per_point();
+        let physical_size = (
+            (rect.width() * pixels_per_point).round().max(1.0) as u32,
+            (rect.height() * pixels_per_point).round().max(1.0) as u32,
+        );
+        let scaling_state = compute_scaling(
+            self.buffer_size,
+            self.crop,
+            physical_size,
+            self.scaling_mode,
+            self.pixel_units.pixel_step(pixels_per_point as f64),
+            &self.viewport,
+        );
+        let layout = CanvasLayout {
+            origin: rect.min,
+            pixels_per_point,
+            physical_size,
+            scaling_state,
+        };
+        self.layout = Some(layout);
+
+        if ui.is_rect_visible(rect) && scaling_state.is_visible() {
+            ui.painter_at(rect)
+                .add(image_mesh(self.texture_id, &layout));
+        }
+
+        let hovered_pixel = response
+            .hover_pos()
+            .and_then(|pos| layout.pos_to_pixel(pos).ok());
+        CanvasResponse {
+            response,
+            hovered_pixel,
+        }
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        self.viewport
+    }
+
+    /// Zoom, pan and rotate the canvas; takes effect at the next `show`.
+    pub fn set_viewport(&mut self, viewport: Viewport) {
+        self.viewport = viewport;
+    }
+
+    /// Multiply the zoom by `factor`, keeping the buffer point under `pos` (egui points) in
+    /// place, e.g. with `ui.input(|i| i.zoom_delta())`. Needs a previous `show`.
+    pub fn zoom_at(&mut self, pos: egui::Pos2, factor: f32) {
+        if let Some(layout) = self.layout {
+            let local = (pos - layout.origin) * layout.pixels_per_point;
+            self.viewport = self.viewport.zoomed_at(
+                &layout.scaling_state,
+                layout.physical_size,
+                (local.x, local.y),
+                factor,
+            );
+        }
+    }
+
+    /// Drop zoom and pan, so the `ScalingMode` fits the buffer again.
+    pub fn zoom_to_fit(&mut self) {
+        self.viewport = self.viewport.fitted();
+    }
+
+    /// Zoom to one buffer pixel per physical pixel (1:1). Needs a previous `show`.
+    pub fn zoom_to_actual_size(&mut self) {
+        if let Some(layout) = self.layout {
+            self.viewport = self
+                .viewport
+                .actual_size(&layout.scaling_state, layout.physical_size);
+        }
+    }
+
+    /// Move the image by `delta` egui points, e.g. `response.drag_delta()`.
+    pub fn pan_by(&mut self, delta: egui::Vec2) {
+        let pixels_per_point = self.layout.map_or(1.0, |layout| layout.pixels_per_point);
+        self.viewport.pan[0] += delta.x * pixels_per_point;
+        self.viewport.pan[1] += delta.y * pixels_per_point;
+    }
+
+    /// Map a position in egui points to a buffer pixel, using the layout of the last `show`.
+    ///
+    /// Same contract as `window_pos_to_pixel`: positions outside the image are `Err` (in
+    /// physical pixels relative to the canvas, or buffer pixels past the edges).
+    pub fn pos_to_pixel(
+        &self,
+        pos: egui::Pos2,
+    ) -> std::result::Result<(usize, usize), (isize, isize)> {
+        match self.layout {
+            Some(layout) => layout.pos_to_pixel(pos),
+            None => Err((pos.x.floor() as isize, pos.y.floor() as isize)),
+        }
+    }
+}
+
+impl CanvasLayout {
+    fn pos_to_pixel(&self, pos: egui::Pos2) -> std::result::Result<(usize, usize), (isize, isize)> {
+        let local = (pos - self.origin) * self.pixels_per_point;
+        pos_to_pixel(&self.scaling_state, (local.x, local.y))
+    }
+}
+
+fn filter_for(scaling_mode: ScalingMode) -> wgpu::FilterMode {
+    match scaling_mode {
+        ScalingMode::PixelPerfect => wgpu::FilterMode::Nearest,
+        ScalingMode::Fill => wgpu::FilterMode::Linear,
+    }
+}
+
+/// The whole (rotated) image as a textured quad, in egui points.
+fn image_mesh(texture_id: egui::TextureId, layout: &CanvasLayout) -> egui::Mesh {
+    let state = &layout.scaling_state;
+    let size = egui::vec2(
+        state.ndc_scale[0] * layout.physical_size.0 as f32,
+        state.ndc_scale[1] * layout.physical_size.1 as f32,
+    ) / layout.pixels_per_point;
+    let min = layout.origin
+        + egui::vec2(state.image_origin[0], state.image_origin[1]) / layout.pixels_per_point;
+
+    let mut mesh = egui::Mesh::with_texture(texture_id);
+    for corner in [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]] {
+        let [u, v] = state.tex_coord(corner);
+        mesh.vertices.push(egui::epaint::Vertex {
+            pos: min + egui::vec2(corner[0] * size.x, corner[1] * size.y),
+            uv: egui::pos2(u, v),
+            color: egui::Color32::WHITE,
+        });
+    }
+    mesh.add_triangle(0, 1, 2);
+    mesh.add_triangle(0, 2, 3);
+    mesh
+}
@@ -0,0 +1,99 @@
+use crate::scaling::{GlobalsUniform, ScalingState};
+use crate::Rect;
+
+/// Per-target state for drawing a stage into a caller's render pass.
+///
+/// Kept apart from the stage's own surface state so both paths can be used in the same frame.
+#[derive(Debug)]
+pub(crate) struct EmbedState {
+    /// Format the pipeline below was built for.
+    pub(crate) format: Option<wgpu::TextureFormat>,
+    /// Pipeline for `format`, or `None` when the stage's own pipeline targets it already.
+    pub(crate) pipeline: Option<wgpu::RenderPipeline>,
+    pub(crate) globals_buffer: wgpu::Buffer,
+    /// Dropped whenever the stage recreates its texture or switches samplers.
+    pub(crate) bind_group: Option<wgpu::BindGroup>,
+    pub(crate) scaling_state: Option<ScalingState>,
+    viewport: Rect,
+}
+
+impl EmbedState {
+    pub(crate) fn new(device: &wgpu::Device, label: &str) -> Self {
+        let globals_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: std::mem::size_of::<GlobalsUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            format: None,
+            pipeline: None,
+            globals_buffer,
+            bind_group: None,
+            scaling_state: None,
+            viewport: Rect {
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 0,
+            },
+        }
+    }
+
+    /// Place the stage in `viewport` (target pixels) with scaling computed for its size.
+    pub(crate) fn set_viewport(
+        &mut self,
+        queue: &wgpu::Queue,
+        viewport: Rect,
+        scaling_state: ScalingState,
+        globals: GlobalsUniform,
+    ) {
+        queue.write_buffer(&self.globals_buffer, 0, bytemuck::bytes_of(&globals));
+        self.viewport = viewport;
+        self.scaling_state = Some(scaling_state);
+    }
+
+    /// The viewport set by the last `set_viewport`.
+    pub(crate) fn viewport(&self) -> Rect {
+        self.viewport
+    }
+
+    /// Record the draw: the stage's quad is laid out in `viewport` and clipped to its scaled
+    /// area, so whatever the pass already holds around it is left alone.
+    pub(crate) fn draw(
+        &self,
+        pass: &mut wgpu::RenderPass<'_>,
+        stage_pipeline: &wgpu::RenderPipeline,
+        vertex_buffer: &wgpu::Buffer,
+    ) {
+        let (Some(bind_group), Some(scaling_state)) =
+            (self.bind_group.as_ref(), self.scaling_state.as_ref())
+        else {
+            return;
+        };
+        let viewport = self.viewport;
+        if viewport.width == 0 || viewport.height == 0 || !scaling_state.is_visible() {
+            return;
+        }
+        let clip = scaling_state.clip_rect;
+
+        pass.set_viewport(
+            viewport.x as f32,
+            viewport.y as f32,
+            viewport.width as f32,
+            viewport.height as f32,
+            0.0,
+            1.0,
+        );
+        pass.set_scissor_rect(
+            viewport.x + clip.x,
+            viewport.y + clip.y,
+            clip.width,
+            clip.height,
+        );
+        pass.set_pipeline(self.pipeline.as_ref().unwrap_or(stage_pipeline));
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,1050 @@
+//! Bitmap fonts for drawing text into stage buffers.
+//!
+//! Fonts can be parsed from BDF, PSF (v1/v2) or AngelCode BMFont (text `.fnt`) data, or taken
+//! from the built-in 8x8 font. Text is drawn with the `draw_text` method of each stage.
+
+use crate::{Error, Rect, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Horizontal alignment of each line of text.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Layout options for `draw_text` and `BitmapFont::measure`.
+///
+/// Without `max_width`, lines are aligned around the anchor `x` (left edge, center or right
+/// edge). With `max_width`, lines are wrapped at word boundaries and aligned inside the box
+/// `[x, x + max_width)`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct TextOptions {
+    pub align: TextAlign,
+    pub max_width: Option<u32>,
+    /// Extra pixels between lines (may be negative).
+    pub line_spacing: i32,
+}
+
+impl TextOptions {
+    pub fn with_align(mut self, align: TextAlign) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn with_max_width(mut self, max_width: u32) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+
+    pub fn with_line_spacing(mut self, line_spacing: i32) -> Self {
+        self.line_spacing = line_spacing;
+        self
+    }
+}
+
+/// An RGBA8 page image referenced by a BMFont descriptor.
+#[derive(Debug, Copy, Clone)]
+pub struct FontPage<'a> {
+    pub rgba: &'a [u8],
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Glyph {
+    width: u32,
+    height: u32,
+    /// Offset of the glyph bitmap from the pen position.
+    x_offset: i32,
+    /// Offset of the glyph bitmap from the top of the line.
+    y_offset: i32,
+    advance: i32,
+    /// Coverage per pixel (`0` = empty, `255` = fully covered), `width * height` entries.
+    coverage: Vec<u8>,
+}
+
+/// A bitmap font: per-character coverage masks plus line metrics.
+#[derive(Debug, Clone)]
+pub struct BitmapFont {
+    glyphs: HashMap<char, Glyph>,
+    kerning: HashMap<(char, char), i32>,
+    line_height: u32,
+    fallback: Option<char>,
+}
+
+impl BitmapFont {
+    /// The built-in 8x8 font covering printable ASCII.
+    pub fn builtin_8x8() -> Self {
+        let mut glyphs = HashMap::with_capacity(FONT_8X8.len());
+        for (i, rows) in FONT_8X8.iter().enumerate() {
+            let ch = char::from(0x20 + i as u8);
+            let mut coverage = Vec::with_capacity(64);
+            for row in rows {
+                for bit in 0..8 {
+                    coverage.push(if row & (1 << bit) != 0 { 255 } else { 0 });
+                }
+            }
+            glyphs.insert(
+                ch,
+                Glyph {
+                    width: 8,
+                    height: 8,
+                    x_offset: 0,
+                    y_offset: 0,
+                    advance: 8,
+                    coverage,
+                },
+            );
+        }
+        Self {
+            glyphs,
+            kerning: HashMap::new(),
+            line_height: 8,
+            fallback: Some('?'),
+        }
+    }
+
+    /// Load a BDF or PSF font file (detected from its contents).
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        if bytes.starts_with(PSF1_MAGIC) || bytes.starts_with(PSF2_MAGIC) {
+            Self::from_psf(&bytes)
+        } else {
+            let text = std::str::from_utf8(&bytes)
+                .map_err(|_| Error::InvalidFont("BDF data is not valid UTF-8".into()))?;
+            Self::from_bdf(text)
+        }
+    }
+
+    /// Parse a BDF (Glyph Bitmap Distribution Format) font.
+    pub fn from_bdf(text: &str) -> Result<Self> {
+        let mut glyphs = HashMap::new();
+        let mut bbox: Option<(u32, u32, i32, i32)> = None;
+        let mut ascent: Option<i32> = None;
+        let mut descent: Option<i32> = None;
+        let mut saw_start = false;
+
+        let mut lines = text.lines().enumerate();
+        while let Some((line_no, line)) = lines.next() {
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some("STARTFONT") => saw_start = true,
+                Some("FONTBOUNDINGBOX") => {
+                    bbox = Some(parse_box(parts, line_no)?);
+                }
+                Some("FONT_ASCENT") => ascent = Some(parse_ints(parts, 1, line_no)?[0]),
+                Some("FONT_DESCENT") => descent = Some(parse_ints(parts, 1, line_no)?[0]),
+                Some("STARTCHAR") => {
+                    // Glyphs without a BBX use the font's bounding box, offsets included.
+                    let font_box @ (bbox_w, bbox_h, _, bbox_y) =
+                        bbox.ok_or_else(|| bdf_error(line_no, "STARTCHAR before FONTBOUNDINGBOX"))?;
+                    let ascent = match ascent {
+                        Some(ascent) => ascent,
+                        None => (bbox_h as i32)
+                            .checked_add(bbox_y)
+                            .ok_or_else(|| bdf_error(line_no, "font bounding box out of range"))?,
+                    };
+                    let mut encoding: Option<i32> = None;
+                    let mut advance = bbox_w as i32;
+                    let mut glyph_box: Option<(u32, u32, i32, i32)> = None;
+                    let mut coverage = Vec::new();
+
+                    while let Some((line_no, line)) = lines.next() {
+                        let mut parts = line.split_whitespace();
+                        match parts.next() {
+                            Some("ENCODING") => encoding = Some(parse_ints(parts, 1, line_no)?[0]),
+                            Some("DWIDTH") => advance = parse_ints(parts, 1, line_no)?[0],
+                            Some("BBX") => {
+                                glyph_box = Some(parse_box(parts, line_no)?);
+                            }
+                            Some("BITMAP") => {
+                                let (w, h, _, _) = glyph_box.unwrap_or(font_box);
+                                coverage.reserve(w as usize * h as usize);
+                                for _ in 0..h {
+                                    let (line_no, row) = lines
+                                        .next()
+                                        .ok_or_else(|| bdf_error(line_no, "truncated BITMAP"))?;
+                                    push_hex_row(&mut coverage, row.trim(), w, line_no)?;
+                                }
+                            }
+                            Some("ENDCHAR") => break,
+                            _ => {}
+                        }
+                    }
+
+                    let Some(ch) = encoding
+                        .filter(|code| *code >= 0)
+                        .and_then(|code| char::from_u32(code as u32))
+                    else {
+                        continue;
+                    };
+                    let (width, height, x_offset, y_offset) = glyph_box.unwrap_or(font_box);
+                    let top = y_offset
+                        .checked_add(height as i32)
+                        .and_then(|top| ascent.checked_sub(top))
+                        .ok_or_else(|| bdf_error(line_no, "glyph offset out of range"))?;
+                    coverage.resize(width as usize * height as usize, 0);
+                    glyphs.insert(
+                        ch,
+                        Glyph {
+                            width,
+                            height,
+                            x_offset,
+                            y_offset: top,
+                            advance,
+                            coverage,
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        if !saw_start {
+            return Err(Error::InvalidFont("missing STARTFONT".into()));
+        }
+        let (_, bbox_h, _, bbox_y) =
+            bbox.ok_or_else(|| Error::InvalidFont("missing FONTBOUNDINGBOX".into()))?;
+        let line_height = match (ascent, descent) {
+            (Some(ascent), Some(descent)) => {
+                ascent.checked_add(descent).map(|sum| sum.max(1) as u32)
+            }
+            _ => (bbox_h as i32)
+                .checked_add(bbox_y)
+                .map(|top| bbox_h.max(top.max(1) as u32)),
+        }
+        .ok_or_else(|| Error::InvalidFont("font ascent out of range".into()))?;
+
+        Ok(Self::from_parts(glyphs, HashMap::new(), line_height))
+    }
+
+    /// Parse a PC Screen Font (PSF1 or PSF2), using its Unicode table when present.
+    pub fn from_psf(bytes: &[u8]) -> Result<Self> {
+        let (count, width, height, bytes_per_glyph, glyph_offset, table) =
+            if bytes.starts_with(PSF1_MAGIC) {
+                if bytes.len() < 4 {
+                    return Err(Error::InvalidFont("truncated PSF1 header".into()));
+                }
+                let mode = bytes[2];
+                let char_size = bytes[3] as usize;
+                let count = if mode & 0x01 != 0 { 512 } else { 256 };
+                let has_table = mode & 0x06 != 0;
+                (count, 8u32, char_size as u32, char_size, 4usize, has_table)
+            } else if bytes.starts_with(PSF2_MAGIC) {
+                if bytes.len() < 32 {
+                    return Err(Error::InvalidFont("truncated PSF2 header".into()));
+                }
+                let read = |at: usize| {
+                    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+                };
+                let header_size = read(8) as usize;
+                let flags = read(12);
+                let count = read(16) as usize;
+                let char_size = read(20) as usize;
+                let height = read(24);
+                let width = read(28);
+                (
+                    count,
+                    width,
+                    height,
+                    char_size,
+                    header_size,
+                    flags & 0x01 != 0,
+                )
+            } else {
+                return Err(Error::InvalidFont("unknown PSF magic".into()));
+            };
+
+        let row_bytes = width.div_ceil(8) as usize;
+        let glyph_size = row_bytes.checked_mul(height as usize);
+        if width == 0 || height == 0 || glyph_size.is_none_or(|size| bytes_per_glyph < size) {
+            return Err(Error::InvalidFont("invalid PSF glyph size".into()));
+        }
+        let glyph_end = count
+            .checked_mul(bytes_per_glyph)
+            .and_then(|size| size.checked_add(glyph_offset))
+            .filter(|end| *end <= bytes.len())
+            .ok_or_else(|| Error::InvalidFont("truncated PSF glyph data".into()))?;
+
+        let mut bitmaps = Vec::with_capacity(count);
+        for index in 0..count {
+            let start = glyph_offset + index * bytes_per_glyph;
+            let data = &bytes[start..start + bytes_per_glyph];
+            let mut coverage = Vec::with_capacity(width as usize * height as usize);
+            for row in 0..height as usize {
+                let row_data = &data[row * row_bytes..(row + 1) * row_bytes];
+                for x in 0..width as usize {
+                    let set = row_data[x / 8] & (0x80 >> (x % 8)) != 0;
+                    coverage.push(if set { 255 } else { 0 });
+                }
+            }
+            bitmaps.push(coverage);
+        }
+
+        let mut mapping: Vec<(char, usize)> = Vec::new();
+        if table {
+            if bytes.starts_with(PSF1_MAGIC) {
+                parse_psf1_table(&bytes[glyph_end..], count, &mut mapping);
+            } else {
+                parse_psf2_table(&bytes[glyph_end..], count, &mut mapping);
+            }
+        } else {
+            for index in 0..count {
+                if let Some(ch) = char::from_u32(index as u32) {
+                    mapping.push((ch, index));
+                }
+            }
+        }
+
+        let mut glyphs = HashMap::with_capacity(mapping.len());
+        for (ch, index) in mapping {
+            glyphs.entry(ch).or_insert_with(|| Glyph {
+                width,
+                height,
+                x_offset: 0,
+                y_offset: 0,
+                advance: width as i32,
+                coverage: bitmaps[index].clone(),
+            });
+        }
+
+        Ok(Self::from_parts(glyphs, HashMap::new(), height))
+    }
+
+    /// Parse an AngelCode BMFont text descriptor; glyph coverage is read from the alpha
+    /// channel of the given pages (indexed by the `page` attribute).
+    pub fn from_bmfont(descriptor: &str, pages: &[FontPage<'_>]) -> Result<Self> {
+        let mut glyphs = HashMap::new();
+        let mut kerning = HashMap::new();
+        let mut line_height: Option<u32> = None;
+
+        for (line_no, line) in descriptor.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            let Some(tag) = tokens.next() else {
+                continue;
+            };
+            let attrs = parse_bmfont_attrs(line[tag.len()..].trim_start());
+            let get = |key: &str| -> Result<i32> {
+                attrs
+                    .iter()
+                    .find(|(k, _)| *k == key)
+                    .and_then(|(_, v)| v.parse::<i32>().ok())
+                    .ok_or_else(|| {
+                        Error::InvalidFont(format!(
+                            "BMFont line {}: missing or invalid `{key}`",
+                            line_no + 1
+                        ))
+                    })
+            };
+
+            match tag {
+                "common" => line_height = Some(get("lineHeight")?.max(1) as u32),
+                "char" => {
+                    let Some(ch) = char::from_u32(get("id")? as u32) else {
+                        continue;
+                    };
+                    let page_index = get("page").unwrap_or(0) as usize;
+                    let page = pages.get(page_index).ok_or_else(|| {
+                        Error::InvalidFont(format!("BMFont page {page_index} not provided"))
+                    })?;
+                    let src_x = get("x")?.max(0) as u32;
+                    let src_y = get("y")?.max(0) as u32;
+                    let width = get("width")?.max(0) as u32;
+                    let height = get("height")?.max(0) as u32;
+                    if src_x as u64 + width as u64 > page.width as u64
+                        || src_y as u64 + height as u64 > page.height as u64
+                        || (page.rgba.len() as u64) < page.width as u64 * page.height as u64 * 4
+                    {
+                        return Err(Error::InvalidFont(format!(
+                            "BMFont glyph {} is outside page {page_index}",
+                            ch.escape_debug()
+                        )));
+                    }
+
+                    let mut coverage = Vec::with_capacity(width as usize * height as usize);
+                    for y in src_y..src_y + height {
+                        for x in src_x..src_x + width {
+                            let offset = (y as usize * page.width as usize + x as usize) * 4;
+                            coverage.push(page.rgba[offset + 3]);
+                        }
+                    }
+                    glyphs.insert(
+                        ch,
+                        Glyph {
+                            width,
+                            height,
+                            x_offset: get("xoffset")?,
+                            y_offset: get("yoffset")?,
+                            advance: get("xadvance")?,
+                            coverage,
+                        },
+                    );
+                }
+                "kerning" => {
+                    let first = char::from_u32(get("first")? as u32);
+                    let second = char::from_u32(get("second")? as u32);
+                    if let (Some(first), Some(second)) = (first, second) {
+                        kerning.insert((first, second), get("amount")?);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let line_height =
+            line_height.ok_or_else(|| Error::InvalidFont("BMFont missing `common`".into()))?;
+        Ok(Self::from_parts(glyphs, kerning, line_height))
+    }
+
+    fn from_parts(
+        glyphs: HashMap<char, Glyph>,
+        kerning: HashMap<(char, char), i32>,
+        line_height: u32,
+    ) -> Self {
+        let fallback = ['\u{fffd}', '?']
+            .into_iter()
+            .find(|ch| glyphs.contains_key(ch));
+        Self {
+            glyphs,
+            kerning,
+            line_height,
+            fallback,
+        }
+    }
+
+    pub fn line_height(&self) -> u32 {
+        self.line_height
+    }
+
+    /// Set the character drawn for characters missing from the font (`None` skips them).
+    pub fn set_fallback(&mut self, fallback: Option<char>) {
+        self.fallback = fallback;
+    }
+
+    pub fn has_glyph(&self, ch: char) -> bool {
+        self.glyphs.contains_key(&ch)
+    }
+
+    /// Measure the size in pixels of `text` laid out with `options`.
+    pub fn measure(&self, text: &str, options: TextOptions) -> (u32, u32) {
+        let lines = self.layout_lines(text, options.max_width);
+        let width = lines.iter().map(|line| line.width).max().unwrap_or(0);
+        (width, self.block_height(lines.len(), options.line_spacing))
+    }
+
+    fn glyph(&self, ch: char) -> Option<(char, &Glyph)> {
+        self.glyphs.get(&ch).map(|glyph| (ch, glyph)).or_else(|| {
+            let fallback = self.fallback?;
+            self.glyphs.get(&fallback).map(|glyph| (fallback, glyph))
+        })
+    }
+
+    fn block_height(&self, lines: usize, line_spacing: i32) -> u32 {
+        if lines == 0 {
+            return 0;
+        }
+        let height =
+            lines as i64 * self.line_height as i64 + (lines as i64 - 1) * line_spacing as i64;
+        height.max(0) as u32
+    }
+
+    fn advance_width(&self, chars: &[char]) -> u32 {
+        let mut width = 0i64;
+        let mut prev: Option<char> = None;
+        for &ch in chars {
+            let Some((resolved, glyph)) = self.glyph(ch) else {
+                continue;
+            };
+            if let Some(prev) = prev {
+                width += *self.kerning.get(&(prev, resolved)).unwrap_or(&0) as i64;
+            }
+            width += glyph.advance as i64;
+            prev = Some(resolved);
+        }
+        width.max(0) as u32
+    }
+
+    fn layout_lines(&self, text: &str, max_width: Option<u32>) -> Vec<Line> {
+        let mut lines = Vec::new();
+        for paragraph in text.split('\n') {
+            let chars: Vec<char> = paragraph.trim_end_matches('\r').chars().collect();
+            let Some(max_width) = max_width else {
+                let width = self.advance_width(&chars);
+                lines.push(Line { chars, width });
+                continue;
+            };
+
+            let mut current: Vec<char> = Vec::new();
+            let mut start = 0;
+            while start < chars.len() {
+                // Each word carries its leading spaces so joined lines keep their spacing.
+                let mut end = start;
+                while end < chars.len() && chars[end] == ' ' {
+                    end += 1;
+                }
+                while end < chars.len() && chars[end] != ' ' {
+                    end += 1;
+                }
+                let word = &chars[start..end];
+                start = end;
+
+                let mut candidate = current.clone();
+                candidate.extend_from_slice(word);
+                if self.advance_width(&candidate) <= max_width {
+                    current = candidate;
+                    continue;
+                }
+
+                if !current.is_empty() {
+                    let width = self.advance_width(&current);
+                    lines.push(Line {
+                        chars: std::mem::take(&mut current),
+                        width,
+                    });
+                }
+
+                // Break words that are too long for a line on their own.
+                for &ch in word.iter().skip_while(|ch| **ch == ' ') {
+                    current.push(ch);
+                    if current.len() > 1 && self.advance_width(&current) > max_width {
+                        current.pop();
+                        let width = self.advance_width(&current);
+                        lines.push(Line {
+                            chars: std::mem::replace(&mut current, vec![ch]),
+                            width,
+                        });
+                    }
+                }
+            }
+            let width = self.advance_width(&current);
+            lines.push(Line {
+                chars: current,
+                width,
+            });
+        }
+        lines
+    }
+}
+
+struct Line {
+    chars: Vec<char>,
+    width: u32,
+}
+
+/// Draw `text` with its top-left layout anchor at `(x, y)`.
+///
+/// `plot` receives each covered destination pixel and its coverage. Returns the touched rect.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn draw_text<P>(
+    dst: &mut [P],
+    dst_width: u32,
+    dst_height: u32,
+    font: &BitmapFont,
+    text: &str,
+    x: i32,
+    y: i32,
+    options: &TextOptions,
+    mut plot: impl FnMut(&mut P, u8),
+) -> Option<Rect> {
+    let lines = font.layout_lines(text, options.max_width);
+    let mut touched: Option<Rect> = None;
+    let line_step = font.line_height as i64 + options.line_spacing as i64;
+
+    for (line_index, line) in lines.iter().enumerate() {
+        let line_y = y as i64 + line_index as i64 * line_step;
+        let width = line.width as i64;
+        let mut pen_x = match (options.align, options.max_width) {
+            (TextAlign::Left, _) => x as i64,
+            (TextAlign::Center, Some(box_width)) => x as i64 + (box_width as i64 - width) / 2,
+            (TextAlign::Center, None) => x as i64 - width / 2,
+            (TextAlign::Right, Some(box_width)) => x as i64 + box_width as i64 - width,
+            (TextAlign::Right, None) => x as i64 - width,
+        };
+
+        let mut prev: Option<char> = None;
+        for &ch in &line.chars {
+            let Some((resolved, glyph)) = font.glyph(ch) else {
+                continue;
+            };
+            if let Some(prev) = prev {
+                pen_x += *font.kerning.get(&(prev, resolved)).unwrap_or(&0) as i64;
+            }
+            prev = Some(resolved);
+
+            let gx = pen_x + glyph.x_offset as i64;
+            let gy = line_y + glyph.y_offset as i64;
+            pen_x += glyph.advance as i64;
+
+            let x0 = gx.max(0);
+            let y0 = gy.max(0);
+            let x1 = (gx + glyph.width as i64).min(dst_width as i64);
+            let y1 = (gy + glyph.height as i64).min(dst_height as i64);
+            if x0 >= x1 || y0 >= y1 {
+                continue;
+            }
+
+            for py in y0..y1 {
+                let src_row = (py - gy) as usize * glyph.width as usize;
+                let dst_row = py as usize * dst_width as usize;
+                for px in x0..x1 {
+                    let coverage = glyph.coverage[src_row + (px - gx) as usize];
+                    if coverage != 0 {
+                        plot(&mut dst[dst_row + px as usize], coverage);
+                    }
+                }
+            }
+
+            let rect = Rect {
+                x: x0 as u32,
+                y: y0 as u32,
+                width: (x1 - x0) as u32,
+                height: (y1 - y0) as u32,
+            };
+            touched = Some(touched.map_or(rect, |touched| touched.union(rect)));
+        }
+    }
+
+    touched
+}
+
+const PSF1_MAGIC: &[u8] = &[0x36, 0x04];
+const PSF2_MAGIC: &[u8] = &[0x72, 0xb5, 0x4a, 0x86];
+
+/// Largest BDF glyph width or height accepted, so a hostile bounding box cannot demand a huge
+/// allocation.
+const MAX_BDF_GLYPH_SIDE: u32 = 1024;
+
+fn bdf_error(line_no: usize, message: &str) -> Error {
+    Error::InvalidFont(format!("BDF line {}: {message}", line_no + 1))
+}
+
+fn parse_ints<'a>(
+    parts: impl Iterator<Item = &'a str>,
+    count: usize,
+    line_no: usize,
+) -> Result<Vec<i32>> {
+    let values: Vec<i32> = parts
+        .take(count)
+        .map(|part| part.parse::<i32>())
+        .collect::<std::result::Result<_, _>>()
+        .map_err(|_| bdf_error(line_no, "invalid number"))?;
+    if values.len() != count {
+        return Err(bdf_error(line_no, "missing values"));
+    }
+    Ok(values)
+}
+
+/// Parse a `FONTBOUNDINGBOX` or `BBX` line, rejecting boxes larger than [`MAX_BDF_GLYPH_SIDE`].
+fn parse_box<'a>(
+    parts: impl Iterator<Item = &'a str>,
+    line_no: usize,
+) -> Result<(u32, u32, i32, i32)> {
+    let values = parse_ints(parts, 4, line_no)?;
+    let side = |value: i32| {
+        u32::try_from(value)
+            .ok()
+            .filter(|side| *side <= MAX_BDF_GLYPH_SIDE)
+            .ok_or_else(|| bdf_error(line_no, "bounding box size out of range"))
+    };
+    Ok((side(values[0])?, side(values[1])?, values[2], values[3]))
+}
+
+fn push_hex_row(coverage: &mut Vec<u8>, row: &str, width: u32, line_no: usize) -> Result<()> {
+    let invalid = || bdf_error(line_no, "invalid BITMAP row");
+    if !row.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    let bytes: Vec<u8> = row
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .filter(|digits| digits.bytes().all(|byte| byte.is_ascii_hexdigit()))
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect::<Result<_>>()?;
+    for x in 0..width as usize {
+        let set = bytes
+            .get(x / 8)
+            .is_some_and(|byte| byte & (0x80 >> (x % 8)) != 0);
+        coverage.push(if set { 255 } else { 0 });
+    }
+    Ok(())
+}
+
+fn parse_psf1_table(table: &[u8], count: usize, mapping: &mut Vec<(char, usize)>) {
+    let mut words = table
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+    for index in 0..count {
+        let mut in_sequence = false;
+        for word in words.by_ref() {
+            match word {
+                0xffff => break,
+                0xfffe => in_sequence = true,
+                code if !in_sequence => {
+                    if let Some(ch) = char::from_u32(code as u32) {
+                        mapping.push((ch, index));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn parse_psf2_table(table: &[u8], count: usize, mapping: &mut Vec<(char, usize)>) {
+    let mut entries = table.split(|byte| *byte == 0xff);
+    for index in 0..count {
+        let Some(entry) = entries.next() else {
+            break;
+        };
+        let singles = entry.split(|byte| *byte == 0xfe).next().unwrap_or(&[]);
+        if let Ok(text) = std::str::from_utf8(singles) {
+            mapping.extend(text.chars().map(|ch| (ch, index)));
+        }
+    }
+}
+
+fn parse_bmfont_attrs(rest: &str) -> Vec<(&str, &str)> {
+    let mut attrs = Vec::new();
+    let mut rest = rest;
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim();
+        let after = &rest[eq + 1..];
+        let (value, remaining) = if let Some(quoted) = after.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            (&quoted[..end], quoted.get(end + 1..).unwrap_or(""))
+        } else {
+            let end = after.find(char::is_whitespace).unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+        attrs.push((key, value));
+        rest = remaining.trim_start();
+    }
+    attrs
+}
+
+/// Public domain 8x8 font (printable ASCII, `0x20..=0x7e`); bit 0 is the leftmost pixel.
+const FONT_8X8: [[u8; 8]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x18, 0x3c, 0x3c, 0x18, 0x18, 0x00, 0x18, 0x00], // '!'
+    [0x36, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '"'
+    [0x36, 0x36, 0x7f, 0x36, 0x7f, 0x36, 0x36, 0x00], // '#'
+    [0x0c, 0x3e, 0x03, 0x1e, 0x30, 0x1f, 0x0c, 0x00], // '$'
+    [0x00, 0x63, 0x33, 0x18, 0x0c, 0x66, 0x63, 0x00], // '%'
+    [0x1c, 0x36, 0x1c, 0x6e, 0x3b, 0x33, 0x6e, 0x00], // '&'
+    [0x06, 0x06, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00], // '\''
+    [0x18, 0x0c, 0x06, 0x06, 0x06, 0x0c, 0x18, 0x00], // '('
+    [0x06, 0x0c, 0x18, 0x18, 0x18, 0x0c, 0x06, 0x00], // ')'
+    [0x00, 0x66, 0x3c, 0xff, 0x3c, 0x66, 0x00, 0x00], // '*'
+    [0x00, 0x0c, 0x0c, 0x3f, 0x0c, 0x0c, 0x00, 0x00], // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c, 0x06], // ','
+    [0x00, 0x00, 0x00, 0x3f, 0x00, 0x00, 0x00, 0x00], // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c, 0x00], // '.'
+    [0x60, 0x30, 0x18, 0x0c, 0x06, 0x03, 0x01, 0x00], // '/'
+    [0x3e, 0x63, 0x73, 0x7b, 0x6f, 0x67, 0x3e, 0x00], // '0'
+    [0x0c, 0x0e, 0x0c, 0x0c, 0x0c, 0x0c, 0x3f, 0x00], // '1'
+    [0x1e, 0x33, 0x30, 0x1c, 0x06, 0x33, 0x3f, 0x00], // '2'
+    [0x1e, 0x33, 0x30, 0x1c, 0x30, 0x33, 0x1e, 0x00], // '3'
+    [0x38, 0x3c, 0x36, 0x33, 0x7f, 0x30, 0x78, 0x00], // '4'
+    [0x3f, 0x03, 0x1f, 0x30, 0x30, 0x33, 0x1e, 0x00], // '5'
+    [0x1c, 0x06, 0x03, 0x1f, 0x33, 0x33, 0x1e, 0x00], // '6'
+    [0x3f, 0x33, 0x30, 0x18, 0x0c, 0x0c, 0x0c, 0x00], // '7'
+    [0x1e, 0x33, 0x33, 0x1e, 0x33, 0x33, 0x1e, 0x00], // '8'
+    [0x1e, 0x33, 0x33, 0x3e, 0x30, 0x18, 0x0e, 0x00], // '9'
+    [0x00, 0x0c, 0x0c, 0x00, 0x00, 0x0c, 0x0c, 0x00], // ':'
+    [0x00, 0x0c, 0x0c, 0x00, 0x00, 0x0c, 0x0c, 0x06], // ';'
+    [0x18, 0x0c, 0x06, 0x03, 0x06, 0x0c, 0x18, 0x00], // '<'
+    [0x00, 0x00, 0x3f, 0x00, 0x00, 0x3f, 0x00, 0x00], // '='
+    [0x06, 0x0c, 0x18, 0x30, 0x18, 0x0c, 0x06, 0x00], // '>'
+    [0x1e, 0x33, 0x30, 0x18, 0x0c, 0x00, 0x0c, 0x00], // '?'
+    [0x3e, 0x63, 0x7b, 0x7b, 0x7b, 0x03, 0x1e, 0x00], // '@'
+    [0x0c, 0x1e, 0x33, 0x33, 0x3f, 0x33, 0x33, 0x00], // 'A'
+    [0x3f, 0x66, 0x66, 0x3e, 0x66, 0x66, 0x3f, 0x00], // 'B'
+    [0x3c, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3c, 0x00], // 'C'
+    [0x1f, 0x36, 0x66, 0x66, 0x66, 0x36, 0x1f, 0x00], // 'D'
+    [0x7f, 0x46, 0x16, 0x1e, 0x16, 0x46, 0x7f, 0x00], // 'E'
+    [0x7f, 0x46, 0x16, 0x1e, 0x16, 0x06, 0x0f, 0x00], // 'F'
+    [0x3c, 0x66, 0x03, 0x03, 0x73, 0x66, 0x7c, 0x00], // 'G'
+    [0x33, 0x33, 0x33, 0x3f, 0x33, 0x33, 0x33, 0x00], // 'H'
+    [0x1e, 0x0c, 0x0c, 0x0c, 0x0c, 0x0c, 0x1e, 0x00], // 'I'
+    [0x78, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1e, 0x00], // 'J'
+    [0x67, 0x66, 0x36, 0x1e, 0x36, 0x66, 0x67, 0x00], // 'K'
+    [0x0f, 0x06, 0x06, 0x06, 0x46, 0x66, 0x7f, 0x00], // 'L'
+    [0x63, 0x77, 0x7f, 0x7f, 0x6b, 0x63, 0x63, 0x00], // 'M'
+    [0x63, 0x67, 0x6f, 0x7b, 0x73, 0x63, 0x63, 0x00], // 'N'
+    [0x1c, 0x36, 0x63, 0x63, 0x63, 0x36, 0x1c, 0x00], // 'O'
+    [0x3f, 0x66, 0x66, 0x3e, 0x06, 0x06, 0x0f, 0x00], // 'P'
+    [0x1e, 0x33, 0x33, 0x33, 0x3b, 0x1e, 0x38, 0x00], // 'Q'
+    [0x3f, 0x66, 0x66, 0x3e, 0x36, 0x66, 0x67, 0x00], // 'R'
+    [0x1e, 0x33, 0x07, 0x0e, 0x38, 0x33, 0x1e, 0x00], // 'S'
+    [0x3f, 0x2d, 0x0c, 0x0c, 0x0c, 0x0c, 0x1e, 0x00], // 'T'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x3f, 0x00], // 'U'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x1e, 0x0c, 0x00], // 'V'
+    [0x63, 0x63, 0x63, 0x6b, 0x7f, 0x77, 0x63, 0x00], // 'W'
+    [0x63, 0x63, 0x36, 0x1c, 0x1c, 0x36, 0x63, 0x00], // 'X'
+    [0x33, 0x33, 0x33, 0x1e, 0x0c, 0x0c, 0x1e, 0x00], // 'Y'
+    [0x7f, 0x63, 0x31, 0x18, 0x4c, 0x66, 0x7f, 0x00], // 'Z'
+    [0x1e, 0x06, 0x06, 0x06, 0x06, 0x06, 0x1e, 0x00], // '['
+    [0x03, 0x06, 0x0c, 0x18, 0x30, 0x60, 0x40, 0x00], // '\\'
+    [0x1e, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1e, 0x00], // ']'
+    [0x08, 0x1c, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00], // '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff], // '_'
+    [0x0c, 0x0c, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+    [0x00, 0x00, 0x1e, 0x30, 0x3e, 0x33, 0x6e, 0x00], // 'a'
+    [0x07, 0x06, 0x06, 0x3e, 0x66, 0x66, 0x3b, 0x00], // 'b'
+    [0x00, 0x00, 0x1e, 0x33, 0x03, 0x33, 0x1e, 0x00], // 'c'
+    [0x38, 0x30, 0x30, 0x3e, 0x33, 0x33, 0x6e, 0x00], // 'd'
+    [0x00, 0x00, 0x1e, 0x33, 0x3f, 0x03, 0x1e, 0x00], // 'e'
+    [0x1c, 0x36, 0x06, 0x0f, 0x06, 0x06, 0x0f, 0x00], // 'f'
+    [0x00, 0x00, 0x6e, 0x33, 0x33, 0x3e, 0x30, 0x1f], // 'g'
+    [0x07, 0x06, 0x36, 0x6e, 0x66, 0x66, 0x67, 0x00], // 'h'
+    [0x0c, 0x00, 0x0e, 0x0c, 0x0c, 0x0c, 0x1e, 0x00], // 'i'
+    [0x30, 0x00, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1e], // 'j'
+    [0x07, 0x06, 0x66, 0x36, 0x1e, 0x36, 0x67, 0x00], // 'k'
+    [0x0e, 0x0c, 0x0c, 0x0c, 0x0c, 0x0c, 0x1e, 0x00], // 'l'
+    [0x00, 0x00, 0x33, 0x7f, 0x7f, 0x6b, 0x63, 0x00], // 'm'
+    [0x00, 0x00, 0x1f, 0x33, 0x33, 0x33, 0x33, 0x00], // 'n'
+    [0x00, 0x00, 0x1e, 0x33, 0x33, 0x33, 0x1e, 0x00], // 'o'
+    [0x00, 0x00, 0x3b, 0x66, 0x66, 0x3e, 0x06, 0x0f], // 'p'
+    [0x00, 0x00, 0x6e, 0x33, 0x33, 0x3e, 0x30, 0x78], // 'q'
+    [0x00, 0x00, 0x3b, 0x6e, 0x66, 0x06, 0x0f, 0x00], // 'r'
+    [0x00, 0x00, 0x3e, 0x03, 0x1e, 0x30, 0x1f, 0x00], // 's'
+    [0x08, 0x0c, 0x3e, 0x0c, 0x0c, 0x2c, 0x18, 0x00], // 't'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x33, 0x6e, 0x00], // 'u'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x1e, 0x0c, 0x00], // 'v'
+    [0x00, 0x00, 0x63, 0x6b, 0x7f, 0x7f, 0x36, 0x00], // 'w'
+    [0x00, 0x00, 0x63, 0x36, 0x1c, 0x36, 0x63, 0x00], // 'x'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x3e, 0x30, 0x1f], // 'y'
+    [0x00, 0x00, 0x3f, 0x19, 0x0c, 0x26, 0x3f, 0x00], // 'z'
+    [0x38, 0x0c, 0x0c, 0x07, 0x0c, 0x0c, 0x38, 0x00], // '{'
+    [0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00], // '|'
+    [0x07, 0x0c, 0x0c, 0x38, 0x0c, 0x0c, 0x07, 0x00], // '}'
+    [0x6e, 0x3b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '~'
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BDF: &str = "STARTFONT 2.1
+FONT test
+SIZE 8 75 75
+FONTBOUNDINGBOX 4 4 0 -1
+STARTPROPERTIES 2
+FONT_ASCENT 3
+FONT_DESCENT 1
+ENDPROPERTIES
+CHARS 2
+STARTCHAR A
+ENCODING 65
+DWIDTH 5 0
+BBX 3 2 1 0
+BITMAP
+A0
+40
+ENDCHAR
+STARTCHAR B
+ENCODING 66
+BITMAP
+F0
+90
+90
+F0
+ENDCHAR
+ENDFONT
+";
+
+    #[test]
+    fn bdf_glyph_boxes() {
+        let font = BitmapFont::from_bdf(BDF).unwrap();
+        assert_eq!(font.line_height(), 4);
+
+        let a = &font.glyphs[&'A'];
+        assert_eq!((a.width, a.height, a.x_offset, a.advance), (3, 2, 1, 5));
+        assert_eq!(a.y_offset, 1);
+        assert_eq!(a.coverage, [255, 0, 255, 0, 255, 0]);
+
+        // No BBX: the font bounding box applies, including its y offset.
+        let b = &font.glyphs[&'B'];
+        assert_eq!((b.width, b.height, b.x_offset, b.advance), (4, 4, 0, 4));
+        assert_eq!(b.y_offset, 0);
+        assert_eq!(&b.coverage[..4], &[255; 4]);
+        assert_eq!(&b.coverage[4..8], &[255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn bdf_rejects_malformed_input() {
+        let truncated = &BDF[..BDF.find("40\n").unwrap()];
+        assert!(matches!(
+            BitmapFont::from_bdf(truncated),
+            Err(Error::InvalidFont(_))
+        ));
+        assert!(BitmapFont::from_bdf("FONTBOUNDINGBOX 4 4 0 0\n").is_err());
+        assert!(BitmapFont::from_bdf("STARTFONT 2.1\nSTARTCHAR A\n").is_err());
+        assert!(BitmapFont::from_bdf("STARTFONT 2.1\nFONTBOUNDINGBOX 4 x 0 0\n").is_err());
+        assert!(BitmapFont::from_bdf("STARTFONT 2.1\nFONTBOUNDINGBOX 4 4 0\n").is_err());
+    }
+
+    #[test]
+    fn bdf_rejects_hostile_input() {
+        let rejects = |text: &str| matches!(BitmapFont::from_bdf(text), Err(Error::InvalidFont(_)));
+        // Non-ASCII and odd-length BITMAP rows.
+        assert!(rejects(&BDF.replace("A0\n40\n", "A0\nü\n")));
+        assert!(rejects(&BDF.replace("A0\n40\n", "A0\n4\n")));
+        assert!(rejects(&BDF.replace("A0\n40\n", "A0\n+4\n")));
+        // Oversized or negative boxes.
+        assert!(rejects(
+            &BDF.replace("BBX 3 2 1 0", "BBX 100000 100000 0 0")
+        ));
+        assert!(rejects(&BDF.replace("BBX 3 2 1 0", "BBX -3 2 1 0")));
+        assert!(rejects(&BDF.replace(
+            "FONTBOUNDINGBOX 4 4 0 -1",
+            "FONTBOUNDINGBOX 4 2000 0 -1"
+        )));
+        // Offsets and ascents that overflow the glyph placement.
+        assert!(rejects(&BDF.replace("BBX 3 2 1 0", "BBX 3 2 1 2147483647")));
+        assert!(rejects(
+            &BDF.replace("FONT_ASCENT 3", "FONT_ASCENT -2147483648")
+        ));
+        assert!(rejects(
+            &BDF.replace("FONT_DESCENT 1", "FONT_DESCENT 2147483647")
+        ));
+        let no_ascent = BDF.replace("FONT_ASCENT 3\n", "");
+        assert!(rejects(&no_ascent.replace(
+            "FONTBOUNDINGBOX 4 4 0 -1",
+            "FONTBOUNDINGBOX 4 4 0 2147483647"
+        )));
+    }
+
+    fn psf1(mode: u8, char_size: u8) -> Vec<u8> {
+        let count = if mode & 0x01 != 0 { 512 } else { 256 };
+        let mut bytes = vec![0x36, 0x04, mode, char_size];
+        for index in 0..count {
+            bytes.extend(std::iter::repeat_n(index as u8, char_size as usize));
+        }
+        bytes
+    }
+
+    #[test]
+    fn psf1_without_table_maps_indices() {
+        let font = BitmapFont::from_psf(&psf1(0, 2)).unwrap();
+        assert_eq!(font.line_height(), 2);
+        let a = &font.glyphs[&'A'];
+        assert_eq!((a.width, a.height), (8, 2));
+        // 0x41 = 0b0100_0001, most significant bit first.
+        assert_eq!(&a.coverage[..8], &[0, 255, 0, 0, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn psf1_unicode_table() {
+        let mut bytes = psf1(0x02, 1);
+        for index in 0..256u16 {
+            // Glyph 1 is '€' (plus a sequence that is ignored), the rest map to nothing.
+            if index == 1 {
+                bytes.extend(0x20acu16.to_le_bytes());
+                bytes.extend(0xfffeu16.to_le_bytes());
+                bytes.extend(0x0041u16.to_le_bytes());
+            }
+            bytes.extend(0xffffu16.to_le_bytes());
+        }
+        let font = BitmapFont::from_psf(&bytes).unwrap();
+        assert!(font.has_glyph('€'));
+        assert!(!font.has_glyph('A'));
+    }
+
+    fn psf2(count: u32, char_size: u32, height: u32, width: u32) -> Vec<u8> {
+        let mut bytes = vec![0x72, 0xb5, 0x4a, 0x86];
+        for value in [0, 32, 0x01, count, char_size, height, width] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn psf2_with_unicode_table() {
+        let mut bytes = psf2(2, 4, 2, 10);
+        bytes.extend([0xff, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x80, 0x40]);
+        bytes.extend("x".as_bytes());
+        bytes.push(0xff);
+        bytes.extend("yz".as_bytes());
+        bytes.push(0xff);
+        let font = BitmapFont::from_psf(&bytes).unwrap();
+        assert_eq!(font.glyphs[&'x'].width, 10);
+        assert_eq!(&font.glyphs[&'x'].coverage[..10], &[255; 10]);
+        assert_eq!(
+            &font.glyphs[&'y'].coverage[10..],
+            &[255, 0, 0, 0, 0, 0, 0, 0, 0, 255]
+        );
+        assert_eq!(font.glyphs[&'z'].coverage, font.glyphs[&'y'].coverage);
+    }
+
+    #[test]
+    fn psf_rejects_malformed_input() {
+        let invalid =
+            |bytes: &[u8]| matches!(BitmapFont::from_psf(bytes), Err(Error::InvalidFont(_)));
+        assert!(invalid(&[0x36, 0x04, 0x00]));
+        assert!(invalid(&psf1(0, 0)));
+        assert!(invalid(&psf1(0, 2)[..100]));
+        assert!(invalid(&[0x72, 0xb5, 0x4a, 0x86, 0, 0]));
+        assert!(invalid(&[0x00, 0x01, 0x02, 0x03]));
+        // Glyph counts and sizes whose product overflows.
+        assert!(invalid(&psf2(u32::MAX, u32::MAX, 1, 8)));
+        assert!(invalid(&psf2(2, 1, u32::MAX, u32::MAX)));
+        assert!(invalid(&psf2(1, 4, 2, 10)));
+    }
+
+    #[test]
+    fn bmfont_reads_page_alpha_and_kerning() {
+        let mut rgba = vec![0u8; 4 * 2 * 4];
+        rgba[3] = 255;
+        rgba[7 * 4 + 3] = 128;
+        let pages = [FontPage {
+            rgba: &rgba,
+            width: 4,
+            height: 2,
+        }];
+        let descriptor = "info face=\"Test Font\" size=8
+common lineHeight=10 base=8
+page id=0 file=\"test.png\"
+char id=65 x=0 y=0 width=4 height=2 xoffset=1 yoffset=2 xadvance=5 page=0
+kerning first=65 second=65 amount=-1
+";
+        let font = BitmapFont::from_bmfont(descriptor, &pages).unwrap();
+        assert_eq!(font.line_height(), 10);
+        let a = &font.glyphs[&'A'];
+        assert_eq!((a.x_offset, a.y_offset, a.advance), (1, 2, 5));
+        assert_eq!(a.coverage, [255, 0, 0, 0, 0, 0, 0, 128]);
+        assert_eq!(font.kerning[&('A', 'A')], -1);
+    }
+
+    #[test]
+    fn bmfont_rejects_malformed_input() {
+        let rgba = [0u8; 16];
+        let pages = [FontPage {
+            rgba: &rgba,
+            width: 2,
+            height: 2,
+        }];
+        let invalid = |descriptor: &str| {
+            matches!(
+                BitmapFont::from_bmfont(descriptor, &pages),
+                Err(Error::InvalidFont(_))
+            )
+        };
+        assert!(invalid(
+            "char id=65 x=0 y=0 width=1 height=1 xoffset=0 yoffset=0 xadvance=1\n"
+        ));
+        assert!(invalid("common lineHeight=8\nchar id=65 x=0 y=0 width=3 height=1 xoffset=0 yoffset=0 xadvance=1\n"));
+        assert!(invalid("common lineHeight=8\nchar id=65 x=2147483647 y=0 width=2147483647 height=1 xoffset=0 yoffset=0 xadvance=1\n"));
+        assert!(invalid("common lineHeight=8\nchar id=65 x=0 y=0 width=1 height=1 xoffset=0 yoffset=0 xadvance=1 page=1\n"));
+        assert!(invalid("common lineHeight=8\nchar id=65 x=0 y=0 width=1\n"));
+    }
+}
@@ -0,0 +1,1131 @@
+//! Decoding and encoding images in stage buffer layouts.
+//!
+//! BMP, TGA and PPM are always available; PNG and GIF require the `png` and `gif` cargo features
+//! (enabled by default). Truecolor images decode to [`RgbaImage`]; paletted PNG, GIF, BMP and
+//! TGA images can also be decoded to [`IndexedImage`] for `PixstageIndexed`.
+//!
+//! Images can be saved as PNG, BMP or PPM; [`IndexedImage`] keeps its palette in PNG and BMP.
+
+use crate::{BlitSource, Error, Result};
+use std::path::Path;
+
+/// Image container formats understood by the loaders.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Gif,
+    Bmp,
+    Tga,
+    /// Binary PPM (`P6`, 8 bits per channel, no alpha).
+    Ppm,
+}
+
+impl ImageFormat {
+    /// Detect the format from the file signature.
+    ///
+    /// TGA has no signature, so anything unrecognized is assumed to be TGA.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            ImageFormat::Png
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            ImageFormat::Gif
+        } else if bytes.starts_with(b"BM") {
+            ImageFormat::Bmp
+        } else if let [b'P', b'1'..=b'7', ..] = bytes {
+            // Any Netpbm variant, so the ones other than `P6` are reported as unsupported.
+            ImageFormat::Ppm
+        } else {
+            ImageFormat::Tga
+        }
+    }
+
+    /// Guess the format from a file extension.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "gif" => Some(ImageFormat::Gif),
+            "bmp" | "dib" => Some(ImageFormat::Bmp),
+            "tga" => Some(ImageFormat::Tga),
+            "ppm" => Some(ImageFormat::Ppm),
+            _ => None,
+        }
+    }
+}
+
+/// A decoded RGBA8 image (tightly packed, row-major).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    pub fn blit_source(&self) -> BlitSource<'_, [u8; 4]> {
+        BlitSource::from_rgba8(&self.pixels, self.width, self.height)
+            .expect("RgbaImage holds width * height RGBA8 pixels")
+    }
+
+    /// Convert to RGB565 words, rounding each channel to the nearest value.
+    pub fn to_rgb565(&self) -> Vec<u16> {
+        self.pixels
+            .chunks_exact(4)
+            .map(|p| crate::rgb565::rgba8_to_rgb565([p[0], p[1], p[2], p[3]]))
+            .collect()
+    }
+
+    /// Convert to RGB565 words using 4x4 ordered dithering.
+    pub fn to_rgb565_dithered(&self) -> Vec<u16> {
+        self.dithered(crate::rgb565::rgba8_to_rgb565, [8, 4, 8])
+    }
+
+    /// Convert to ARGB1555 words; pixels with alpha below 128 become transparent.
+    pub fn to_argb1555(&self) -> Vec<u16> {
+        self.pixels
+            .chunks_exact(4)
+            .map(|p| crate::argb1555::rgba8_to_argb1555([p[0], p[1], p[2], p[3]]))
+            .collect()
+    }
+
+    /// Convert to ARGB1555 words using 4x4 ordered dithering.
+    pub fn to_argb1555_dithered(&self) -> Vec<u16> {
+        self.dithered(crate::argb1555::rgba8_to_argb1555, [8, 8, 8])
+    }
+
+    /// Encode as PNG (RGBA), BMP (24-bit, alpha dropped) or PPM (alpha dropped).
+    pub fn encode(&self, format: ImageFormat) -> Result<Vec<u8>> {
+        self.check_size()?;
+        match format {
+            ImageFormat::Png => encode_png(self.width, self.height, &self.pixels, None),
+            ImageFormat::Bmp => Ok(encode_bmp_rgb(self)),
+            ImageFormat::Ppm => Ok(encode_ppm(self)),
+            format => Err(Error::UnsupportedImage(format!(
+                "encoding {format:?} is not supported"
+            ))),
+        }
+    }
+
+    /// Save to `path`, choosing the format from its extension.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let bytes = self.encode(format_for_path(path.as_ref())?)?;
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    pub(crate) fn check_size(&self) -> Result<()> {
+        if self.width == 0
+            || self.height == 0
+            || self.pixels.len() != self.width as usize * self.height as usize * 4
+        {
+            return Err(Error::InvalidImage(
+                "pixel data does not match image size".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn dithered(&self, convert: impl Fn([u8; 4]) -> u16, steps: [i32; 3]) -> Vec<u16> {
+        const BAYER_4X4: [[i32; 4]; 4] =
+            [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+        let width = self.width.max(1) as usize;
+        self.pixels
+            .chunks_exact(4)
+            .enumerate()
+            .map(|(i, p)| {
+                let threshold = BAYER_4X4[(i / width) % 4][(i % width) % 4];
+                let mut rgba = [p[0], p[1], p[2], p[3]];
+                for c in 0..3 {
+                    // Offset by up to +/- half a quantization step before rounding.
+                    let offset = (threshold * 2 - 15) * steps[c] / 32;
+                    rgba[c] = (rgba[c] as i32 + offset).clamp(0, 255) as u8;
+                }
+                convert(rgba)
+            })
+            .collect()
+    }
+}
+
+/// A decoded paletted image: one index per pixel plus up to 256 RGBA8 palette entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedImage {
+    pub width: u32,
+    pub height: u32,
+    pub indices: Vec<u8>,
+    pub palette: Vec<[u8; 4]>,
+}
+
+impl IndexedImage {
+    pub fn blit_source(&self) -> BlitSource<'_, u8> {
+        BlitSource::new(&self.indices, self.width, self.height)
+            .expect("IndexedImage holds width * height indices")
+    }
+
+    pub(crate) fn check_size(&self) -> Result<()> {
+        if self.width == 0
+            || self.height == 0
+            || self.indices.len() != self.width as usize * self.height as usize
+            || self.palette.is_empty()
+            || self.palette.len() > 256
+        {
+            return Err(Error::InvalidImage(
+                "index or palette data does not match image size".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Encode as indexed PNG (with per-entry alpha), 8-bit BMP, or PPM (palette resolved).
+    pub fn encode(&self, format: ImageFormat) -> Result<Vec<u8>> {
+        self.check_size()?;
+        match format {
+            ImageFormat::Png => {
+                encode_png(self.width, self.height, &self.indices, Some(&self.palette))
+            }
+            ImageFormat::Bmp => Ok(encode_bmp_indexed(self)),
+            ImageFormat::Ppm => Ok(encode_ppm(&self.to_rgba8())),
+            format => Err(Error::UnsupportedImage(format!(
+                "encoding {format:?} is not supported"
+            ))),
+        }
+    }
+
+    /// Save to `path`, choosing the format from its extension.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let bytes = self.encode(format_for_path(path.as_ref())?)?;
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    /// Resolve the palette into an RGBA8 image (out-of-range indices become transparent).
+    pub fn to_rgba8(&self) -> RgbaImage {
+        let mut pixels = Vec::with_capacity(self.indices.len() * 4);
+        for &index in &self.indices {
+            let color = self
+                .palette
+                .get(index as usize)
+                .copied()
+                .unwrap_or([0, 0, 0, 0]);
+            pixels.extend_from_slice(&color);
+        }
+        RgbaImage {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+}
+
+/// Decode PNG/GIF/BMP/TGA bytes into RGBA8.
+pub fn decode_rgba8(bytes: &[u8]) -> Result<RgbaImage> {
+    match decode(bytes)? {
+        Decoded::Rgba(image) => Ok(image),
+        Decoded::Indexed(image) => Ok(image.to_rgba8()),
+    }
+}
+
+/// Decode a paletted PNG/GIF/BMP/TGA image into indices plus palette.
+pub fn decode_indexed(bytes: &[u8]) -> Result<IndexedImage> {
+    match decode(bytes)? {
+        Decoded::Indexed(image) => Ok(image),
+        Decoded::Rgba(_) => Err(Error::UnsupportedImage("image is not paletted".to_string())),
+    }
+}
+
+/// Read and decode an image file into RGBA8.
+pub fn open_rgba8(path: impl AsRef<Path>) -> Result<RgbaImage> {
+    decode_rgba8(&std::fs::read(path)?)
+}
+
+/// Read and decode a paletted image file into indices plus palette.
+pub fn open_indexed(path: impl AsRef<Path>) -> Result<IndexedImage> {
+    decode_indexed(&std::fs::read(path)?)
+}
+
+fn format_for_path(path: &Path) -> Result<ImageFormat> {
+    ImageFormat::from_path(path).ok_or_else(|| {
+        Error::UnsupportedImage(format!("unknown image extension: {}", path.display()))
+    })
+}
+
+enum Decoded {
+    Rgba(RgbaImage),
+    Indexed(IndexedImage),
+}
+
+fn decode(bytes: &[u8]) -> Result<Decoded> {
+    let decoded = match ImageFormat::from_bytes(bytes) {
+        ImageFormat::Png => decode_png(bytes)?,
+        ImageFormat::Gif => decode_gif(bytes)?,
+        ImageFormat::Bmp => decode_bmp(bytes)?,
+        ImageFormat::Tga => decode_tga(bytes)?,
+        ImageFormat::Ppm => decode_ppm(bytes)?,
+    };
+    let (width, height) = match &decoded {
+        Decoded::Rgba(image) => (image.width, image.height),
+        Decoded::Indexed(image) => (image.width, image.height),
+    };
+    if width == 0 || height == 0 {
+        return Err(Error::InvalidImage(format!("empty image {width}x{height}")));
+    }
+    Ok(decoded)
+}
+
+#[cfg(feature = "png")]
+fn decode_png(bytes: &[u8]) -> Result<Decoded> {
+    let mut decoder = png::Decoder::new(std::io::Cursor::new(bytes));
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let header = decoder.read_header_info()?;
+    let paletted = header.color_type == png::ColorType::Indexed;
+    if !paletted {
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+    }
+
+    let mut reader = decoder.read_info()?;
+    let buffer_size = reader
+        .output_buffer_size()
+        .ok_or_else(|| Error::InvalidImage("PNG is too large".to_string()))?;
+    let mut buffer = vec![0u8; buffer_size];
+    let frame = reader.next_frame(&mut buffer)?;
+    let (width, height) = (frame.width, frame.height);
+
+    if paletted {
+        let info = reader.info();
+        let rgb = info.palette.as_deref().unwrap_or(&[]);
+        let alpha = info.trns.as_deref().unwrap_or(&[]);
+        let palette = rgb
+            .chunks_exact(3)
+            .enumerate()
+            .map(|(i, c)| [c[0], c[1], c[2], alpha.get(i).copied().unwrap_or(255)])
+            .collect();
+
+        let bits = frame.bit_depth as usize;
+        let mut indices = Vec::with_capacity(width as usize * height as usize);
+        for row in buffer.chunks_exact(frame.line_size).take(height as usize) {
+            unpack_indices(&mut indices, row, bits, width as usize);
+        }
+        return Ok(Decoded::Indexed(IndexedImage {
+            width,
+            height,
+            indices,
+            palette,
+        }));
+    }
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in buffer.chunks_exact(frame.line_size).take(height as usize) {
+        match frame.color_type {
+            png::ColorType::Rgba => pixels.extend_from_slice(&row[..width as usize * 4]),
+            png::ColorType::Rgb => {
+                for c in row.chunks_exact(3).take(width as usize) {
+                    pixels.extend_from_slice(&[c[0], c[1], c[2], 255]);
+                }
+            }
+            png::ColorType::GrayscaleAlpha => {
+                for c in row.chunks_exact(2).take(width as usize) {
+                    pixels.extend_from_slice(&[c[0], c[0], c[0], c[1]]);
+                }
+            }
+            png::ColorType::Grayscale => {
+                for &g in row.iter().take(width as usize) {
+                    pixels.extend_from_slice(&[g, g, g, 255]);
+                }
+            }
+            png::ColorType::Indexed => unreachable!("palette is expanded"),
+        }
+    }
+    Ok(Decoded::Rgba(RgbaImage {
+        width,
+        height,
+        pixels,
+    }))
+}
+
+#[cfg(not(feature = "png"))]
+fn decode_png(_bytes: &[u8]) -> Result<Decoded> {
+    Err(Error::UnsupportedImage(
+        "PNG support requires the `png` feature".to_string(),
+    ))
+}
+
+#[cfg(feature = "gif")]
+fn decode_gif(bytes: &[u8]) -> Result<Decoded> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(bytes)?;
+    let width = decoder.width() as u32;
+    let height = decoder.height() as u32;
+    let global_palette = decoder.global_palette().map(<[u8]>::to_vec);
+    let background = decoder.bg_color();
+
+    let frame = decoder
+        .read_next_frame()?
+        .ok_or_else(|| Error::InvalidImage("GIF has no frames".to_string()))?;
+    let rgb = frame
+        .palette
+        .as_deref()
+        .or(global_palette.as_deref())
+        .ok_or_else(|| Error::InvalidImage("GIF has no palette".to_string()))?;
+    let mut palette: Vec<[u8; 4]> = rgb
+        .chunks_exact(3)
+        .map(|c| [c[0], c[1], c[2], 255])
+        .collect();
+    if let Some(entry) = frame
+        .transparent
+        .and_then(|index| palette.get_mut(index as usize))
+    {
+        entry[3] = 0;
+    }
+
+    // Pixels outside the first frame show the transparent or background index.
+    let fill = frame
+        .transparent
+        .map(usize::from)
+        .or(background)
+        .unwrap_or(0) as u8;
+    let mut indices = vec![fill; width as usize * height as usize];
+    for row in 0..frame.height as usize {
+        let y = frame.top as usize + row;
+        if y >= height as usize {
+            break;
+        }
+        for col in 0..frame.width as usize {
+            let x = frame.left as usize + col;
+            if x >= width as usize {
+                break;
+            }
+            indices[y * width as usize + x] = frame.buffer[row * frame.width as usize + col];
+        }
+    }
+
+    Ok(Decoded::Indexed(IndexedImage {
+        width,
+        height,
+        indices,
+        palette,
+    }))
+}
+
+#[cfg(not(feature = "gif"))]
+fn decode_gif(_bytes: &[u8]) -> Result<Decoded> {
+    Err(Error::UnsupportedImage(
+        "GIF support requires the `gif` feature".to_string(),
+    ))
+}
+
+fn decode_bmp(bytes: &[u8]) -> Result<Decoded> {
+    let invalid = |reason: &str| Error::InvalidImage(format!("BMP: {reason}"));
+    let u16_at = |at: usize| -> Result<u16> {
+        bytes
+            .get(at..at + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .ok_or_else(|| invalid("truncated header"))
+    };
+    let u32_at = |at: usize| -> Result<u32> {
+        bytes
+            .get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| invalid("truncated header"))
+    };
+
+    let data_offset = u32_at(10)? as usize;
+    let header_size = u32_at(14)? as usize;
+    let (width, raw_height, bpp, compression, colors_used, palette_entry_size) =
+        if header_size == 12 {
+            (
+                u16_at(18)? as i32,
+                u16_at(20)? as i16 as i32,
+                u16_at(24)?,
+                0,
+                0,
+                3,
+            )
+        } else if header_size >= 40 {
+            (
+                u32_at(18)? as i32,
+                u32_at(22)? as i32,
+                u16_at(28)?,
+                u32_at(30)?,
+                u32_at(46)? as usize,
+                4,
+            )
+        } else {
+            return Err(invalid("unsupported header"));
+        };
+
+    if width <= 0 || raw_height == 0 {
+        return Err(invalid("invalid dimensions"));
+    }
+    if !matches!(bpp, 1 | 4 | 8 | 16 | 24 | 32) {
+        return Err(invalid("invalid bits per pixel"));
+    }
+    let width = width as u32;
+    let top_down = raw_height < 0;
+    let height = raw_height.unsigned_abs();
+
+    // BI_RGB, or BI_BITFIELDS / BI_ALPHABITFIELDS with explicit channel masks.
+    let masks = match compression {
+        0 => None,
+        3 | 6 => {
+            // Masks directly follow the 40-byte info header (or are part of a larger one).
+            let alpha = if compression == 6 || header_size >= 56 {
+                u32_at(66)?
+            } else {
+                0
+            };
+            Some([u32_at(54)?, u32_at(58)?, u32_at(62)?, alpha])
+        }
+        _ => {
+            return Err(Error::UnsupportedImage(format!(
+                "BMP compression {compression}"
+            )))
+        }
+    };
+
+    let row_size = (width as usize)
+        .checked_mul(bpp as usize)
+        .map(|bits| bits.div_ceil(32) * 4)
+        .ok_or_else(|| invalid("image too large"))?;
+    let pixel_data = row_size
+        .checked_mul(height as usize)
+        .and_then(|len| byte_range(bytes, data_offset, len))
+        .ok_or_else(|| invalid("truncated pixel data"))?;
+    let rows = (0..height as usize).map(|y| {
+        let src_y = if top_down { y } else { height as usize - 1 - y };
+        &pixel_data[src_y * row_size..(src_y + 1) * row_size]
+    });
+
+    if bpp <= 8 {
+        let count = if colors_used == 0 {
+            1usize << bpp
+        } else {
+            colors_used.min(256)
+        };
+        let palette_bytes = 14usize
+            .checked_add(header_size)
+            .and_then(|offset| byte_range(bytes, offset, count * palette_entry_size))
+            .ok_or_else(|| invalid("truncated palette"))?;
+        let palette = palette_bytes
+            .chunks_exact(palette_entry_size)
+            .map(|c| [c[2], c[1], c[0], 255])
+            .collect();
+
+        let mut indices = Vec::with_capacity(width as usize * height as usize);
+        for row in rows {
+            unpack_indices(&mut indices, row, bpp as usize, width as usize);
+        }
+        return Ok(Decoded::Indexed(IndexedImage {
+            width,
+            height,
+            indices,
+            palette,
+        }));
+    }
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in rows {
+        for x in 0..width as usize {
+            let rgba = match (bpp, masks) {
+                (24, None) => {
+                    let p = &row[x * 3..x * 3 + 3];
+                    [p[2], p[1], p[0], 255]
+                }
+                (32, None) => {
+                    let p = &row[x * 4..x * 4 + 4];
+                    [p[2], p[1], p[0], 255]
+                }
+                (16, None) => {
+                    let value = u16::from_le_bytes([row[x * 2], row[x * 2 + 1]]) as u32;
+                    unpack_masked(value, [0x7c00, 0x03e0, 0x001f, 0])
+                }
+                (16, Some(masks)) => {
+                    let value = u16::from_le_bytes([row[x * 2], row[x * 2 + 1]]) as u32;
+                    unpack_masked(value, masks)
+                }
+                (32, Some(masks)) => {
+                    let p = &row[x * 4..x * 4 + 4];
+                    unpack_masked(u32::from_le_bytes([p[0], p[1], p[2], p[3]]), masks)
+                }
+                _ => return Err(Error::UnsupportedImage(format!("BMP {bpp} bits per pixel"))),
+            };
+            pixels.extend_from_slice(&rgba);
+        }
+    }
+    Ok(Decoded::Rgba(RgbaImage {
+        width,
+        height,
+        pixels,
+    }))
+}
+
+fn decode_tga(bytes: &[u8]) -> Result<Decoded> {
+    let invalid = |reason: &str| Error::InvalidImage(format!("TGA: {reason}"));
+    if bytes.len() < 18 {
+        return Err(invalid("truncated header"));
+    }
+    let id_length = bytes[0] as usize;
+    let color_map_type = bytes[1];
+    let image_type = bytes[2];
+    let color_map_first = u16::from_le_bytes([bytes[3], bytes[4]]) as usize;
+    let color_map_length = u16::from_le_bytes([bytes[5], bytes[6]]) as usize;
+    let color_map_depth = bytes[7];
+    let width = u16::from_le_bytes([bytes[12], bytes[13]]) as u32;
+    let height = u16::from_le_bytes([bytes[14], bytes[15]]) as u32;
+    let bpp = bytes[16];
+    let descriptor = bytes[17];
+
+    let rle = matches!(image_type, 9..=11);
+    let kind = image_type & !8;
+    if !matches!(kind, 1..=3) || color_map_type > 1 {
+        return Err(Error::UnsupportedImage(format!(
+            "TGA image type {image_type}"
+        )));
+    }
+
+    let mut offset = 18 + id_length;
+    let mut palette = Vec::new();
+    if color_map_type == 1 {
+        if color_map_depth == 0 {
+            return Err(invalid("invalid color map depth"));
+        }
+        let entry_size = (color_map_depth as usize).div_ceil(8);
+        let map = color_map_length
+            .checked_mul(entry_size)
+            .and_then(|len| byte_range(bytes, offset, len))
+            .ok_or_else(|| invalid("truncated color map"))?;
+        palette = vec![[0, 0, 0, 255]; color_map_first];
+        for entry in map.chunks_exact(entry_size) {
+            palette.push(tga_color(entry)?);
+        }
+        offset += map.len();
+    }
+
+    let bytes_per_pixel = (bpp as usize).div_ceil(8);
+    if bytes_per_pixel == 0 || (kind != 2 && bytes_per_pixel != 1) {
+        return Err(Error::UnsupportedImage(format!("TGA {bpp} bits per pixel")));
+    }
+    let pixel_count = (width as usize)
+        .checked_mul(height as usize)
+        .ok_or_else(|| invalid("image too large"))?;
+    let raw_len = pixel_count
+        .checked_mul(bytes_per_pixel)
+        .ok_or_else(|| invalid("image too large"))?;
+    let mut data = bytes
+        .get(offset..)
+        .ok_or_else(|| invalid("truncated pixel data"))?;
+    // An RLE packet expands to at most 128 pixels, so don't trust the header for the capacity.
+    let mut raw = Vec::with_capacity(raw_len.min(data.len().saturating_mul(128)));
+    if rle {
+        while raw.len() < raw_len {
+            let (&packet, rest) = data.split_first().ok_or_else(|| invalid("truncated RLE"))?;
+            let count = (packet & 0x7f) as usize + 1;
+            let len = if packet & 0x80 != 0 {
+                bytes_per_pixel
+            } else {
+                count * bytes_per_pixel
+            };
+            let chunk = rest.get(..len).ok_or_else(|| invalid("truncated RLE"))?;
+            if packet & 0x80 != 0 {
+                for _ in 0..count {
+                    raw.extend_from_slice(chunk);
+                }
+            } else {
+                raw.extend_from_slice(chunk);
+            }
+            data = &rest[len..];
+        }
+        raw.truncate(raw_len);
+    } else {
+        raw.extend_from_slice(
+            data.get(..raw_len)
+                .ok_or_else(|| invalid("truncated pixel data"))?,
+        );
+    }
+
+    // Reorder rows/columns into top-left origin.
+    let right_to_left = descriptor & 0x10 != 0;
+    let top_down = descriptor & 0x20 != 0;
+    let source_index = |x: usize, y: usize| {
+        let sx = if right_to_left {
+            width as usize - 1 - x
+        } else {
+            x
+        };
+        let sy = if top_down { y } else { height as usize - 1 - y };
+        (sy * width as usize + sx) * bytes_per_pixel
+    };
+
+    if kind == 1 {
+        let mut indices = Vec::with_capacity(pixel_count);
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                indices.push(raw[source_index(x, y)]);
+            }
+        }
+        palette.truncate(256);
+        return Ok(Decoded::Indexed(IndexedImage {
+            width,
+            height,
+            indices,
+            palette,
+        }));
+    }
+
+    let mut pixels = Vec::with_capacity(pixel_count * 4);
+    for y in 0..height as usize {
+        for x in 0..width as usize {
+            let start = source_index(x, y);
+            let p = &raw[start..start + bytes_per_pixel];
+            let rgba = if kind == 3 {
+                [p[0], p[0], p[0], 255]
+            } else {
+                tga_color(p)?
+            };
+            pixels.extend_from_slice(&rgba);
+        }
+    }
+    Ok(Decoded::Rgba(RgbaImage {
+        width,
+        height,
+        pixels,
+    }))
+}
+
+fn decode_ppm(bytes: &[u8]) -> Result<Decoded> {
+    let invalid = |reason: &str| Error::InvalidImage(format!("PPM: {reason}"));
+
+    // Header: magic, width, height, maxval, separated by whitespace (with `#` comments).
+    let mut fields = Vec::with_capacity(4);
+    let mut pos = 0;
+    while fields.len() < 4 {
+        match bytes.get(pos) {
+            Some(b'#') => {
+                while bytes.get(pos).is_some_and(|b| *b != b'\n') {
+                    pos += 1;
+                }
+            }
+            Some(b) if b.is_ascii_whitespace() => pos += 1,
+            Some(_) => {
+                let start = pos;
+                while bytes.get(pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+                    pos += 1;
+                }
+                fields.push(&bytes[start..pos]);
+            }
+            None => return Err(invalid("truncated header")),
+        }
+    }
+    // Exactly one whitespace byte separates the header from the samples.
+    pos += 1;
+
+    let number = |field: &[u8]| -> Result<u32> {
+        std::str::from_utf8(field)
+            .ok()
+            .and_then(|text| text.parse().ok())
+            .ok_or_else(|| invalid("invalid header"))
+    };
+    match fields[0] {
+        b"P6" => {}
+        [b'P', b'1'..=b'7'] => {
+            return Err(Error::UnsupportedImage(format!(
+                "Netpbm variant {}, only binary PPM (P6) is supported",
+                String::from_utf8_lossy(fields[0])
+            )))
+        }
+        _ => return Err(invalid("bad magic")),
+    }
+    let width = number(fields[1])?;
+    let height = number(fields[2])?;
+    if number(fields[3])? != 255 {
+        return Err(Error::UnsupportedImage(
+            "PPM with maxval other than 255".to_string(),
+        ));
+    }
+
+    let samples = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(3))
+        .and_then(|len| byte_range(bytes, pos, len))
+        .ok_or_else(|| invalid("truncated pixel data"))?;
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for rgb in samples.chunks_exact(3) {
+        pixels.extend_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+    }
+    Ok(Decoded::Rgba(RgbaImage {
+        width,
+        height,
+        pixels,
+    }))
+}
+
+#[cfg(feature = "png")]
+fn encode_png(
+    width: u32,
+    height: u32,
+    data: &[u8],
+    palette: Option<&[[u8; 4]]>,
+) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_depth(png::BitDepth::Eight);
+    match palette {
+        Some(palette) => {
+            encoder.set_color(png::ColorType::Indexed);
+            encoder.set_palette(
+                palette
+                    .iter()
+                    .flat_map(|c| [c[0], c[1], c[2]])
+                    .collect::<Vec<u8>>(),
+            );
+            // tRNS may omit trailing opaque entries.
+            let alpha: Vec<u8> = palette.iter().map(|c| c[3]).collect();
+            let len = alpha.iter().rposition(|a| *a != 255).map_or(0, |i| i + 1);
+            if len > 0 {
+                encoder.set_trns(alpha[..len].to_vec());
+            }
+        }
+        None => encoder.set_color(png::ColorType::Rgba),
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(data)?;
+    writer.finish()?;
+    Ok(out)
+}
+
+#[cfg(not(feature = "png"))]
+fn encode_png(
+    _width: u32,
+    _height: u32,
+    _data: &[u8],
+    _palette: Option<&[[u8; 4]]>,
+) -> Result<Vec<u8>> {
+    Err(Error::UnsupportedImage(
+        "PNG support requires the `png` feature".to_string(),
+    ))
+}
+
+fn bmp_header(width: u32, height: u32, bpp: u16, palette_len: usize, row_size: usize) -> Vec<u8> {
+    let data_offset = 14 + 40 + palette_len * 4;
+    let image_size = row_size * height as usize;
+    let mut out = Vec::with_capacity(data_offset + image_size);
+    out.extend_from_slice(b"BM");
+    out.extend_from_slice(&((data_offset + image_size) as u32).to_le_bytes());
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&(data_offset as u32).to_le_bytes());
+    out.extend_from_slice(&40u32.to_le_bytes());
+    out.extend_from_slice(&(width as i32).to_le_bytes());
+    out.extend_from_slice(&(height as i32).to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&bpp.to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&(image_size as u32).to_le_bytes());
+    // 2835 pixels per meter is 72 DPI.
+    out.extend_from_slice(&2835i32.to_le_bytes());
+    out.extend_from_slice(&2835i32.to_le_bytes());
+    out.extend_from_slice(&(palette_len as u32).to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out
+}
+
+fn encode_bmp_rgb(image: &RgbaImage) -> Vec<u8> {
+    let row_size = (image.width as usize * 3).div_ceil(4) * 4;
+    let mut out = bmp_header(image.width, image.height, 24, 0, row_size);
+    let row_len = image.width as usize * 4;
+    for row in image.pixels.chunks_exact(row_len).rev() {
+        for p in row.chunks_exact(4) {
+            out.extend_from_slice(&[p[2], p[1], p[0]]);
+        }
+        out.resize(out.len() + row_size - image.width as usize * 3, 0);
+    }
+    out
+}
+
+fn encode_bmp_indexed(image: &IndexedImage) -> Vec<u8> {
+    let row_size = (image.width as usize).div_ceil(4) * 4;
+    let mut out = bmp_header(image.width, image.height, 8, image.palette.len(), row_size);
+    for c in &image.palette {
+        out.extend_from_slice(&[c[2], c[1], c[0], 0]);
+    }
+    for row in image.indices.chunks_exact(image.width as usize).rev() {
+        out.extend_from_slice(row);
+        out.resize(out.len() + row_size - row.len(), 0);
+    }
+    out
+}
+
+fn encode_ppm(image: &RgbaImage) -> Vec<u8> {
+    let mut out = format!("P6\n{} {}\n255\n", image.width, image.height).into_bytes();
+    out.reserve(image.width as usize * image.height as usize * 3);
+    for p in image.pixels.chunks_exact(4) {
+        out.extend_from_slice(&p[..3]);
+    }
+    out
+}
+
+fn tga_color(entry: &[u8]) -> Result<[u8; 4]> {
+    match entry.len() {
+        2 => {
+            let value = u16::from_le_bytes([entry[0], entry[1]]) as u32;
+            let mut rgba = unpack_masked(value, [0x7c00, 0x03e0, 0x001f, 0]);
+            rgba[3] = 255;
+            Ok(rgba)
+        }
+        3 => Ok([entry[2], entry[1], entry[0], 255]),
+        4 => Ok([entry[2], entry[1], entry[0], entry[3]]),
+        len => Err(Error::UnsupportedImage(format!(
+            "TGA {} bits per color",
+            len * 8
+        ))),
+    }
+}
+
+/// `len` bytes of `bytes` starting at `offset`, or `None` if out of bounds (or overflowing).
+fn byte_range(bytes: &[u8], offset: usize, len: usize) -> Option<&[u8]> {
+    bytes.get(offset..offset.checked_add(len)?)
+}
+
+/// Unpack `width` indices of `bits` (1, 2, 4 or 8) each from a row (most significant bits
+/// first).
+fn unpack_indices(out: &mut Vec<u8>, row: &[u8], bits: usize, width: usize) {
+    debug_assert!(matches!(bits, 1 | 2 | 4 | 8));
+    if bits == 8 {
+        out.extend_from_slice(&row[..width]);
+        return;
+    }
+    let per_byte = 8 / bits;
+    let mask = (1u8 << bits) - 1;
+    for x in 0..width {
+        let byte = row[x / per_byte];
+        let slot = x % per_byte;
+        let shift = 8 - bits * (slot + 1);
+        out.push((byte >> shift) & mask);
+    }
+}
+
+/// Extract RGBA8 from a packed pixel with channel masks (a zero alpha mask means opaque).
+fn unpack_masked(value: u32, masks: [u32; 4]) -> [u8; 4] {
+    let channel = |mask: u32| -> u8 {
+        if mask == 0 {
+            return 255;
+        }
+        let shift = mask.trailing_zeros();
+        let max = (mask >> shift) as u64;
+        let raw = ((value & mask) >> shift) as u64;
+        ((raw * 255 + max / 2) / max) as u8
+    };
+    [
+        channel(masks[0]),
+        channel(masks[1]),
+        channel(masks[2]),
+        channel(masks[3]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba_image() -> RgbaImage {
+        RgbaImage {
+            width: 3,
+            height: 2,
+            pixels: (0..24).map(|i| i * 10).collect(),
+        }
+    }
+
+    fn opaque(image: &RgbaImage) -> Vec<u8> {
+        image
+            .pixels
+            .chunks_exact(4)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect()
+    }
+
+    fn assert_invalid(bytes: &[u8]) {
+        match decode_rgba8(bytes) {
+            Err(Error::InvalidImage(_)) => {}
+            other => panic!("expected InvalidImage, got {other:?}"),
+        }
+    }
+
+    /// Every truncation of a valid file is rejected without panicking.
+    fn assert_prefixes_rejected(bytes: &[u8]) {
+        for len in 0..bytes.len() {
+            assert!(
+                decode_rgba8(&bytes[..len]).is_err(),
+                "prefix of {len} bytes"
+            );
+        }
+    }
+
+    fn set_u16(bytes: &mut [u8], at: usize, value: u16) {
+        bytes[at..at + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn set_u32(bytes: &mut [u8], at: usize, value: u32) {
+        bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn bmp_round_trips() {
+        let image = rgba_image();
+        let bmp = image.encode(ImageFormat::Bmp).unwrap();
+        assert_eq!(decode_rgba8(&bmp).unwrap().pixels, opaque(&image));
+        assert_prefixes_rejected(&bmp);
+
+        let indexed = IndexedImage {
+            width: 5,
+            height: 2,
+            indices: vec![0, 1, 2, 1, 0, 2, 2, 1, 0, 0],
+            palette: vec![[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]],
+        };
+        let bmp = indexed.encode(ImageFormat::Bmp).unwrap();
+        assert_eq!(decode_indexed(&bmp).unwrap(), indexed);
+        assert_prefixes_rejected(&bmp);
+    }
+
+    #[test]
+    fn bmp_rejects_unsupported_bit_depths() {
+        let mut bmp = rgba_image().encode(ImageFormat::Bmp).unwrap();
+        for bpp in [0, 2, 3, 7, 12, 33, 64, u16::MAX] {
+            set_u16(&mut bmp, 28, bpp);
+            assert_invalid(&bmp);
+        }
+    }
+
+    #[test]
+    fn bmp_rejects_hostile_sizes() {
+        let mut bmp = rgba_image().encode(ImageFormat::Bmp).unwrap();
+        set_u32(&mut bmp, 18, i32::MAX as u32);
+        set_u32(&mut bmp, 22, i32::MAX as u32);
+        assert_invalid(&bmp);
+
+        let mut bmp = rgba_image().encode(ImageFormat::Bmp).unwrap();
+        set_u32(&mut bmp, 10, u32::MAX);
+        assert_invalid(&bmp);
+
+        let mut bmp = IndexedImage {
+            width: 1,
+            height: 1,
+            indices: vec![0],
+            palette: vec![[0, 0, 0, 255]],
+        }
+        .encode(ImageFormat::Bmp)
+        .unwrap();
+        set_u32(&mut bmp, 14, u32::MAX);
+        assert!(decode_rgba8(&bmp).is_err());
+    }
+
+    #[test]
+    fn bmp_full_32_bit_masks() {
+        // BI_BITFIELDS: 1x2 pixels at 32 bits with a full-width red mask.
+        let mut bmp = bmp_header(1, 2, 32, 0, 4);
+        set_u32(&mut bmp, 10, 14 + 40 + 12);
+        set_u32(&mut bmp, 30, 3);
+        for mask in [u32::MAX, 0xff00, 0xff] {
+            bmp.extend_from_slice(&mask.to_le_bytes());
+        }
+        bmp.extend_from_slice(&0x8000_0000u32.to_le_bytes());
+        bmp.extend_from_slice(&u32::MAX.to_le_bytes());
+        let image = decode_rgba8(&bmp).unwrap();
+        assert_eq!(image.pixels, [255, 255, 255, 255, 128, 0, 0, 255]);
+        assert_prefixes_rejected(&bmp);
+    }
+
+    fn tga_header(image_type: u8, width: u16, height: u16, bpp: u8) -> Vec<u8> {
+        let mut tga = vec![0u8; 18];
+        tga[2] = image_type;
+        set_u16(&mut tga, 12, width);
+        set_u16(&mut tga, 14, height);
+        tga[16] = bpp;
+        tga[17] = 0x20;
+        tga
+    }
+
+    #[test]
+    fn tga_truecolor_and_rle() {
+        let mut tga = tga_header(2, 2, 1, 24);
+        tga.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+        assert_eq!(
+            decode_rgba8(&tga).unwrap().pixels,
+            [3, 2, 1, 255, 6, 5, 4, 255]
+        );
+        assert_prefixes_rejected(&tga);
+
+        let mut tga = tga_header(10, 3, 1, 32);
+        tga.extend_from_slice(&[0x82, 1, 2, 3, 4]);
+        assert_eq!(decode_rgba8(&tga).unwrap().pixels, [3, 2, 1, 4].repeat(3));
+        assert_prefixes_rejected(&tga);
+    }
+
+    #[test]
+    fn tga_color_mapped() {
+        let mut tga = tga_header(1, 2, 1, 8);
+        tga[1] = 1;
+        set_u16(&mut tga, 5, 2);
+        tga[7] = 24;
+        tga.extend_from_slice(&[0, 0, 255, 255, 0, 0]);
+        tga.extend_from_slice(&[1, 0]);
+        let image = decode_indexed(&tga).unwrap();
+        assert_eq!(image.indices, [1, 0]);
+        assert_eq!(image.palette, [[255, 0, 0, 255], [0, 0, 255, 255]]);
+        assert_prefixes_rejected(&tga);
+    }
+
+    #[test]
+    fn tga_rejects_hostile_headers() {
+        let mut tga = tga_header(1, 1, 1, 8);
+        tga[1] = 1;
+        set_u16(&mut tga, 5, 4);
+        tga[7] = 0;
+        tga.extend_from_slice(&[0; 8]);
+        assert_invalid(&tga);
+
+        // Huge dimensions with almost no data, raw and RLE.
+        let mut tga = tga_header(2, u16::MAX, u16::MAX, 32);
+        tga.extend_from_slice(&[0; 8]);
+        assert_invalid(&tga);
+        let mut tga = tga_header(10, u16::MAX, u16::MAX, 32);
+        tga.extend_from_slice(&[0xff, 0, 0, 0, 0]);
+        assert_invalid(&tga);
+    }
+
+    #[test]
+    fn ppm_round_trips_and_rejects_hostile_headers() {
+        let image = rgba_image();
+        let ppm = image.encode(ImageFormat::Ppm).unwrap();
+        assert_eq!(decode_rgba8(&ppm).unwrap().pixels, opaque(&image));
+        assert_prefixes_rejected(&ppm);
+
+        assert_invalid(b"P6\n4294967295 4294967295\n255\n\0\0\0");
+        assert_invalid(b"P6\n2 x\n255\n");
+        assert_invalid(b"P6 # comment only");
+        assert_invalid(b"P61 2 2 255\n");
+    }
+
+    #[test]
+    fn other_netpbm_variants_are_unsupported() {
+        for magic in ["P1", "P2", "P3", "P4", "P5", "P7"] {
+            let bytes = format!("{magic}\n1 1\n255\n\0\0\0");
+            match decode_rgba8(bytes.as_bytes()) {
+                Err(Error::UnsupportedImage(_)) => {}
+                other => panic!("{magic}: expected UnsupportedImage, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn unpack_masked_handles_every_mask_width() {
+        assert_eq!(unpack_masked(u32::MAX, [u32::MAX, 0, 0, 0]), [255; 4]);
+        assert_eq!(unpack_masked(0x8000_0000, [u32::MAX, 0, 0, 0])[0], 128);
+        assert_eq!(
+            unpack_masked(0x7c00, [0x7c00, 0x03e0, 0x001f, 0]),
+            [255, 0, 0, 255]
+        );
+        assert_eq!(
+            unpack_masked(0x8000, [0, 0, 0, 0x8000]),
+            [255, 255, 255, 255]
+        );
+    }
+}
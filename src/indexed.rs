@@ -1,600 +1,579 @@
-use crate::dirty::DirtyTiles;
-use crate::scaling::{compute_scaling, ScalingState};
-use crate::{Error, PixstageOptions, Rect, Result, ScalingMode, SurfaceTexture};
+use crate::background::BackgroundLayer;
+use crate::convert::ConvertRow;
+use crate::image::IndexedImage;
+use crate::overlay::overlay_bytes;
+use crate::scaling::{compute_scaling, globals_bytes};
+use crate::stage::{
+    create_pipeline, impl_stage, sampler_entry, texture_entry, uniform_entries, Stage, StageFormat,
+    StageGpu,
+};
+use crate::{
+    BitmapFont, BlitOptions, BlitSource, Error, FrameRecorder, FrameSink, Overlays, Rect,
+    RegionMut, Result, ScalingMode, TextOptions, Viewport,
+};
 use wgpu::util::DeviceExt;
 
-fn globals_bytes(ndc_scale: [f32; 2]) -> [f32; 4] {
-    [ndc_scale[0], ndc_scale[1], 0.0, 0.0]
-}
+const SHADER: &str = concat!(
+    include_str!("shaders/overlays.wgsl"),
+    include_str!("shaders/indexed.wgsl")
+);
 
-/// Indexed8 + 256-entry palette (GPU lookup) + incremental texture upload.
+/// Indices looked up through the palette into an RGBA texture, for `resolved_texture`.
 #[derive(Debug)]
-pub struct PixstageIndexed<'win> {
-    surface: wgpu::Surface<'win>,
-    adapter: wgpu::Adapter,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    surface_config: wgpu::SurfaceConfiguration,
-
-    scaling_mode: ScalingMode,
-    scaling_state: ScalingState,
-    clear_color: wgpu::Color,
-
-    vertex_buffer: wgpu::Buffer,
-    globals_buffer: wgpu::Buffer,
+struct PaletteResolve {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    /// The indices or palette changed since the last resolve.
+    pending: bool,
+}
 
+/// The index and palette textures of an indexed stage, drawn with a palette lookup.
+#[derive(Debug)]
+pub(crate) struct IndexedTextures {
     index_texture: wgpu::Texture,
     index_view: wgpu::TextureView,
     palette_texture: wgpu::Texture,
     palette_view: wgpu::TextureView,
-
-    sampler_nearest: wgpu::Sampler,
     bind_group: wgpu::BindGroup,
     pipeline: wgpu::RenderPipeline,
-
-    width: u32,
-    height: u32,
-    indices: Vec<u8>,
+    resolve: Option<PaletteResolve>,
     palette: [[u8; 4]; 256],
-    dirty: DirtyTiles,
     palette_dirty: bool,
-    upload_buffer: Vec<u8>,
+    border_index: Option<u8>,
 }
 
-impl<'win> PixstageIndexed<'win> {
-    /// Asynchronously create an Indexed8 stage.
-    pub async fn new_async<W: wgpu::WindowHandle + 'win>(
-        width: u32,
-        height: u32,
-        surface_texture: SurfaceTexture<W>,
-    ) -> Result<Self> {
-        Self::new_async_with_options(width, height, surface_texture, PixstageOptions::default())
-            .await
+impl IndexedTextures {
+    fn bind_group_with(
+        &self,
+        gpu: &StageGpu,
+        globals_buffer: &wgpu::Buffer,
+        overlays_buffer: &wgpu::Buffer,
+        label: &str,
+    ) -> wgpu::BindGroup {
+        create_bind_group(
+            &gpu.device,
+            &self.pipeline.get_bind_group_layout(0),
+            &self.index_view,
+            &gpu.sampler_nearest,
+            &self.palette_view,
+            globals_buffer,
+            overlays_buffer,
+            label,
+        )
     }
 
-    /// Asynchronously create an Indexed8 stage with options.
-    pub async fn new_async_with_options<W: wgpu::WindowHandle + 'win>(
-        width: u32,
-        height: u32,
-        surface_texture: SurfaceTexture<W>,
-        options: PixstageOptions,
-    ) -> Result<Self> {
-        if width == 0 || height == 0 {
-            return Err(Error::InvalidBufferSize { width, height });
-        }
-
-        let backends = options.backends;
-        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
-            backends,
-            ..Default::default()
+    fn create_resolve(&self, gpu: &StageGpu, width: u32, height: u32) -> PaletteResolve {
+        let device = &gpu.device;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("pixstage_indexed_resolved_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
         });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-        let surface = instance.create_surface(surface_texture.window)?;
-        let compatible_surface = Some(&surface);
-        let adapter =
-            wgpu::util::initialize_adapter_from_env_or_default(&instance, compatible_surface)
-                .await
-                .map_err(|_| Error::AdapterNotFound)?;
-
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                required_limits: adapter.limits(),
-                ..Default::default()
-            })
-            .await?;
-
-        let surface_capabilities = surface.get_capabilities(&adapter);
-        let surface_format = surface_capabilities
-            .formats
-            .iter()
-            .copied()
-            .find(|format| format.is_srgb())
-            .unwrap_or(surface_capabilities.formats[0]);
-        let present_mode = if surface_capabilities
-            .present_modes
-            .contains(&options.present_mode)
-        {
-            options.present_mode
-        } else {
-            wgpu::PresentMode::AutoVsync
-        };
-        let alpha_mode = surface_capabilities.alpha_modes[0];
-
-        let surface_config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: surface_texture.width,
-            height: surface_texture.height,
-            present_mode,
-            desired_maximum_frame_latency: 2,
-            alpha_mode,
-            view_formats: vec![],
-        };
-        surface.configure(&device, &surface_config);
-
-        let scaling_mode = options.scaling_mode;
-        let scaling_state = compute_scaling(
+        let identity = compute_scaling(
+            (width, height),
+            None,
             (width, height),
-            (surface_config.width, surface_config.height),
-            scaling_mode,
+            ScalingMode::Fill,
+            1.0,
+            &Viewport::default(),
         );
-
-        let globals = globals_bytes(scaling_state.ndc_scale);
+        let globals = globals_bytes(&identity);
         let globals_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("pixstage_indexed_globals_buffer"),
+            label: Some("pixstage_indexed_resolve_globals_buffer"),
             contents: bytemuck::bytes_of(&globals),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            usage: wgpu::BufferUsages::UNIFORM,
         });
-
-        let (index_texture, index_view) = create_index_texture(&device, width, height);
-        let (palette_texture, palette_view) = create_palette_texture(&device);
-
-        let sampler_nearest = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: Some("pixstage_indexed_sampler_nearest"),
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Nearest,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
+        // The resolved texture holds plain palette colors, without overlays.
+        let overlays = overlay_bytes(&Overlays::default(), (width, height));
+        let overlays_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("pixstage_indexed_resolve_overlays_buffer"),
+            contents: bytemuck::bytes_of(&overlays),
+            usage: wgpu::BufferUsages::UNIFORM,
         });
+        let bind_group = self.bind_group_with(
+            gpu,
+            &globals_buffer,
+            &overlays_buffer,
+            "pixstage_indexed_resolve_bind_group",
+        );
+        let pipeline = create_pipeline(
+            device,
+            &self.pipeline.get_bind_group_layout(0),
+            "pixstage_indexed_resolve",
+            SHADER,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            None,
+        );
 
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("pixstage_indexed_bind_group_layout"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 3,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: wgpu::BufferSize::new(
-                            std::mem::size_of::<[f32; 4]>() as u64
-                        ),
-                    },
-                    count: None,
-                },
-            ],
-        });
+        PaletteResolve {
+            texture,
+            view,
+            pipeline,
+            bind_group,
+            pending: true,
+        }
+    }
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("pixstage_indexed_bind_group"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&index_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler_nearest),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&palette_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: globals_buffer.as_entire_binding(),
+    fn resolve_palette(&mut self, gpu: &StageGpu, encoder: &mut wgpu::CommandEncoder) {
+        let Some(resolve) = self.resolve.as_mut() else {
+            return;
+        };
+        if !resolve.pending {
+            return;
+        }
+        resolve.pending = false;
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("pixstage_indexed_resolve_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &resolve.view,
+                resolve_target: None,
+                depth_slice: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
                 },
-            ],
-        });
-
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("pixstage_indexed_shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/indexed.wgsl").into()),
-        });
-
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("pixstage_indexed_pipeline_layout"),
-            bind_group_layouts: &[&bind_group_layout],
-            push_constant_ranges: &[],
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
         });
+        pass.set_pipeline(&resolve.pipeline);
+        pass.set_bind_group(0, &resolve.bind_group, &[]);
+        pass.set_vertex_buffer(0, gpu.vertex_buffer.slice(..));
+        pass.draw(0..3, 0..1);
+    }
 
-        let vertex_buffer = create_fullscreen_triangle(&device);
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("pixstage_indexed_pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                compilation_options: Default::default(),
-                buffers: &[fullscreen_triangle_layout()],
+    fn upload_palette(&mut self, queue: &wgpu::Queue) {
+        let palette_bytes = bytemuck::cast_slice(&self.palette);
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.palette_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
             },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                compilation_options: Default::default(),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_config.format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState::default(),
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-            cache: None,
-        });
+            palette_bytes,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(256 * 4),
+                rows_per_image: Some(1),
+            },
+            wgpu::Extent3d {
+                width: 256,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.palette_dirty = false;
+    }
+}
+
+impl StageFormat for IndexedTextures {
+    type Element = u8;
+    type Pixel = u8;
+
+    const LABEL: &'static str = "pixstage_indexed";
+    const ELEMENTS_PER_PIXEL: usize = 1;
+    const TEXEL_BYTES: u32 = 1;
+    const CONVERT: Option<ConvertRow<u8>> = None;
+
+    fn new(gpu: &StageGpu, width: u32, height: u32, target_format: wgpu::TextureFormat) -> Self {
+        let (index_texture, index_view) = create_index_texture(&gpu.device, width, height);
+        let (palette_texture, palette_view) = create_palette_texture(&gpu.device);
+
+        let [globals_entry, overlays_entry] = uniform_entries(3);
+        let bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("pixstage_indexed_bind_group_layout"),
+                    entries: &[
+                        texture_entry(0),
+                        sampler_entry(1),
+                        texture_entry(2),
+                        globals_entry,
+                        overlays_entry,
+                    ],
+                });
+
+        let bind_group = create_bind_group(
+            &gpu.device,
+            &bind_group_layout,
+            &index_view,
+            &gpu.sampler_nearest,
+            &palette_view,
+            &gpu.globals_buffer,
+            &gpu.overlays_buffer,
+            "pixstage_indexed_bind_group",
+        );
+        let pipeline = create_pipeline(
+            &gpu.device,
+            &bind_group_layout,
+            Self::LABEL,
+            SHADER,
+            target_format,
+            Some(wgpu::BlendState::ALPHA_BLENDING),
+        );
 
         let mut palette = [[0u8; 4]; 256];
         for entry in palette.iter_mut() {
             entry[3] = 255;
         }
 
-        let mut dirty = DirtyTiles::new(width, height, 32);
-        dirty.mark_full();
-
-        let mut stage = Self {
-            surface,
-            adapter,
-            device,
-            queue,
-            surface_config,
-            scaling_mode,
-            scaling_state,
-            clear_color: options.clear_color,
-            vertex_buffer,
-            globals_buffer,
+        let mut textures = Self {
             index_texture,
             index_view,
             palette_texture,
             palette_view,
-            sampler_nearest,
             bind_group,
             pipeline,
-            width,
-            height,
-            indices: vec![0u8; width as usize * height as usize],
+            resolve: None,
             palette,
-            dirty,
             palette_dirty: true,
-            upload_buffer: Vec::new(),
+            border_index: None,
         };
-
-        stage.upload_palette();
-        Ok(stage)
+        textures.upload_palette(&gpu.queue);
+        textures
     }
 
-    /// Synchronously create an Indexed8 stage (native only).
-    #[cfg(not(target_arch = "wasm32"))]
-    pub fn new<W: wgpu::WindowHandle + 'win>(
-        width: u32,
-        height: u32,
-        surface_texture: SurfaceTexture<W>,
-    ) -> Result<Self> {
-        pollster::block_on(Self::new_async(width, height, surface_texture))
+    fn resize(&mut self, gpu: &StageGpu, width: u32, height: u32) {
+        (self.index_texture, self.index_view) = create_index_texture(&gpu.device, width, height);
+        self.bind_group = self.bind_group_with(
+            gpu,
+            &gpu.globals_buffer,
+            &gpu.overlays_buffer,
+            "pixstage_indexed_bind_group",
+        );
+        if self.resolve.is_some() {
+            self.resolve = Some(self.create_resolve(gpu, width, height));
+        }
     }
 
-    /// Synchronously create an Indexed8 stage with options (native only).
-    #[cfg(not(target_arch = "wasm32"))]
-    pub fn new_with_options<W: wgpu::WindowHandle + 'win>(
-        width: u32,
-        height: u32,
-        surface_texture: SurfaceTexture<W>,
-        options: PixstageOptions,
-    ) -> Result<Self> {
-        pollster::block_on(Self::new_async_with_options(
-            width,
-            height,
-            surface_texture,
-            options,
-        ))
+    fn upload_texture(&self) -> &wgpu::Texture {
+        &self.index_texture
     }
 
-    pub fn adapter(&self) -> &wgpu::Adapter {
-        &self.adapter
+    fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
     }
 
-    pub fn device(&self) -> &wgpu::Device {
-        &self.device
+    fn bind_group(&self, _scaling_mode: ScalingMode) -> &wgpu::BindGroup {
+        &self.bind_group
     }
 
-    pub fn queue(&self) -> &wgpu::Queue {
-        &self.queue
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        target_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        create_pipeline(
+            device,
+            &self.pipeline.get_bind_group_layout(0),
+            Self::LABEL,
+            SHADER,
+            target_format,
+            Some(wgpu::BlendState::ALPHA_BLENDING),
+        )
     }
 
-    pub fn buffer_size(&self) -> (u32, u32) {
-        (self.width, self.height)
+    fn create_bind_group(
+        &self,
+        gpu: &StageGpu,
+        _scaling_mode: ScalingMode,
+        globals_buffer: &wgpu::Buffer,
+        label: &str,
+    ) -> wgpu::BindGroup {
+        self.bind_group_with(gpu, globals_buffer, &gpu.overlays_buffer, label)
     }
 
-    pub fn surface_size(&self) -> (u32, u32) {
-        (self.surface_config.width, self.surface_config.height)
+    fn frame_view(&self) -> &wgpu::TextureView {
+        // Image backgrounds bring their own texture; any view will do for them.
+        self.resolve
+            .as_ref()
+            .map_or(&self.index_view, |resolve| &resolve.view)
     }
 
-    pub fn set_scaling_mode(&mut self, scaling_mode: ScalingMode) {
-        if self.scaling_mode == scaling_mode {
-            return;
+    fn sample_frame(&mut self, gpu: &StageGpu, width: u32, height: u32) {
+        if self.resolve.is_none() {
+            self.resolve = Some(self.create_resolve(gpu, width, height));
         }
-        self.scaling_mode = scaling_mode;
-        self.recompute_scaling();
     }
 
-    pub fn clear_color(&mut self, clear_color: wgpu::Color) {
-        self.clear_color = clear_color;
+    fn border_color(&self) -> Option<wgpu::Color> {
+        let [r, g, b, a] = self.palette[self.border_index? as usize];
+        Some(wgpu::Color {
+            r: srgb_to_linear(r),
+            g: srgb_to_linear(g),
+            b: srgb_to_linear(b),
+            a: a as f64 / 255.0,
+        })
     }
 
-    pub fn resize_surface(&mut self, width: u32, height: u32) {
-        if width == 0 || height == 0 {
-            return;
+    fn reset_border(&mut self) {
+        self.border_index = None;
+    }
+
+    fn write_frame(
+        &self,
+        sink: &mut FrameSink,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+    ) -> Result<()> {
+        sink.write_indexed_frame(&IndexedImage {
+            width,
+            height,
+            indices: pixels.to_vec(),
+            palette: self.palette.to_vec(),
+        })
+    }
+
+    fn capture(&self, recorder: &mut FrameRecorder, pixels: &[u8], width: u32, height: u32) {
+        recorder.capture_indexed(width, height, pixels, &self.palette);
+    }
+
+    fn upload_state(&mut self, queue: &wgpu::Queue) -> bool {
+        let palette_changed = self.palette_dirty;
+        if palette_changed {
+            self.upload_palette(queue);
         }
-        self.surface_config.width = width;
-        self.surface_config.height = height;
-        self.surface.configure(&self.device, &self.surface_config);
-        self.recompute_scaling();
+        palette_changed
     }
 
-    pub fn resize_buffer(&mut self, width: u32, height: u32) -> Result<()> {
-        if width == 0 || height == 0 {
-            return Err(Error::InvalidBufferSize { width, height });
+    fn finish_upload(&mut self, gpu: &StageGpu, encoder: &mut wgpu::CommandEncoder, changed: bool) {
+        if let Some(resolve) = self.resolve.as_mut() {
+            resolve.pending |= changed;
         }
+        self.resolve_palette(gpu, encoder);
+    }
+}
 
-        self.width = width;
-        self.height = height;
-        self.indices.resize(width as usize * height as usize, 0);
-        self.dirty.resize(width, height);
-
-        let (index_texture, index_view) = create_index_texture(&self.device, width, height);
-        self.index_texture = index_texture;
-        self.index_view = index_view;
-
-        let bind_group_layout = self.pipeline.get_bind_group_layout(0);
-        self.bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("pixstage_indexed_bind_group"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&self.index_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&self.sampler_nearest),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&self.palette_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: self.globals_buffer.as_entire_binding(),
-                },
-            ],
-        });
+/// Indexed8 + 256-entry palette (GPU lookup) + incremental texture upload.
+#[derive(Debug)]
+pub struct PixstageIndexed<'win> {
+    stage: Stage<'win, IndexedTextures>,
+}
 
-        self.recompute_scaling();
-        self.dirty.mark_full();
-        Ok(())
+impl_stage!(PixstageIndexed, "Indexed8", u8);
+
+impl PixstageIndexed<'_> {
+    /// The GPU texture holding the uploaded indices (`R8Unorm`, index / 255).
+    ///
+    /// Current once the encoder from `render`, `prepare` or `render_to` has been submitted;
+    /// `resize_buffer` replaces it.
+    pub fn index_texture(&self) -> &wgpu::Texture {
+        &self.stage.format.index_texture
     }
 
-    pub fn frame(&self) -> &[u8] {
-        &self.indices
+    pub fn index_view(&self) -> &wgpu::TextureView {
+        &self.stage.format.index_view
     }
 
-    pub fn frame_mut(&mut self) -> &mut [u8] {
-        self.dirty.mark_full();
-        &mut self.indices
+    /// The 256x1 `Rgba8UnormSrgb` palette texture.
+    pub fn palette_texture(&self) -> &wgpu::Texture {
+        &self.stage.format.palette_texture
     }
 
-    pub fn set_index(&mut self, x: u32, y: u32, index: u8) {
-        if x >= self.width || y >= self.height {
+    pub fn palette_view(&self) -> &wgpu::TextureView {
+        &self.stage.format.palette_view
+    }
+
+    /// Also look the indices up in the palette on the GPU, into an `Rgba8UnormSrgb` texture of
+    /// the buffer size (see `resolved_texture`), whenever either changes.
+    ///
+    /// A glow `Background` samples this texture, so it keeps the resolve enabled. The resolved
+    /// texture never shows overlays.
+    pub fn set_palette_resolve(&mut self, enabled: bool) {
+        let stage = &mut self.stage;
+        let enabled = enabled
+            || stage
+                .background
+                .as_ref()
+                .is_some_and(BackgroundLayer::samples_frame);
+        if enabled == stage.format.resolve.is_some() {
             return;
         }
-        let offset = (y * self.width + x) as usize;
-        self.indices[offset] = index;
-        self.dirty.mark_point(x, y);
+        stage.format.resolve = enabled.then(|| {
+            stage
+                .format
+                .create_resolve(&stage.gpu, stage.width, stage.height)
+        });
     }
 
-    /// Mark a region as dirty (useful if you modify `frame_mut()` partially).
-    pub fn mark_dirty(&mut self, rect: Rect) {
-        self.dirty.mark_rect(rect);
+    /// The palette-resolved RGBA texture, if `set_palette_resolve` is enabled.
+    ///
+    /// Like `index_texture`, it is current once the frame's encoder has been submitted.
+    pub fn resolved_texture(&self) -> Option<&wgpu::Texture> {
+        self.stage
+            .format
+            .resolve
+            .as_ref()
+            .map(|resolve| &resolve.texture)
     }
 
-    pub fn palette(&self) -> &[[u8; 4]; 256] {
-        &self.palette
+    pub fn resolved_view(&self) -> Option<&wgpu::TextureView> {
+        self.stage
+            .format
+            .resolve
+            .as_ref()
+            .map(|resolve| &resolve.view)
     }
 
-    pub fn palette_mut(&mut self) -> &mut [[u8; 4]; 256] {
-        self.palette_dirty = true;
-        &mut self.palette
+    /// Fill the area around the image with palette entry `border_index` (following palette
+    /// changes) instead of `clear_color` in `render`, like the border of a C64 or ZX Spectrum.
+    pub fn set_border_index(&mut self, border_index: Option<u8>) {
+        self.stage.format.border_index = border_index;
+        self.stage.border_color = None;
     }
 
-    pub fn set_palette_entry(&mut self, index: u8, color: [u8; 4]) {
-        self.palette[index as usize] = color;
-        self.palette_dirty = true;
+    /// Replace the indices and the leading palette entries with `image`, resizing the buffer to
+    /// match if needed.
+    pub fn load_image(&mut self, image: &IndexedImage) -> Result<()> {
+        if image.indices.len() != image.width as usize * image.height as usize {
+            return Err(Error::InvalidImage(
+                "index data does not match image size".to_string(),
+            ));
+        }
+        if (image.width, image.height) != self.buffer_size() {
+            self.resize_buffer(image.width, image.height)?;
+        }
+        let stage = &mut self.stage;
+        stage.pixels.copy_from_slice(&image.indices);
+        for (entry, color) in stage.format.palette.iter_mut().zip(&image.palette) {
+            *entry = *color;
+        }
+        stage.format.palette_dirty = true;
+        stage.dirty.mark_full();
+        Ok(())
     }
 
-    pub fn render(&mut self) -> Result<()> {
-        if self.surface_config.width == 0 || self.surface_config.height == 0 {
-            return Ok(());
+    /// Copy the CPU-side indices and the full 256-entry palette into an `IndexedImage`.
+    pub fn to_image(&self) -> IndexedImage {
+        IndexedImage {
+            width: self.stage.width,
+            height: self.stage.height,
+            indices: self.stage.pixels.clone(),
+            palette: self.stage.format.palette.to_vec(),
         }
+    }
 
-        let frame = self.surface.get_current_texture().or_else(|_| {
-            self.surface.configure(&self.device, &self.surface_config);
-            self.surface.get_current_texture()
-        })?;
+    /// Save the indices and palette to `path` (indexed PNG, 8-bit BMP, or PPM with the palette
+    /// resolved), choosing the format from the extension.
+    pub fn save_image(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        self.to_image().save(path)
+    }
 
-        if self.palette_dirty {
-            self.upload_palette();
-        }
-        self.upload_dirty_regions();
-
-        let view = frame
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("pixstage_indexed_command_encoder"),
-            });
-
-        {
-            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("pixstage_indexed_render_pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    depth_slice: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(self.clear_color),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            });
-
-            let clip = self.scaling_state.clip_rect;
-            pass.set_scissor_rect(clip.x, clip.y, clip.width, clip.height);
-            pass.set_pipeline(&self.pipeline);
-            pass.set_bind_group(0, &self.bind_group, &[]);
-            pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            pass.draw(0..3, 0..1);
+    pub fn set_index(&mut self, x: u32, y: u32, index: u8) {
+        let stage = &mut self.stage;
+        if x >= stage.width || y >= stage.height {
+            return;
         }
+        let offset = (y * stage.width + x) as usize;
+        stage.pixels[offset] = index;
+        stage.dirty.mark_point(x, y);
+    }
 
-        self.queue.submit(Some(encoder.finish()));
-        frame.present();
-        Ok(())
+    /// Borrow `rect` (clipped to the buffer) as a 2D view; only `rect` is marked dirty on drop.
+    pub fn region_mut(&mut self, rect: Rect) -> RegionMut<'_, u8> {
+        let stage = &mut self.stage;
+        RegionMut::new(
+            &mut stage.pixels,
+            stage.width,
+            stage.height,
+            rect,
+            &mut stage.dirty,
+        )
     }
 
-    fn recompute_scaling(&mut self) {
-        self.scaling_state = compute_scaling(
-            (self.width, self.height),
-            (self.surface_config.width, self.surface_config.height),
-            self.scaling_mode,
+    /// Copy `src_rect` of `src` indices to `(dst_x, dst_y)`, clipped to the buffer.
+    ///
+    /// `options.key` is an index key; `options.blend` is ignored. Only the touched destination
+    /// rect is marked dirty.
+    pub fn blit(
+        &mut self,
+        src: &BlitSource<'_, u8>,
+        src_rect: Rect,
+        dst_x: i32,
+        dst_y: i32,
+        options: BlitOptions<u8>,
+    ) {
+        let stage = &mut self.stage;
+        let dirty = crate::blit::blit(
+            &mut stage.pixels,
+            stage.width,
+            stage.height,
+            src,
+            src_rect,
+            dst_x,
+            dst_y,
+            &options,
+            |_, src| src,
         );
-        let globals = globals_bytes(self.scaling_state.ndc_scale);
-        self.queue
-            .write_buffer(&self.globals_buffer, 0, bytemuck::bytes_of(&globals));
+        if let Some(rect) = dirty {
+            stage.dirty.mark_rect(rect);
+        }
     }
 
-    fn upload_palette(&mut self) {
-        let palette_bytes = bytemuck::cast_slice(&self.palette);
-        self.queue.write_texture(
-            wgpu::TexelCopyTextureInfo {
-                texture: &self.palette_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            palette_bytes,
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(256 * 4),
-                rows_per_image: Some(1),
-            },
-            wgpu::Extent3d {
-                width: 256,
-                height: 1,
-                depth_or_array_layers: 1,
+    /// Draw `text` with `font` at `(x, y)` using palette `index`.
+    ///
+    /// Glyph pixels with at least 50% coverage are written. Only the text bounds are marked dirty.
+    pub fn draw_text(
+        &mut self,
+        font: &BitmapFont,
+        text: &str,
+        x: i32,
+        y: i32,
+        index: u8,
+        options: TextOptions,
+    ) {
+        let stage = &mut self.stage;
+        let dirty = crate::font::draw_text(
+            &mut stage.pixels,
+            stage.width,
+            stage.height,
+            font,
+            text,
+            x,
+            y,
+            &options,
+            |dst, coverage| {
+                if coverage >= 128 {
+                    *dst = index;
+                }
             },
         );
-        self.palette_dirty = false;
+        if let Some(rect) = dirty {
+            stage.dirty.mark_rect(rect);
+        }
     }
 
-    fn upload_dirty_regions(&mut self) {
-        let regions = self.dirty.take_regions(64);
-        if regions.is_empty() {
-            return;
-        }
+    pub fn palette(&self) -> &[[u8; 4]; 256] {
+        &self.stage.format.palette
+    }
 
-        for region in regions {
-            if region.x == 0
-                && region.y == 0
-                && region.width == self.width
-                && region.height == self.height
-            {
-                self.queue.write_texture(
-                    wgpu::TexelCopyTextureInfo {
-                        texture: &self.index_texture,
-                        mip_level: 0,
-                        origin: wgpu::Origin3d::ZERO,
-                        aspect: wgpu::TextureAspect::All,
-                    },
-                    &self.indices,
-                    wgpu::TexelCopyBufferLayout {
-                        offset: 0,
-                        bytes_per_row: Some(self.width),
-                        rows_per_image: Some(self.height),
-                    },
-                    wgpu::Extent3d {
-                        width: self.width,
-                        height: self.height,
-                        depth_or_array_layers: 1,
-                    },
-                );
-                continue;
-            }
-
-            let bytes_per_row = region.width as usize;
-            let upload_len = bytes_per_row * region.height as usize;
-            self.upload_buffer.resize(upload_len, 0);
-
-            for row in 0..region.height as usize {
-                let src_y = region.y as usize + row;
-                let src_x = region.x as usize;
-                let src_start = src_y * self.width as usize + src_x;
-                let src_end = src_start + bytes_per_row;
-                let dst_start = row * bytes_per_row;
-                let dst_end = dst_start + bytes_per_row;
-                self.upload_buffer[dst_start..dst_end]
-                    .copy_from_slice(&self.indices[src_start..src_end]);
-            }
-
-            self.queue.write_texture(
-                wgpu::TexelCopyTextureInfo {
-                    texture: &self.index_texture,
-                    mip_level: 0,
-                    origin: wgpu::Origin3d {
-                        x: region.x,
-                        y: region.y,
-                        z: 0,
-                    },
-                    aspect: wgpu::TextureAspect::All,
-                },
-                &self.upload_buffer,
-                wgpu::TexelCopyBufferLayout {
-                    offset: 0,
-                    bytes_per_row: Some(region.width),
-                    rows_per_image: Some(region.height),
-                },
-                wgpu::Extent3d {
-                    width: region.width,
-                    height: region.height,
-                    depth_or_array_layers: 1,
-                },
-            );
-        }
+    pub fn palette_mut(&mut self) -> &mut [[u8; 4]; 256] {
+        self.stage.format.palette_dirty = true;
+        &mut self.stage.format.palette
+    }
+
+    pub fn set_palette_entry(&mut self, index: u8, color: [u8; 4]) {
+        self.stage.format.palette[index as usize] = color;
+        self.stage.format.palette_dirty = true;
     }
 }
 
@@ -640,23 +619,50 @@ fn create_palette_texture(device: &wgpu::Device) -> (wgpu::Texture, wgpu::Textur
     (texture, view)
 }
 
-fn create_fullscreen_triangle(device: &wgpu::Device) -> wgpu::Buffer {
-    let vertex_data: [[f32; 2]; 3] = [[-1.0, -1.0], [3.0, -1.0], [-1.0, 3.0]];
-    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("pixstage_fullscreen_triangle_vertex_buffer"),
-        contents: bytemuck::cast_slice(&vertex_data),
-        usage: wgpu::BufferUsages::VERTEX,
-    })
+fn srgb_to_linear(value: u8) -> f64 {
+    let value = value as f64 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
 }
 
-fn fullscreen_triangle_layout() -> wgpu::VertexBufferLayout<'static> {
-    wgpu::VertexBufferLayout {
-        array_stride: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
-        step_mode: wgpu::VertexStepMode::Vertex,
-        attributes: &[wgpu::VertexAttribute {
-            offset: 0,
-            shader_location: 0,
-            format: wgpu::VertexFormat::Float32x2,
-        }],
-    }
+#[allow(clippy::too_many_arguments)]
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    index_view: &wgpu::TextureView,
+    sampler: &wgpu::Sampler,
+    palette_view: &wgpu::TextureView,
+    globals_buffer: &wgpu::Buffer,
+    overlays_buffer: &wgpu::Buffer,
+    label: &str,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(label),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(index_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(palette_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: globals_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: overlays_buffer.as_entire_binding(),
+            },
+        ],
+    })
 }
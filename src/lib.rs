@@ -4,25 +4,62 @@
 pub use raw_window_handle;
 pub use wgpu;
 
+mod background;
+mod blit;
+mod capture;
+mod context;
+mod convert;
+mod direct;
 mod dirty;
+mod embed;
 mod options;
+mod overlay;
+mod producer;
 mod rect;
+mod region;
 mod scaling;
+mod stage;
+mod stats;
 mod surface;
+mod upload;
 
 pub mod argb1555;
+#[cfg(feature = "egui")]
+pub mod egui;
+pub mod font;
+pub mod image;
 pub mod indexed;
+pub mod pacing;
+pub mod recorder;
 pub mod rgb565;
 pub mod rgba;
+pub mod sink;
 
 pub use argb1555::PixstageArgb1555;
+pub use background::{Background, BackgroundFit};
+pub use blit::{BlendMode, BlitOptions, BlitSource};
+pub use capture::CaptureSource;
+pub use context::PixstageContext;
+pub use dirty::RegionMerge;
+pub use font::{BitmapFont, TextAlign, TextOptions};
 pub use indexed::PixstageIndexed;
 pub use options::PixstageOptions;
+pub use overlay::{Checkerboard, Crosshair, Overlays, PixelGrid, Selection, TileGrid};
+pub use pacing::{
+    Clock, FramePacer, FrameTick, ManualClock, PacingOptions, PacingStats, PresentModeChoice,
+    SystemClock,
+};
+pub use producer::FrameProducer;
+pub use recorder::{ClipFormat, FrameRecorder, RecorderOptions};
 pub use rect::Rect;
+pub use region::RegionMut;
 pub use rgb565::PixstageRgb565;
 pub use rgba::PixstageRgba;
-pub use scaling::ScalingMode;
+pub use scaling::{PixelUnits, Rotation, ScalingMode, Viewport};
+pub use sink::FrameSink;
+pub use stats::FrameStats;
 pub use surface::SurfaceTexture;
+pub use upload::UploadPath;
 
 /// Pixstage unified error type.
 #[derive(thiserror::Error, Debug)]
@@ -30,10 +67,34 @@ pub use surface::SurfaceTexture;
 pub enum Error {
     #[error("No suitable `wgpu::Adapter` found.")]
     AdapterNotFound,
+    #[error("The shared `wgpu::Adapter` cannot present to this surface.")]
+    IncompatibleSurface,
+    #[error("This stage has no surface to present to; draw it with `render_to` or `draw`.")]
+    NoSurface,
     #[error("Invalid buffer size: {width}x{height}.")]
     InvalidBufferSize { width: u32, height: u32 },
     #[error("Invalid surface size: {width}x{height}.")]
     InvalidSurfaceSize { width: u32, height: u32 },
+    #[error("Invalid font data: {0}")]
+    InvalidFont(String),
+    #[error("Unsupported image: {0}")]
+    UnsupportedImage(String),
+    #[error("Invalid image data: {0}")]
+    InvalidImage(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[cfg(feature = "png")]
+    #[error(transparent)]
+    PngDecode(#[from] png::DecodingError),
+    #[cfg(feature = "png")]
+    #[error(transparent)]
+    PngEncode(#[from] png::EncodingError),
+    #[cfg(feature = "gif")]
+    #[error(transparent)]
+    GifDecode(#[from] gif::DecodingError),
+    #[cfg(feature = "gif")]
+    #[error(transparent)]
+    GifEncode(#[from] gif::EncodingError),
     #[error(transparent)]
     CreateSurface(#[from] wgpu::CreateSurfaceError),
     #[error(transparent)]
@@ -1,4 +1,4 @@
-use crate::ScalingMode;
+use crate::{PixelUnits, RegionMerge, Rotation, ScalingMode, UploadPath, Viewport};
 
 /// Options shared by all Pixstage variants.
 #[derive(Debug, Copy, Clone)]
@@ -6,7 +6,24 @@ pub struct PixstageOptions {
     pub backends: wgpu::Backends,
     pub present_mode: wgpu::PresentMode,
     pub scaling_mode: ScalingMode,
+    /// Whether `ScalingMode::PixelPerfect` picks whole physical or logical pixel factors.
+    pub pixel_units: PixelUnits,
+    /// Screen orientation, e.g. `Rotation::Deg90` for vertical arcade monitors.
+    pub rotation: Rotation,
+    /// Mirror the buffer horizontally (before `rotation`).
+    pub flip_x: bool,
+    /// Mirror the buffer vertically (before `rotation`).
+    pub flip_y: bool,
     pub clear_color: wgpu::Color,
+    /// Edge length of the dirty-tracking tiles, in pixels (the starting size under
+    /// `RegionMerge::Adaptive`).
+    pub dirty_tile_size: u32,
+    /// Upper bound on texture uploads per frame.
+    pub max_dirty_regions: usize,
+    pub region_merge: RegionMerge,
+    /// Measure the render pass with GPU timestamp queries (see `FrameStats::gpu_time`).
+    pub gpu_timing: bool,
+    pub upload_path: UploadPath,
 }
 
 impl Default for PixstageOptions {
@@ -15,7 +32,16 @@ impl Default for PixstageOptions {
             backends: wgpu::Backends::from_env().unwrap_or_else(wgpu::Backends::all),
             present_mode: wgpu::PresentMode::AutoVsync,
             scaling_mode: ScalingMode::PixelPerfect,
+            pixel_units: PixelUnits::Physical,
+            rotation: Rotation::Deg0,
+            flip_x: false,
+            flip_y: false,
             clear_color: wgpu::Color::BLACK,
+            dirty_tile_size: 32,
+            max_dirty_regions: 64,
+            region_merge: RegionMerge::BoundingUnion,
+            gpu_timing: false,
+            upload_path: UploadPath::WriteTexture,
         }
     }
 }
@@ -36,8 +62,61 @@ impl PixstageOptions {
         self
     }
 
+    pub fn with_pixel_units(mut self, pixel_units: PixelUnits) -> Self {
+        self.pixel_units = pixel_units;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_flip_x(mut self, flip_x: bool) -> Self {
+        self.flip_x = flip_x;
+        self
+    }
+
+    pub fn with_flip_y(mut self, flip_y: bool) -> Self {
+        self.flip_y = flip_y;
+        self
+    }
+
     pub fn with_clear_color(mut self, clear_color: wgpu::Color) -> Self {
         self.clear_color = clear_color;
         self
     }
+
+    pub fn with_dirty_tile_size(mut self, dirty_tile_size: u32) -> Self {
+        self.dirty_tile_size = dirty_tile_size;
+        self
+    }
+
+    pub fn with_max_dirty_regions(mut self, max_dirty_regions: usize) -> Self {
+        self.max_dirty_regions = max_dirty_regions;
+        self
+    }
+
+    pub fn with_region_merge(mut self, region_merge: RegionMerge) -> Self {
+        self.region_merge = region_merge;
+        self
+    }
+
+    pub fn with_gpu_timing(mut self, gpu_timing: bool) -> Self {
+        self.gpu_timing = gpu_timing;
+        self
+    }
+
+    pub fn with_upload_path(mut self, upload_path: UploadPath) -> Self {
+        self.upload_path = upload_path;
+        self
+    }
+
+    /// The viewport a new stage starts with.
+    pub(crate) fn viewport(&self) -> Viewport {
+        Viewport::default()
+            .with_rotation(self.rotation)
+            .with_flip_x(self.flip_x)
+            .with_flip_y(self.flip_y)
+    }
 }
@@ -0,0 +1,221 @@
+use crate::Rect;
+
+/// Guides drawn by the GPU on top of the scaled buffer (see `set_overlays`).
+///
+/// Overlays never touch the pixel data or dirty state; changing them only updates a uniform.
+/// Colors are linear, like `clear_color`.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Overlays {
+    pub pixel_grid: Option<PixelGrid>,
+    pub checkerboard: Option<Checkerboard>,
+    pub tile_grid: Option<TileGrid>,
+    pub crosshair: Option<Crosshair>,
+    pub selection: Option<Selection>,
+}
+
+impl Overlays {
+    pub fn with_pixel_grid(mut self, pixel_grid: PixelGrid) -> Self {
+        self.pixel_grid = Some(pixel_grid);
+        self
+    }
+
+    pub fn with_checkerboard(mut self, checkerboard: Checkerboard) -> Self {
+        self.checkerboard = Some(checkerboard);
+        self
+    }
+
+    pub fn with_tile_grid(mut self, tile_grid: TileGrid) -> Self {
+        self.tile_grid = Some(tile_grid);
+        self
+    }
+
+    pub fn with_crosshair(mut self, crosshair: Crosshair) -> Self {
+        self.crosshair = Some(crosshair);
+        self
+    }
+
+    pub fn with_selection(mut self, selection: Selection) -> Self {
+        self.selection = Some(selection);
+        self
+    }
+}
+
+/// One-pixel lines between buffer pixels, shown once they are at least `min_scale` surface
+/// pixels wide.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PixelGrid {
+    pub color: wgpu::Color,
+    pub min_scale: f32,
+}
+
+impl Default for PixelGrid {
+    fn default() -> Self {
+        Self {
+            color: wgpu::Color {
+                r: 0.0,
+                g: 0.0,
+                b: 0.0,
+                a: 0.25,
+            },
+            min_scale: 8.0,
+        }
+    }
+}
+
+/// A checkerboard behind transparent pixels (RGBA alpha below 255, ARGB1555 alpha 0, palette
+/// entries with alpha below 255), in squares of `cell_size` surface pixels.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Checkerboard {
+    pub colors: [wgpu::Color; 2],
+    pub cell_size: u32,
+}
+
+impl Default for Checkerboard {
+    fn default() -> Self {
+        let gray = |level| wgpu::Color {
+            r: level,
+            g: level,
+            b: level,
+            a: 1.0,
+        };
+        Self {
+            colors: [gray(0.35), gray(0.6)],
+            cell_size: 8,
+        }
+    }
+}
+
+/// Lines every `tile_width` x `tile_height` buffer pixels, starting at `offset`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TileGrid {
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub offset: (u32, u32),
+    pub color: wgpu::Color,
+}
+
+impl Default for TileGrid {
+    fn default() -> Self {
+        Self {
+            tile_width: 8,
+            tile_height: 8,
+            offset: (0, 0),
+            color: wgpu::Color {
+                r: 0.0,
+                g: 0.5,
+                b: 1.0,
+                a: 0.6,
+            },
+        }
+    }
+}
+
+/// Horizontal and vertical lines through the center of buffer pixel (`x`, `y`).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Crosshair {
+    pub x: u32,
+    pub y: u32,
+    pub color: wgpu::Color,
+}
+
+impl Crosshair {
+    pub fn new(x: u32, y: u32) -> Self {
+        Self {
+            x,
+            y,
+            color: wgpu::Color {
+                r: 1.0,
+                g: 0.0,
+                b: 0.0,
+                a: 0.8,
+            },
+        }
+    }
+}
+
+/// An outlined (and optionally tinted) rect in buffer pixels.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Selection {
+    pub rect: Rect,
+    pub outline: wgpu::Color,
+    pub fill: Option<wgpu::Color>,
+}
+
+impl Selection {
+    pub fn new(rect: Rect) -> Self {
+        Self {
+            rect,
+            outline: wgpu::Color {
+                r: 1.0,
+                g: 1.0,
+                b: 1.0,
+                a: 0.9,
+            },
+            fill: None,
+        }
+    }
+}
+
+/// The shaders' `Overlays` uniform.
+pub(crate) type OverlayUniform = [[f32; 4]; 13];
+
+/// Pack `overlays` for a `buffer_size` buffer into the shaders' `Overlays` uniform.
+pub(crate) fn overlay_bytes(overlays: &Overlays, buffer_size: (u32, u32)) -> OverlayUniform {
+    let color = |color: wgpu::Color| {
+        [
+            color.r as f32,
+            color.g as f32,
+            color.b as f32,
+            color.a as f32,
+        ]
+    };
+    let enabled = |on: bool| if on { 1.0 } else { 0.0 };
+    let transparent = wgpu::Color::TRANSPARENT;
+
+    let pixel_grid = overlays.pixel_grid.unwrap_or_default();
+    let checkerboard = overlays.checkerboard.unwrap_or_default();
+    let tile_grid = overlays.tile_grid.unwrap_or_default();
+    let crosshair = overlays.crosshair.unwrap_or(Crosshair::new(0, 0));
+    let selection = overlays.selection.unwrap_or(Selection::new(Rect {
+        x: 0,
+        y: 0,
+        width: 0,
+        height: 0,
+    }));
+
+    [
+        [
+            buffer_size.0 as f32,
+            buffer_size.1 as f32,
+            checkerboard.cell_size.max(1) as f32,
+            pixel_grid.min_scale,
+        ],
+        [
+            enabled(overlays.pixel_grid.is_some()),
+            enabled(overlays.checkerboard.is_some()),
+            enabled(overlays.tile_grid.is_some()),
+            enabled(overlays.crosshair.is_some()),
+        ],
+        [enabled(overlays.selection.is_some()), 0.0, 0.0, 0.0],
+        color(pixel_grid.color),
+        color(checkerboard.colors[0]),
+        color(checkerboard.colors[1]),
+        [
+            tile_grid.tile_width.max(1) as f32,
+            tile_grid.tile_height.max(1) as f32,
+            tile_grid.offset.0 as f32,
+            tile_grid.offset.1 as f32,
+        ],
+        color(tile_grid.color),
+        [crosshair.x as f32 + 0.5, crosshair.y as f32 + 0.5, 0.0, 0.0],
+        color(crosshair.color),
+        [
+            selection.rect.x as f32,
+            selection.rect.y as f32,
+            selection.rect.width as f32,
+            selection.rect.height as f32,
+        ],
+        color(selection.outline),
+        color(selection.fill.unwrap_or(transparent)),
+    ]
+}
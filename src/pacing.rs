@@ -0,0 +1,667 @@
+//! Frame pacing for presenting at an emulated refresh rate.
+//!
+//! `FramePacer` decides, on every redraw, how many emulated frames are due at the target rate
+//! (e.g. 59.73 Hz, or 50 Hz for PAL) and whether to present: when the display is faster than
+//! the target a frame is shown again (doubled), when it is slower the extra frames are run
+//! without presenting (skipped). It reads time from a `Clock`, so the pacing logic can be
+//! driven by a `ManualClock` in tests.
+//!
+//! With `PresentMode::Fifo` the display's vsync sets the redraw rate and the pacer only picks
+//! skips and doubles. With a variable refresh rate display, `PresentMode::Immediate` or
+//! `Mailbox` (see `set_present_mode` on the stages) plus `FramePacer::wait` lets the target
+//! rate drive the presents instead.
+
+use crate::Result;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A monotonic time source for `FramePacer`.
+pub trait Clock {
+    /// Time since an arbitrary fixed origin. Must never go backwards.
+    fn now(&self) -> Duration;
+
+    /// Block the current thread for `duration`.
+    fn sleep(&self, duration: Duration);
+}
+
+/// The system's monotonic clock.
+///
+/// `sleep` blocks the thread on native targets and does nothing on the web, where the
+/// browser's animation frames pace the redraws.
+#[derive(Debug, Copy, Clone)]
+pub struct SystemClock {
+    origin: web_time::Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            origin: web_time::Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::sleep(duration);
+        #[cfg(target_arch = "wasm32")]
+        let _ = duration;
+    }
+}
+
+/// A clock that only moves when told to, for testing pacing logic.
+///
+/// Clones share the same time, so a test can keep one while the pacer owns another. `sleep`
+/// advances the time instead of blocking.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Jump to `now`; ignored if that would move the clock backwards.
+    pub fn set(&self, now: Duration) {
+        self.nanos
+            .fetch_max(now.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// Options for `FramePacer`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PacingOptions {
+    /// Emulated frames per second, e.g. `59.73` (Game Boy) or `50.0` (PAL).
+    pub target_fps: f64,
+    /// Most emulated frames run without presenting in one tick when falling behind; frames
+    /// beyond that are dropped and the schedule restarts (e.g. after a stall or a breakpoint).
+    pub max_frame_skip: u32,
+    /// Present the previous frame again when a tick comes before the next frame is due.
+    /// Without it such ticks don't present at all.
+    pub frame_doubling: bool,
+    /// A frame counts as due this much before its deadline, absorbing redraw jitter.
+    pub tolerance: Duration,
+    /// Number of present intervals kept for `present_intervals`.
+    pub interval_history: usize,
+}
+
+impl Default for PacingOptions {
+    fn default() -> Self {
+        Self {
+            target_fps: 60.0,
+            max_frame_skip: 4,
+            frame_doubling: true,
+            tolerance: Duration::from_millis(1),
+            interval_history: 120,
+        }
+    }
+}
+
+impl PacingOptions {
+    /// Invalid rates (not finite or not positive) are ignored.
+    pub fn with_target_fps(mut self, target_fps: f64) -> Self {
+        if target_fps.is_finite() && target_fps > 0.0 {
+            self.target_fps = target_fps;
+        }
+        self
+    }
+
+    pub fn with_max_frame_skip(mut self, max_frame_skip: u32) -> Self {
+        self.max_frame_skip = max_frame_skip;
+        self
+    }
+
+    pub fn with_frame_doubling(mut self, frame_doubling: bool) -> Self {
+        self.frame_doubling = frame_doubling;
+        self
+    }
+
+    pub fn with_tolerance(mut self, tolerance: Duration) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn with_interval_history(mut self, interval_history: usize) -> Self {
+        self.interval_history = interval_history;
+        self
+    }
+
+    /// Duration of one emulated frame (60 Hz if `target_fps` is invalid).
+    pub fn frame_period(&self) -> Duration {
+        if self.target_fps.is_finite() && self.target_fps > 0.0 {
+            Duration::from_secs_f64(1.0 / self.target_fps)
+        } else {
+            Duration::from_secs_f64(1.0 / 60.0)
+        }
+    }
+}
+
+/// What to do on one redraw, from `FramePacer::tick`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FrameTick {
+    /// Emulated frames to run before presenting; `0` when none is due yet.
+    pub frames: u32,
+    /// Whether to render and present this tick (see `FramePacer::present`).
+    pub present: bool,
+    /// Time until the next emulated frame is due.
+    pub wait: Duration,
+}
+
+impl FrameTick {
+    /// Frames run this tick without being presented.
+    pub fn skipped(&self) -> u32 {
+        if self.present {
+            self.frames.saturating_sub(1)
+        } else {
+            self.frames
+        }
+    }
+
+    /// Whether the previous frame is presented again.
+    pub fn is_doubled(&self) -> bool {
+        self.present && self.frames == 0
+    }
+}
+
+/// Running totals kept by `FramePacer`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PacingStats {
+    /// Emulated frames handed out by `tick`.
+    pub frames_run: u64,
+    /// Emulated frames run without being presented.
+    pub frames_skipped: u64,
+    /// Ticks that present the previous frame again.
+    pub frames_doubled: u64,
+    /// Frames given up beyond `max_frame_skip` when the schedule restarted.
+    pub frames_dropped: u64,
+    /// Successful presents recorded by `present` or `record_present`.
+    pub presents: u64,
+}
+
+/// Schedules emulated frames at a target rate and measures the actual presents.
+///
+/// ```no_run
+/// # fn run(stage: &mut pixstage::PixstageRgba<'_>) -> pixstage::Result<()> {
+/// use pixstage::pacing::{FramePacer, PacingOptions};
+///
+/// let mut pacer = FramePacer::new(PacingOptions::default().with_target_fps(50.0));
+/// // On every redraw:
+/// let tick = pacer.tick();
+/// for _ in 0..tick.frames {
+///     // Run one emulated frame into the stage's buffer.
+/// }
+/// if tick.present {
+///     pacer.present(|| stage.render())?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct FramePacer<C: Clock = SystemClock> {
+    clock: C,
+    options: PacingOptions,
+    period: Duration,
+    next_frame: Option<Duration>,
+    last_present: Option<Duration>,
+    intervals: VecDeque<Duration>,
+    stats: PacingStats,
+}
+
+impl FramePacer {
+    /// Create a pacer on the system clock.
+    pub fn new(options: PacingOptions) -> Self {
+        Self::with_clock(options, SystemClock::new())
+    }
+}
+
+impl<C: Clock> FramePacer<C> {
+    /// Create a pacer reading time from `clock`, e.g. a `ManualClock`.
+    pub fn with_clock(options: PacingOptions, clock: C) -> Self {
+        Self {
+            clock,
+            period: options.frame_period(),
+            options,
+            next_frame: None,
+            last_present: None,
+            intervals: VecDeque::with_capacity(options.interval_history),
+            stats: PacingStats::default(),
+        }
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    pub fn options(&self) -> &PacingOptions {
+        &self.options
+    }
+
+    /// Replace the options; a new target rate applies from the next frame on.
+    pub fn set_options(&mut self, options: PacingOptions) {
+        self.period = options.frame_period();
+        self.options = options;
+        while self.intervals.len() > self.options.interval_history {
+            self.intervals.pop_front();
+        }
+    }
+
+    /// Change the target rate (e.g. when the emulated machine switches between NTSC and PAL).
+    /// Invalid rates are ignored.
+    pub fn set_target_fps(&mut self, target_fps: f64) {
+        self.set_options(self.options.with_target_fps(target_fps));
+    }
+
+    /// Duration of one emulated frame.
+    pub fn frame_period(&self) -> Duration {
+        self.period
+    }
+
+    /// Decide what to do on this redraw. Call once per redraw, before running the emulator.
+    ///
+    /// The first tick after creation or `reset` runs one frame and starts the schedule.
+    pub fn tick(&mut self) -> FrameTick {
+        let now = self.clock.now();
+        let deadline = *self.next_frame.get_or_insert(now);
+
+        if now + self.options.tolerance < deadline {
+            let present = self.options.frame_doubling;
+            if present {
+                self.stats.frames_doubled += 1;
+            }
+            return FrameTick {
+                frames: 0,
+                present,
+                wait: deadline - now,
+            };
+        }
+
+        let behind = now.saturating_sub(deadline);
+        let due = (behind.as_nanos() / self.period.as_nanos().max(1)) as u64 + 1;
+        let max_frames = self.options.max_frame_skip as u64 + 1;
+        let frames = if due > max_frames {
+            self.stats.frames_dropped += due - max_frames;
+            self.next_frame = Some(now + self.period);
+            max_frames
+        } else {
+            self.next_frame = Some(deadline + self.period.mul_f64(due as f64));
+            due
+        };
+
+        self.stats.frames_run += frames;
+        self.stats.frames_skipped += frames - 1;
+        FrameTick {
+            frames: frames as u32,
+            present: true,
+            wait: self.time_until_next_frame(),
+        }
+    }
+
+    /// Time until the next emulated frame is due (zero if it already is).
+    pub fn time_until_next_frame(&self) -> Duration {
+        self.next_frame
+            .map_or(Duration::ZERO, |next| next.saturating_sub(self.clock.now()))
+    }
+
+    /// Sleep on the clock until the next emulated frame is due, for loops that are not paced
+    /// by vsync (`PresentMode::Immediate`/`Mailbox`).
+    pub fn wait(&self) {
+        let wait = self.time_until_next_frame();
+        if !wait.is_zero() {
+            self.clock.sleep(wait);
+        }
+    }
+
+    /// Restart the schedule, e.g. after pausing, so the time away is not caught up.
+    pub fn reset(&mut self) {
+        self.next_frame = None;
+        self.last_present = None;
+    }
+
+    /// Run `render` (usually a stage's `render()`) and record the present if it succeeds.
+    pub fn present<T>(&mut self, render: impl FnOnce() -> Result<T>) -> Result<T> {
+        let output = render()?;
+        self.record_present();
+        Ok(output)
+    }
+
+    /// Record a present made without `present`, e.g. through `render_to` and your own
+    /// surface.
+    pub fn record_present(&mut self) {
+        let now = self.clock.now();
+        if let Some(last) = self.last_present {
+            if self.options.interval_history > 0 {
+                if self.intervals.len() == self.options.interval_history {
+                    self.intervals.pop_front();
+                }
+                self.intervals.push_back(now - last);
+            }
+        }
+        self.last_present = Some(now);
+        self.stats.presents += 1;
+    }
+
+    /// The most recent intervals between presents, oldest first.
+    ///
+    /// With a blocking present mode (`Fifo`), `render()` waits for a free swapchain image, so
+    /// these track the display's actual refresh.
+    pub fn present_intervals(&self) -> impl ExactSizeIterator<Item = Duration> + '_ {
+        self.intervals.iter().copied()
+    }
+
+    pub fn last_present_interval(&self) -> Option<Duration> {
+        self.intervals.back().copied()
+    }
+
+    /// Mean of `present_intervals`.
+    pub fn average_present_interval(&self) -> Option<Duration> {
+        if self.intervals.is_empty() {
+            return None;
+        }
+        let total: Duration = self.intervals.iter().sum();
+        Some(total / self.intervals.len() as u32)
+    }
+
+    /// Presents per second, from `average_present_interval`.
+    pub fn measured_fps(&self) -> Option<f64> {
+        self.average_present_interval()
+            .filter(|interval| !interval.is_zero())
+            .map(|interval| 1.0 / interval.as_secs_f64())
+    }
+
+    pub fn stats(&self) -> PacingStats {
+        self.stats
+    }
+}
+
+/// The present mode a stage asked for and the one its surface was configured with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PresentModeChoice {
+    pub requested: wgpu::PresentMode,
+    pub chosen: wgpu::PresentMode,
+}
+
+impl PresentModeChoice {
+    /// Pick `requested` if the surface supports it, otherwise the closest supported mode:
+    /// `Immediate` falls back to `Mailbox`, and anything else to `Fifo`, which every surface
+    /// supports. The `Auto*` modes are always accepted, since wgpu resolves them itself.
+    pub fn negotiate(requested: wgpu::PresentMode, supported: &[wgpu::PresentMode]) -> Self {
+        use wgpu::PresentMode;
+
+        let fallbacks: &[PresentMode] = match requested {
+            PresentMode::AutoVsync | PresentMode::AutoNoVsync => &[requested],
+            PresentMode::Immediate => &[PresentMode::Immediate, PresentMode::Mailbox],
+            _ => &[requested],
+        };
+        let chosen = fallbacks
+            .iter()
+            .copied()
+            .find(|mode| {
+                matches!(mode, PresentMode::AutoVsync | PresentMode::AutoNoVsync)
+                    || supported.contains(mode)
+            })
+            .unwrap_or(PresentMode::Fifo);
+        let choice = Self { requested, chosen };
+        if choice.fell_back() {
+            log::warn!("Present mode {requested:?} is not supported, using {chosen:?} instead");
+        }
+        choice
+    }
+
+    /// Whether the surface could not use the requested mode.
+    pub fn fell_back(&self) -> bool {
+        self.requested != self.chosen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+    use wgpu::PresentMode;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    /// A 50 FPS pacer (20 ms frames) on a manual clock, after its first tick at time zero.
+    fn pacer(options: PacingOptions) -> (FramePacer<ManualClock>, ManualClock) {
+        let clock = ManualClock::new();
+        let mut pacer = FramePacer::with_clock(options.with_target_fps(50.0), clock.clone());
+        let first = pacer.tick();
+        assert_eq!((first.frames, first.present), (1, true));
+        (pacer, clock)
+    }
+
+    #[test]
+    fn frames_on_schedule_run_one_at_a_time() {
+        let (mut pacer, clock) = pacer(PacingOptions::default());
+        assert_eq!(pacer.frame_period(), 20 * MS);
+        for _ in 0..3 {
+            clock.advance(20 * MS);
+            let tick = pacer.tick();
+            assert_eq!(
+                tick,
+                FrameTick {
+                    frames: 1,
+                    present: true,
+                    wait: 20 * MS,
+                }
+            );
+            assert_eq!((tick.skipped(), tick.is_doubled()), (0, false));
+        }
+        // Within the tolerance of the deadline still counts as due.
+        clock.advance(19 * MS + MS / 2);
+        assert_eq!(pacer.tick().frames, 1);
+        assert_eq!(pacer.stats().frames_run, 5);
+    }
+
+    #[test]
+    fn early_ticks_double_the_previous_frame() {
+        let (mut pacer, clock) = pacer(PacingOptions::default());
+        clock.advance(10 * MS);
+        let tick = pacer.tick();
+        assert_eq!(
+            tick,
+            FrameTick {
+                frames: 0,
+                present: true,
+                wait: 10 * MS,
+            }
+        );
+        assert!(tick.is_doubled());
+        assert_eq!(pacer.stats().frames_doubled, 1);
+
+        // The schedule is unchanged: the next frame is still due at 20 ms.
+        clock.advance(10 * MS);
+        assert_eq!(pacer.tick().frames, 1);
+    }
+
+    #[test]
+    fn early_ticks_without_doubling_do_not_present() {
+        let (mut pacer, clock) = pacer(PacingOptions::default().with_frame_doubling(false));
+        clock.advance(10 * MS);
+        let tick = pacer.tick();
+        assert_eq!((tick.frames, tick.present), (0, false));
+        assert!(!tick.is_doubled());
+        assert_eq!(pacer.stats().frames_doubled, 0);
+    }
+
+    #[test]
+    fn late_ticks_skip_up_to_max_frame_skip() {
+        let (mut pacer, clock) = pacer(PacingOptions::default().with_max_frame_skip(2));
+        // Three frames are due (at 20, 40 and 60 ms): run them all, present the last.
+        clock.advance(60 * MS);
+        let tick = pacer.tick();
+        assert_eq!(
+            tick,
+            FrameTick {
+                frames: 3,
+                present: true,
+                wait: 20 * MS,
+            }
+        );
+        assert_eq!(tick.skipped(), 2);
+        let stats = pacer.stats();
+        assert_eq!((stats.frames_skipped, stats.frames_dropped), (2, 0));
+    }
+
+    #[test]
+    fn frames_beyond_max_frame_skip_are_dropped_and_the_schedule_restarts() {
+        let (mut pacer, clock) = pacer(PacingOptions::default().with_max_frame_skip(2));
+        // A 200 ms stall: ten frames are due, three run and seven are dropped.
+        clock.advance(200 * MS);
+        let tick = pacer.tick();
+        assert_eq!((tick.frames, tick.skipped()), (3, 2));
+        assert_eq!(tick.wait, 20 * MS);
+        let stats = pacer.stats();
+        assert_eq!(
+            (stats.frames_run, stats.frames_skipped, stats.frames_dropped),
+            (4, 2, 7)
+        );
+
+        // The schedule restarted from the stall instead of catching up.
+        clock.advance(20 * MS);
+        assert_eq!(pacer.tick().frames, 1);
+    }
+
+    #[test]
+    fn reset_forgets_the_time_away() {
+        let (mut pacer, clock) = pacer(PacingOptions::default());
+        pacer.record_present();
+        clock.advance(Duration::from_secs(5));
+
+        pacer.reset();
+        let tick = pacer.tick();
+        assert_eq!((tick.frames, tick.present), (1, true));
+        assert_eq!(pacer.stats().frames_dropped, 0);
+
+        // The pause is not counted as a present interval either.
+        pacer.record_present();
+        assert_eq!(pacer.present_intervals().len(), 0);
+    }
+
+    #[test]
+    fn wait_sleeps_until_the_next_frame() {
+        let (mut pacer, clock) = pacer(PacingOptions::default());
+        clock.advance(5 * MS);
+        assert_eq!(pacer.time_until_next_frame(), 15 * MS);
+        pacer.wait();
+        assert_eq!(clock.now(), 20 * MS);
+        assert_eq!(pacer.time_until_next_frame(), Duration::ZERO);
+        assert_eq!(pacer.tick().frames, 1);
+    }
+
+    #[test]
+    fn present_intervals_and_measured_fps() {
+        let (mut pacer, clock) = pacer(PacingOptions::default().with_interval_history(3));
+        assert_eq!(pacer.measured_fps(), None);
+
+        for interval in [10, 20, 20, 20] {
+            pacer.present(|| Ok(())).unwrap();
+            clock.advance(interval * MS);
+        }
+        pacer.present(|| Ok(())).unwrap();
+
+        // Only the last three intervals are kept.
+        assert_eq!(
+            pacer.present_intervals().collect::<Vec<_>>(),
+            vec![20 * MS; 3]
+        );
+        assert_eq!(pacer.last_present_interval(), Some(20 * MS));
+        assert_eq!(pacer.average_present_interval(), Some(20 * MS));
+        assert_eq!(pacer.measured_fps(), Some(50.0));
+        assert_eq!(pacer.stats().presents, 5);
+
+        // A failed render is not a present.
+        clock.advance(20 * MS);
+        assert!(pacer.present(|| Err::<(), _>(Error::NoSurface)).is_err());
+        assert_eq!(pacer.stats().presents, 5);
+    }
+
+    #[test]
+    fn target_fps_changes_the_period_and_ignores_invalid_rates() {
+        let (mut pacer, _clock) = pacer(PacingOptions::default());
+        pacer.set_target_fps(25.0);
+        assert_eq!(pacer.frame_period(), 40 * MS);
+        pacer.set_target_fps(f64::NAN);
+        pacer.set_target_fps(0.0);
+        assert_eq!(pacer.frame_period(), 40 * MS);
+    }
+
+    #[test]
+    fn present_mode_negotiation_falls_back_to_the_closest_mode() {
+        let all = [
+            PresentMode::Fifo,
+            PresentMode::FifoRelaxed,
+            PresentMode::Mailbox,
+            PresentMode::Immediate,
+        ];
+        let immediate = PresentModeChoice::negotiate(PresentMode::Immediate, &all);
+        assert_eq!(immediate.chosen, PresentMode::Immediate);
+        assert!(!immediate.fell_back());
+
+        let cases = [
+            (
+                PresentMode::Immediate,
+                &[PresentMode::Fifo, PresentMode::Mailbox][..],
+                PresentMode::Mailbox,
+            ),
+            (
+                PresentMode::Immediate,
+                &[PresentMode::Fifo][..],
+                PresentMode::Fifo,
+            ),
+            (
+                PresentMode::Mailbox,
+                &[PresentMode::Fifo, PresentMode::Immediate][..],
+                PresentMode::Fifo,
+            ),
+            (
+                PresentMode::FifoRelaxed,
+                &[PresentMode::Fifo][..],
+                PresentMode::Fifo,
+            ),
+        ];
+        for (requested, supported, chosen) in cases {
+            let choice = PresentModeChoice::negotiate(requested, supported);
+            assert_eq!(choice, PresentModeChoice { requested, chosen });
+            assert!(choice.fell_back());
+        }
+
+        // wgpu resolves the automatic modes itself.
+        for requested in [PresentMode::AutoVsync, PresentMode::AutoNoVsync] {
+            assert_eq!(
+                PresentModeChoice::negotiate(requested, &[]).chosen,
+                requested
+            );
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use crate::Rect;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Pending dirty rects past this count are collapsed into a full upload.
+const MAX_PENDING_RECTS: usize = 4096;
+
+/// Published frames whose dirty rects are remembered for bringing a recycled buffer up to date.
+const HISTORY_LEN: usize = 4;
+
+/// The frame handed from a `FrameProducer` to its stage.
+#[derive(Debug)]
+pub(crate) struct ProducedFrame<P> {
+    pixels: Vec<P>,
+    width: u32,
+    height: u32,
+    dirty: Vec<Rect>,
+    full: bool,
+    fresh: bool,
+    /// Publish count `pixels` holds, `None` if unknown.
+    generation: Option<u64>,
+    /// Publish count the stage's buffer holds, `None` if unknown.
+    stage_generation: Option<u64>,
+}
+
+pub(crate) type FrameSlot<P> = Arc<Mutex<ProducedFrame<P>>>;
+
+/// `Send` handle for filling a stage's frames from another thread.
+///
+/// Obtained from a stage's `producer()`. The producer draws into its own buffer and `publish()`es
+/// it; the stage picks up the latest published frame at `render()`. Frames are triple buffered
+/// (producer, hand-off slot, stage), so the lock is only held for a buffer swap, and the render
+/// thread merely tries it, skipping the pickup if the producer holds it.
+///
+/// Buffer layout matches the stage's `frame_mut()` (e.g. 4 bytes per pixel for `PixstageRgba`).
+///
+/// While a producer is connected it owns the stage's frame contents: anything drawn into the
+/// stage's buffer directly is replaced by the next published frame. Such writes also end up in a
+/// buffer the producer recycles; debug builds panic in `publish()` when they detect one.
+#[derive(Debug)]
+pub struct FrameProducer<P> {
+    slot: FrameSlot<P>,
+    pixels: Vec<P>,
+    spare: Vec<P>,
+    spare_generation: Option<u64>,
+    width: u32,
+    height: u32,
+    elements_per_pixel: usize,
+    dirty: Vec<Rect>,
+    full: bool,
+    generation: u64,
+    /// Dirty rects of the most recent publishes (`None` for a full frame), newest last.
+    history: VecDeque<Option<Vec<Rect>>>,
+}
+
+impl<P: Copy + Default + PartialEq> FrameProducer<P> {
+    pub(crate) fn new(
+        pixels: Vec<P>,
+        width: u32,
+        height: u32,
+        elements_per_pixel: usize,
+    ) -> (Self, FrameSlot<P>) {
+        let slot = Arc::new(Mutex::new(ProducedFrame {
+            pixels: Vec::new(),
+            width,
+            height,
+            dirty: Vec::new(),
+            full: false,
+            fresh: false,
+            generation: None,
+            // The producer starts from a copy of the stage's buffer.
+            stage_generation: Some(0),
+        }));
+        let producer = Self {
+            slot: Arc::clone(&slot),
+            pixels,
+            spare: Vec::new(),
+            spare_generation: None,
+            width,
+            height,
+            elements_per_pixel,
+            dirty: Vec::new(),
+            full: false,
+            generation: 0,
+            history: VecDeque::with_capacity(HISTORY_LEN),
+        };
+        (producer, slot)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn frame(&self) -> &[P] {
+        &self.pixels
+    }
+
+    /// The producer's buffer. Use `mark_dirty` to limit the upload; a frame published without
+    /// any marks is uploaded in full.
+    pub fn frame_mut(&mut self) -> &mut [P] {
+        &mut self.pixels
+    }
+
+    pub fn mark_dirty(&mut self, rect: Rect) {
+        if let Some(rect) = rect.clamp_to(self.width, self.height) {
+            self.dirty.push(rect);
+        }
+    }
+
+    /// Mark the whole frame dirty.
+    pub fn mark_full(&mut self) {
+        self.full = true;
+    }
+
+    /// Change the frame size; the stage resizes its buffer when it picks up the next frame.
+    ///
+    /// Returns `false` (and keeps the old size) if `width` or `height` is zero.
+    pub fn resize(&mut self, width: u32, height: u32) -> bool {
+        if width == 0 || height == 0 {
+            return false;
+        }
+        self.width = width;
+        self.height = height;
+        self.pixels.resize(
+            width as usize * height as usize * self.elements_per_pixel,
+            P::default(),
+        );
+        self.full = true;
+        true
+    }
+
+    /// Hand the current frame to the stage.
+    ///
+    /// If the stage has not picked up the previous frame yet, it is replaced and its dirty
+    /// rects carry over. The producer keeps its buffer contents for the next frame; only the
+    /// regions dirtied since a recycled buffer was last published are copied into it.
+    pub fn publish(&mut self) {
+        let full = self.full || self.dirty.is_empty();
+        self.generation += 1;
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back((!full).then(|| self.dirty.clone()));
+        self.sync_spare();
+
+        let mut produced = match self.slot.lock() {
+            Ok(produced) => produced,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        std::mem::swap(&mut produced.pixels, &mut self.spare);
+        self.spare_generation = produced.generation.replace(self.generation);
+        if !produced.fresh {
+            produced.dirty.clear();
+            produced.full = false;
+        }
+        if produced.width != self.width || produced.height != self.height {
+            produced.width = self.width;
+            produced.height = self.height;
+            produced.full = true;
+        }
+        produced.full |= full || produced.dirty.len() + self.dirty.len() > MAX_PENDING_RECTS;
+        if produced.full {
+            produced.dirty.clear();
+        } else {
+            produced.dirty.append(&mut self.dirty);
+        }
+        produced.fresh = true;
+        drop(produced);
+
+        self.dirty.clear();
+        self.full = false;
+    }
+
+    /// Whether the stage is still alive and picking up frames from this producer.
+    pub fn is_connected(&self) -> bool {
+        Arc::strong_count(&self.slot) > 1
+    }
+
+    /// Bring the spare buffer up to date with `pixels`, copying only the rects dirtied since the
+    /// spare's generation when they are all still in the history.
+    fn sync_spare(&mut self) {
+        let behind = self
+            .spare_generation
+            .filter(|_| self.spare.len() == self.pixels.len())
+            .map(|generation| (self.generation - generation) as usize)
+            .filter(|behind| *behind <= self.history.len());
+        let rects: Option<Vec<&Rect>> = behind.and_then(|behind| {
+            self.history
+                .iter()
+                .skip(self.history.len() - behind)
+                .map(|dirty| dirty.as_ref().map(|rects| rects.iter()))
+                .collect::<Option<Vec<_>>>()
+                .map(|rects| rects.into_iter().flatten().collect())
+        });
+
+        match rects {
+            Some(rects) => {
+                let stride = self.width as usize * self.elements_per_pixel;
+                for rect in rects {
+                    let start = rect.x as usize * self.elements_per_pixel;
+                    let len = rect.width as usize * self.elements_per_pixel;
+                    for y in rect.y as usize..(rect.y + rect.height) as usize {
+                        let row = y * stride + start;
+                        self.spare[row..row + len].copy_from_slice(&self.pixels[row..row + len]);
+                    }
+                }
+                debug_assert!(
+                    self.spare == self.pixels,
+                    "a stage's buffer was written directly while its FrameProducer is connected"
+                );
+            }
+            None => {
+                self.spare.clear();
+                self.spare.extend_from_slice(&self.pixels);
+            }
+        }
+    }
+}
+
+/// Take the latest published frame, swapping `front` with it.
+///
+/// `front_modified` says the stage changed `front` since its last pickup (it has dirty marks
+/// pending), so the producer copies the whole frame when it recycles that buffer.
+///
+/// Returns `None` without blocking if nothing new was published or the producer holds the lock.
+pub(crate) fn take_produced<P>(
+    slot: &FrameSlot<P>,
+    front: &mut Vec<P>,
+    front_modified: bool,
+) -> Option<TakenFrame> {
+    let mut produced = slot.try_lock().ok()?;
+    if !produced.fresh {
+        return None;
+    }
+    produced.fresh = false;
+    std::mem::swap(&mut produced.pixels, front);
+    let front_generation = produced.stage_generation.filter(|_| !front_modified);
+    produced.stage_generation = std::mem::replace(&mut produced.generation, front_generation);
+    Some(TakenFrame {
+        width: produced.width,
+        height: produced.height,
+        dirty: std::mem::take(&mut produced.dirty),
+        full: produced.full,
+    })
+}
+
+/// Size and dirty state of a frame taken by `take_produced`.
+pub(crate) struct TakenFrame {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) dirty: Vec<Rect>,
+    pub(crate) full: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dot(x: u32) -> Rect {
+        Rect {
+            x,
+            y: 1,
+            width: 1,
+            height: 1,
+        }
+    }
+
+    /// Publish `frames` single-pixel changes, letting the stage pick up every `pickup`th frame,
+    /// and check every pickup against what was published.
+    fn run(frames: u32, pickup: u32) {
+        let mut front = vec![0u16; 12];
+        let (mut producer, slot) = FrameProducer::new(front.clone(), 4, 3, 1);
+        for frame in 1..=frames {
+            let x = frame % 4;
+            producer.frame_mut()[4 + x as usize] = frame as u16;
+            producer.mark_dirty(dot(x));
+            producer.publish();
+            if frame % pickup == 0 {
+                let taken = take_produced(&slot, &mut front, false).unwrap();
+                assert!(!taken.full);
+                assert_eq!(front, producer.frame(), "frame {frame}");
+            }
+        }
+    }
+
+    #[test]
+    fn publish_recycles_buffers_with_dirty_copies() {
+        run(20, 1);
+        run(20, 2);
+        // The stage falls behind the remembered history.
+        run(40, 7);
+    }
+
+    #[test]
+    fn stage_marked_writes_force_a_full_copy() {
+        let mut front = vec![0u8; 4];
+        let (mut producer, slot) = FrameProducer::new(front.clone(), 2, 2, 1);
+        for frame in 1..=6u8 {
+            producer.frame_mut()[0] = frame;
+            producer.mark_dirty(Rect {
+                x: 0,
+                y: 0,
+                width: 1,
+                height: 1,
+            });
+            producer.publish();
+            take_produced(&slot, &mut front, true).unwrap();
+            assert_eq!(front, producer.frame());
+            front[3] = 99;
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "written directly")]
+    fn unmarked_stage_writes_are_caught_in_debug_builds() {
+        let mut front = vec![0u8; 4];
+        let (mut producer, slot) = FrameProducer::new(front.clone(), 2, 2, 1);
+        for frame in 1..=6u8 {
+            producer.frame_mut()[0] = frame;
+            producer.mark_dirty(Rect {
+                x: 0,
+                y: 0,
+                width: 1,
+                height: 1,
+            });
+            producer.publish();
+            take_produced(&slot, &mut front, false).unwrap();
+            front[3] = 99;
+        }
+    }
+}
@@ -0,0 +1,480 @@
+//! Recording rendered frames into animated GIF or APNG clips.
+//!
+//! A stage with recording enabled (`start_recording`) captures a frame on every `render()`:
+//! by default its CPU-side buffer, or with `CaptureSource::Output` the rendered output read
+//! back from the GPU. `PixstageIndexed` buffer frames keep their indices and palette, so GIF
+//! clips use the stage palette directly; everything else is recorded as RGBA8.
+//!
+//! Once a "last N seconds" recording is full, each new frame reuses the pixel buffer of the
+//! frame it pushes out.
+
+use crate::image::{IndexedImage, RgbaImage};
+use crate::{CaptureSource, Error, Result};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::Duration;
+
+/// Animated clip container formats.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ClipFormat {
+    Gif,
+    Apng,
+}
+
+impl ClipFormat {
+    /// Guess the format from a file extension (`.gif`, `.png` or `.apng`).
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "gif" => Some(ClipFormat::Gif),
+            "png" | "apng" => Some(ClipFormat::Apng),
+            _ => None,
+        }
+    }
+}
+
+/// Options for `FrameRecorder`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RecorderOptions {
+    /// Delay stored with each captured frame.
+    pub frame_delay: Duration,
+    /// Keep only the most recent frames covering this duration ("save the last N seconds").
+    pub max_duration: Option<Duration>,
+    /// What stages capture: the CPU-side buffer or the rendered output.
+    pub source: CaptureSource,
+}
+
+impl Default for RecorderOptions {
+    fn default() -> Self {
+        Self {
+            frame_delay: Duration::from_micros(16_667),
+            max_duration: None,
+            source: CaptureSource::Buffer,
+        }
+    }
+}
+
+impl RecorderOptions {
+    pub fn with_frame_delay(mut self, frame_delay: Duration) -> Self {
+        self.frame_delay = frame_delay;
+        self
+    }
+
+    pub fn with_max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = Some(max_duration);
+        self
+    }
+
+    pub fn with_source(mut self, source: CaptureSource) -> Self {
+        self.source = source;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+enum FrameData {
+    Rgba(RgbaImage),
+    Indexed(IndexedImage),
+}
+
+impl FrameData {
+    fn size(&self) -> (u32, u32) {
+        match self {
+            FrameData::Rgba(image) => (image.width, image.height),
+            FrameData::Indexed(image) => (image.width, image.height),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RecordedFrame {
+    data: FrameData,
+    delay: Duration,
+}
+
+/// Captured frames plus their delays, encodable as animated GIF or APNG.
+///
+/// All frames share one size; pushing a frame with a different size restarts the clip.
+#[derive(Debug, Clone)]
+pub struct FrameRecorder {
+    options: RecorderOptions,
+    frames: VecDeque<RecordedFrame>,
+    duration: Duration,
+    /// The most recently dropped frame, whose buffers the next capture reuses.
+    spare: Option<FrameData>,
+}
+
+impl FrameRecorder {
+    pub fn new(options: RecorderOptions) -> Self {
+        Self {
+            options,
+            frames: VecDeque::new(),
+            duration: Duration::ZERO,
+            spare: None,
+        }
+    }
+
+    /// Change the delay stored with subsequently captured frames.
+    pub fn set_frame_delay(&mut self, frame_delay: Duration) {
+        self.options.frame_delay = frame_delay;
+    }
+
+    pub fn options(&self) -> RecorderOptions {
+        self.options
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Total playback duration of the recorded frames.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    pub fn clear(&mut self) {
+        if let Some(last) = self.frames.pop_back() {
+            self.spare = Some(last.data);
+        }
+        self.frames.clear();
+        self.duration = Duration::ZERO;
+    }
+
+    /// Append an RGBA8 frame with the current frame delay.
+    ///
+    /// Fails with [`Error::InvalidImage`] unless the image holds `width * height * 4` bytes.
+    pub fn push_rgba8(&mut self, image: RgbaImage) -> Result<()> {
+        image.check_size()?;
+        self.push(FrameData::Rgba(image), 1);
+        Ok(())
+    }
+
+    /// Append an indexed frame (indices plus palette) with the current frame delay.
+    ///
+    /// Fails with [`Error::InvalidImage`] unless the image holds `width * height` indices and
+    /// 1 to 256 palette entries.
+    pub fn push_indexed(&mut self, image: IndexedImage) -> Result<()> {
+        image.check_size()?;
+        self.push(FrameData::Indexed(image), 1);
+        Ok(())
+    }
+
+    /// Append an RGBA8 frame written by `fill` into a recycled pixel buffer (cleared first),
+    /// shown for `frames` frame delays.
+    pub(crate) fn capture_rgba8(
+        &mut self,
+        width: u32,
+        height: u32,
+        frames: u32,
+        fill: impl FnOnce(&mut Vec<u8>),
+    ) {
+        let mut pixels = match self.spare.take() {
+            Some(FrameData::Rgba(image)) => image.pixels,
+            Some(FrameData::Indexed(image)) => image.indices,
+            None => Vec::new(),
+        };
+        pixels.clear();
+        fill(&mut pixels);
+        self.push(
+            FrameData::Rgba(RgbaImage {
+                width,
+                height,
+                pixels,
+            }),
+            frames,
+        );
+    }
+
+    /// Append an indexed frame, copying `indices` and `palette` into recycled buffers.
+    pub(crate) fn capture_indexed(
+        &mut self,
+        width: u32,
+        height: u32,
+        indices: &[u8],
+        palette: &[[u8; 4]],
+    ) {
+        let (mut recycled_indices, mut recycled_palette) = match self.spare.take() {
+            Some(FrameData::Indexed(image)) => (image.indices, image.palette),
+            Some(FrameData::Rgba(image)) => (image.pixels, Vec::new()),
+            None => (Vec::new(), Vec::new()),
+        };
+        recycled_indices.clear();
+        recycled_indices.extend_from_slice(indices);
+        recycled_palette.clear();
+        recycled_palette.extend_from_slice(palette);
+        self.push(
+            FrameData::Indexed(IndexedImage {
+                width,
+                height,
+                indices: recycled_indices,
+                palette: recycled_palette,
+            }),
+            1,
+        );
+    }
+
+    fn push(&mut self, data: FrameData, frames: u32) {
+        if let Some(first) = self.frames.front() {
+            if first.data.size() != data.size() {
+                log::warn!(
+                    "frame size changed from {:?} to {:?}; restarting recording",
+                    first.data.size(),
+                    data.size()
+                );
+                self.clear();
+            }
+        }
+
+        let delay = self.options.frame_delay.saturating_mul(frames.max(1));
+        self.frames.push_back(RecordedFrame { data, delay });
+        self.duration += delay;
+
+        if let Some(max_duration) = self.options.max_duration {
+            while self.frames.len() > 1 && self.duration > max_duration {
+                if let Some(dropped) = self.frames.pop_front() {
+                    self.duration -= dropped.delay;
+                    self.spare = Some(dropped.data);
+                }
+            }
+        }
+    }
+
+    /// Encode the recorded frames as an animated clip.
+    pub fn encode(&self, format: ClipFormat) -> Result<Vec<u8>> {
+        let Some(first) = self.frames.front() else {
+            return Err(Error::InvalidImage("no frames recorded".to_string()));
+        };
+        let (width, height) = first.data.size();
+        match format {
+            ClipFormat::Gif => self.encode_gif(width, height),
+            ClipFormat::Apng => self.encode_apng(width, height),
+        }
+    }
+
+    /// Save the recorded frames to `path`, choosing the format from its extension.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let format = ClipFormat::from_path(path).ok_or_else(|| {
+            Error::UnsupportedImage(format!("unknown clip extension: {}", path.display()))
+        })?;
+        std::fs::write(path, self.encode(format)?)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "gif")]
+    fn encode_gif(&self, width: u32, height: u32) -> Result<Vec<u8>> {
+        let (Ok(gif_width), Ok(gif_height)) = (u16::try_from(width), u16::try_from(height)) else {
+            return Err(Error::UnsupportedImage(format!(
+                "GIF frames are limited to 65535x65535, got {width}x{height}"
+            )));
+        };
+
+        let mut encoder = gif::Encoder::new(Vec::new(), gif_width, gif_height, &[])?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+        for recorded in &self.frames {
+            let mut frame = match &recorded.data {
+                FrameData::Indexed(image) => {
+                    let palette: Vec<u8> = image
+                        .palette
+                        .iter()
+                        .flat_map(|c| [c[0], c[1], c[2]])
+                        .collect();
+                    // GIF has a single transparent index; use the first fully transparent entry.
+                    let transparent = image
+                        .palette
+                        .iter()
+                        .position(|c| c[3] == 0)
+                        .map(|index| index as u8);
+                    gif::Frame::from_palette_pixels(
+                        gif_width,
+                        gif_height,
+                        image.indices.clone(),
+                        palette,
+                        transparent,
+                    )
+                }
+                FrameData::Rgba(image) => {
+                    let mut pixels = image.pixels.clone();
+                    gif::Frame::from_rgba_speed(gif_width, gif_height, &mut pixels, 10)
+                }
+            };
+            // GIF delays are in hundredths of a second.
+            let centis = (recorded.delay.as_millis() + 5) / 10;
+            frame.delay = centis.clamp(1, u16::MAX as u128) as u16;
+            encoder.write_frame(&frame)?;
+        }
+        Ok(encoder.into_inner()?)
+    }
+
+    #[cfg(not(feature = "gif"))]
+    fn encode_gif(&self, _width: u32, _height: u32) -> Result<Vec<u8>> {
+        Err(Error::UnsupportedImage(
+            "GIF support requires the `gif` feature".to_string(),
+        ))
+    }
+
+    #[cfg(feature = "png")]
+    fn encode_apng(&self, width: u32, height: u32) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(self.frames.len() as u32, 0)?;
+
+        let mut writer = encoder.write_header()?;
+        for recorded in &self.frames {
+            let millis = recorded.delay.as_millis().min(u16::MAX as u128) as u16;
+            writer.set_frame_delay(millis, 1000)?;
+            match &recorded.data {
+                FrameData::Rgba(image) => writer.write_image_data(&image.pixels)?,
+                FrameData::Indexed(image) => writer.write_image_data(&image.to_rgba8().pixels)?,
+            }
+        }
+        writer.finish()?;
+        Ok(out)
+    }
+
+    #[cfg(not(feature = "png"))]
+    fn encode_apng(&self, _width: u32, _height: u32) -> Result<Vec<u8>> {
+        Err(Error::UnsupportedImage(
+            "APNG support requires the `png` feature".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_millis(10);
+
+    fn recorder(max_frames: u32) -> FrameRecorder {
+        FrameRecorder::new(
+            RecorderOptions::default()
+                .with_frame_delay(FRAME)
+                .with_max_duration(FRAME * max_frames),
+        )
+    }
+
+    fn rgba_pixels(frame: &RecordedFrame) -> &Vec<u8> {
+        match &frame.data {
+            FrameData::Rgba(image) => &image.pixels,
+            FrameData::Indexed(_) => panic!("expected an RGBA frame"),
+        }
+    }
+
+    #[test]
+    fn full_ring_reuses_the_dropped_frame_buffer() {
+        let mut recorder = recorder(2);
+        for value in 0..2u8 {
+            recorder.capture_rgba8(2, 1, 1, |pixels| pixels.extend_from_slice(&[value; 8]));
+        }
+        let oldest = rgba_pixels(&recorder.frames[0]).as_ptr();
+
+        // The third frame pushes the first out; the fourth is written into its buffer.
+        for value in 2..4u8 {
+            recorder.capture_rgba8(2, 1, 1, |pixels| {
+                assert!(pixels.is_empty());
+                pixels.extend_from_slice(&[value; 8]);
+            });
+        }
+
+        assert_eq!(recorder.len(), 2);
+        assert_eq!(recorder.duration(), FRAME * 2);
+        assert_eq!(rgba_pixels(&recorder.frames[0]), &vec![2; 8]);
+        let newest = rgba_pixels(&recorder.frames[1]);
+        assert_eq!(newest, &vec![3; 8]);
+        assert_eq!(newest.as_ptr(), oldest);
+    }
+
+    #[test]
+    fn indexed_captures_reuse_indices_and_palette() {
+        let mut recorder = recorder(1);
+        recorder.capture_indexed(2, 2, &[0, 1, 2, 3], &[[1, 2, 3, 255]; 4]);
+        let FrameData::Indexed(first) = &recorder.frames[0].data else {
+            panic!("expected an indexed frame");
+        };
+        let indices = first.indices.as_ptr();
+
+        recorder.capture_indexed(2, 2, &[1, 1, 1, 1], &[[0, 0, 0, 255]; 4]);
+        recorder.capture_indexed(2, 2, &[3, 2, 1, 0], &[[4, 5, 6, 255]; 4]);
+
+        assert_eq!(recorder.len(), 1);
+        let FrameData::Indexed(second) = &recorder.frames[0].data else {
+            panic!("expected an indexed frame");
+        };
+        assert_eq!(second.indices, vec![3, 2, 1, 0]);
+        assert_eq!(second.palette, vec![[4, 5, 6, 255]; 4]);
+        assert_eq!(second.indices.as_ptr(), indices);
+    }
+
+    #[test]
+    fn merged_captures_last_for_every_frame_they_stand_for() {
+        let mut recorder = recorder(10);
+        recorder.capture_rgba8(1, 1, 3, |pixels| pixels.extend_from_slice(&[0; 4]));
+        recorder.capture_rgba8(1, 1, 0, |pixels| pixels.extend_from_slice(&[0; 4]));
+
+        assert_eq!(recorder.frames[0].delay, FRAME * 3);
+        assert_eq!(recorder.frames[1].delay, FRAME);
+        assert_eq!(recorder.duration(), FRAME * 4);
+    }
+
+    #[test]
+    fn size_change_restarts_the_clip() {
+        let mut recorder = recorder(10);
+        recorder
+            .push_rgba8(RgbaImage {
+                width: 1,
+                height: 1,
+                pixels: vec![0; 4],
+            })
+            .unwrap();
+        recorder.capture_rgba8(2, 1, 1, |pixels| pixels.extend_from_slice(&[0; 8]));
+
+        assert_eq!(recorder.len(), 1);
+        assert_eq!(recorder.duration(), FRAME);
+    }
+
+    #[test]
+    fn pushed_frames_must_match_their_size() {
+        let mut recorder = recorder(10);
+        let short = RgbaImage {
+            width: 2,
+            height: 2,
+            pixels: vec![0; 12],
+        };
+        assert!(matches!(
+            recorder.push_rgba8(short),
+            Err(Error::InvalidImage(_))
+        ));
+
+        let indexed = IndexedImage {
+            width: 2,
+            height: 1,
+            indices: vec![0, 1],
+            palette: vec![[0; 4]; 2],
+        };
+        let long = IndexedImage {
+            indices: vec![0; 3],
+            ..indexed.clone()
+        };
+        let big_palette = IndexedImage {
+            palette: vec![[0; 4]; 257],
+            ..indexed.clone()
+        };
+        for bad in [long, big_palette] {
+            assert!(matches!(
+                recorder.push_indexed(bad),
+                Err(Error::InvalidImage(_))
+            ));
+        }
+        assert!(recorder.is_empty());
+
+        recorder.push_indexed(indexed).unwrap();
+        assert_eq!(recorder.len(), 1);
+    }
+}
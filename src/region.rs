@@ -0,0 +1,118 @@
+use crate::dirty::DirtyTiles;
+use crate::Rect;
+
+/// Mutable view into a rectangle of a stage buffer, returned by `region_mut`.
+///
+/// Coordinates are relative to the region. The region is marked dirty when the guard is dropped,
+/// so writing a sprite-sized area uploads only that area.
+pub struct RegionMut<'a, P> {
+    pixels: &'a mut [P],
+    stride: usize,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    dirty: &'a mut DirtyTiles,
+}
+
+impl<'a, P> RegionMut<'a, P> {
+    /// `rect` is clipped to the `buffer_width x buffer_height` buffer; it may end up empty.
+    pub(crate) fn new(
+        pixels: &'a mut [P],
+        buffer_width: u32,
+        buffer_height: u32,
+        rect: Rect,
+        dirty: &'a mut DirtyTiles,
+    ) -> Self {
+        let (x, y, width, height) = match rect.clamp_to(buffer_width, buffer_height) {
+            Some(rect) => (rect.x, rect.y, rect.width, rect.height),
+            None => (0, 0, 0, 0),
+        };
+        Self {
+            pixels,
+            stride: buffer_width as usize,
+            x,
+            y,
+            width,
+            height,
+            dirty,
+        }
+    }
+
+    /// The clipped region in buffer coordinates, or `None` if it is empty.
+    pub fn rect(&self) -> Option<Rect> {
+        Rect::new(self.x, self.y, self.width, self.height)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Option<&P> {
+        let index = self.index(x, y)?;
+        self.pixels.get(index)
+    }
+
+    pub fn get_mut(&mut self, x: u32, y: u32) -> Option<&mut P> {
+        let index = self.index(x, y)?;
+        self.pixels.get_mut(index)
+    }
+
+    pub fn row(&self, y: u32) -> Option<&[P]> {
+        let start = self.index(0, y)?;
+        self.pixels.get(start..start + self.width as usize)
+    }
+
+    pub fn row_mut(&mut self, y: u32) -> Option<&mut [P]> {
+        let start = self.index(0, y)?;
+        self.pixels.get_mut(start..start + self.width as usize)
+    }
+
+    /// Rows of the region, top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &[P]> + '_ {
+        let (x, width) = (self.x as usize, self.width as usize);
+        self.pixels
+            .chunks_exact(self.stride.max(1))
+            .skip(self.y as usize)
+            .take(self.height as usize)
+            .map(move |row| &row[x..x + width])
+    }
+
+    /// Mutable rows of the region, top to bottom.
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [P]> + '_ {
+        let (x, width) = (self.x as usize, self.width as usize);
+        self.pixels
+            .chunks_exact_mut(self.stride.max(1))
+            .skip(self.y as usize)
+            .take(self.height as usize)
+            .map(move |row| &mut row[x..x + width])
+    }
+
+    pub fn fill(&mut self, value: P)
+    where
+        P: Copy,
+    {
+        for row in self.rows_mut() {
+            row.fill(value);
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> Option<usize> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some((self.y + y) as usize * self.stride + (self.x + x) as usize)
+    }
+}
+
+impl<P> Drop for RegionMut<'_, P> {
+    fn drop(&mut self) {
+        if let Some(rect) = self.rect() {
+            self.dirty.mark_rect(rect);
+        }
+    }
+}
@@ -10,19 +10,288 @@ pub enum ScalingMode {
     Fill,
 }
 
+/// Which pixels `ScalingMode::PixelPerfect` scales by whole multiples of.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum PixelUnits {
+    /// Device pixels: always crisp, but the image size jumps with the HiDPI scale factor.
+    #[default]
+    Physical,
+    /// Logical pixels (physical / scale factor): the image keeps its size across displays, at
+    /// the cost of crispness on fractional scale factors.
+    Logical,
+}
+
+impl PixelUnits {
+    /// Size of one unit in physical pixels at `scale_factor`.
+    pub(crate) fn pixel_step(self, scale_factor: f64) -> f32 {
+        match self {
+            PixelUnits::Physical => 1.0,
+            PixelUnits::Logical => scale_factor as f32,
+        }
+    }
+}
+
+/// Clockwise rotation of the displayed buffer, in 90° steps.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum Rotation {
+    #[default]
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+impl Rotation {
+    /// The next rotation, 90° further clockwise.
+    pub fn clockwise(self) -> Self {
+        match self {
+            Rotation::Deg0 => Rotation::Deg90,
+            Rotation::Deg90 => Rotation::Deg180,
+            Rotation::Deg180 => Rotation::Deg270,
+            Rotation::Deg270 => Rotation::Deg0,
+        }
+    }
+
+    /// The previous rotation, 90° further counter-clockwise.
+    pub fn counter_clockwise(self) -> Self {
+        self.clockwise().clockwise().clockwise()
+    }
+
+    /// Whether width and height trade places on screen.
+    pub fn swaps_axes(self) -> bool {
+        matches!(self, Rotation::Deg90 | Rotation::Deg270)
+    }
+
+    /// Texture-space directions of the displayed x and y axes, for `tex = 0.5 + x * dx + y * dy`
+    /// with `dx`, `dy` centered on the displayed image.
+    fn tex_axes(self) -> [[f32; 2]; 2] {
+        match self {
+            Rotation::Deg0 => [[1.0, 0.0], [0.0, 1.0]],
+            Rotation::Deg90 => [[0.0, -1.0], [1.0, 0.0]],
+            Rotation::Deg180 => [[-1.0, 0.0], [0.0, -1.0]],
+            Rotation::Deg270 => [[0.0, 1.0], [-1.0, 0.0]],
+        }
+    }
+}
+
+/// Zoom, pan, rotation and flips applied on top of the `ScalingMode`, e.g. for editors or
+/// rotated arcade monitors.
+///
+/// The default viewport shows the buffer exactly as the `ScalingMode` lays it out. Flips mirror
+/// the buffer before it is rotated.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Viewport {
+    /// Magnification relative to the `ScalingMode`'s fitted scale (`1.0` = fitted).
+    pub zoom: f32,
+    /// Offset of the image center from the surface center, in surface pixels.
+    pub pan: [f32; 2],
+    pub rotation: Rotation,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self {
+            zoom: 1.0,
+            pan: [0.0, 0.0],
+            rotation: Rotation::Deg0,
+            flip_x: false,
+            flip_y: false,
+        }
+    }
+}
+
+impl Viewport {
+    /// Smallest and largest effective scale (surface pixels per buffer pixel) the zoom helpers
+    /// go to.
+    pub const MIN_SCALE: f32 = 1.0 / 64.0;
+    pub const MAX_SCALE: f32 = 1024.0;
+
+    pub fn with_zoom(mut self, zoom: f32) -> Self {
+        self.zoom = zoom;
+        self
+    }
+
+    pub fn with_pan(mut self, pan: [f32; 2]) -> Self {
+        self.pan = pan;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_flip_x(mut self, flip_x: bool) -> Self {
+        self.flip_x = flip_x;
+        self
+    }
+
+    pub fn with_flip_y(mut self, flip_y: bool) -> Self {
+        self.flip_y = flip_y;
+        self
+    }
+
+    /// Multiply the zoom by `factor`, keeping the buffer point under `position` (surface
+    /// pixels) in place. `state` is the layout this viewport currently produces.
+    pub(crate) fn zoomed_at(
+        self,
+        state: &ScalingState,
+        surface_size: (u32, u32),
+        position: (f32, f32),
+        factor: f32,
+    ) -> Self {
+        let scale = state.buffer_to_surface_scale;
+        if !(factor.is_finite() && factor > 0.0 && scale > 0.0) {
+            return self;
+        }
+        let new_scale = (scale * factor).clamp(Self::MIN_SCALE, Self::MAX_SCALE);
+        let factor = new_scale / scale;
+
+        let center_x = surface_size.0 as f32 / 2.0 + self.pan[0];
+        let center_y = surface_size.1 as f32 / 2.0 + self.pan[1];
+        let new_center_x = position.0 - (position.0 - center_x) * factor;
+        let new_center_y = position.1 - (position.1 - center_y) * factor;
+        Self {
+            zoom: self.zoom * factor,
+            pan: [
+                new_center_x - surface_size.0 as f32 / 2.0,
+                new_center_y - surface_size.1 as f32 / 2.0,
+            ],
+            ..self
+        }
+    }
+
+    /// Back to the fitted layout, keeping the rotation and flips.
+    pub(crate) fn fitted(self) -> Self {
+        Self {
+            zoom: 1.0,
+            pan: [0.0, 0.0],
+            ..self
+        }
+    }
+
+    /// One buffer pixel per surface pixel, zoomed around the surface center.
+    pub(crate) fn actual_size(self, state: &ScalingState, surface_size: (u32, u32)) -> Self {
+        let scale = state.buffer_to_surface_scale.max(f32::MIN_POSITIVE);
+        self.zoomed_at(
+            state,
+            surface_size,
+            (surface_size.0 as f32 / 2.0, surface_size.1 as f32 / 2.0),
+            1.0 / scale,
+        )
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub(crate) struct ScalingState {
     pub(crate) ndc_scale: [f32; 2],
+    pub(crate) ndc_offset: [f32; 2],
+    /// Part of the surface covered by the image; empty if the image is panned off-screen.
     pub(crate) clip_rect: Rect,
     pub(crate) buffer_to_surface_scale: f32,
+    /// Top-left corner of the (rotated) image on the surface, possibly off-screen.
+    pub(crate) image_origin: [f32; 2],
+    pub(crate) rotation: Rotation,
+    pub(crate) flip: [bool; 2],
+    pub(crate) buffer_size: (u32, u32),
+    /// Part of the buffer that is displayed (the whole buffer unless cropped).
+    pub(crate) crop: Rect,
+}
+
+impl ScalingState {
+    /// Whether any of the image is on the surface.
+    pub(crate) fn is_visible(&self) -> bool {
+        self.clip_rect.width > 0 && self.clip_rect.height > 0
+    }
+
+    /// Texture coordinates at the center of the displayed image, and the texture-space
+    /// directions of its x and y axes (with the crop and flips applied).
+    pub(crate) fn tex_transform(&self) -> ([f32; 2], [[f32; 2]; 2]) {
+        let crop = self.crop;
+        let buffer_width = self.buffer_size.0.max(1) as f32;
+        let buffer_height = self.buffer_size.1.max(1) as f32;
+        let extent = [
+            crop.width as f32 / buffer_width,
+            crop.height as f32 / buffer_height,
+        ];
+        let center = [
+            (crop.x as f32 + crop.width as f32 / 2.0) / buffer_width,
+            (crop.y as f32 + crop.height as f32 / 2.0) / buffer_height,
+        ];
+
+        let mut axes = self.rotation.tex_axes();
+        for axis in axes.iter_mut() {
+            for ((component, flip), extent) in axis.iter_mut().zip(self.flip).zip(extent) {
+                *component *= if flip { -extent } else { extent };
+            }
+        }
+        (center, axes)
+    }
+
+    /// Texture coordinates shown at `displayed` (0..1 across the displayed image).
+    #[cfg(feature = "egui")]
+    pub(crate) fn tex_coord(&self, displayed: [f32; 2]) -> [f32; 2] {
+        let (center, [tex_x, tex_y]) = self.tex_transform();
+        let x = displayed[0] - 0.5;
+        let y = displayed[1] - 0.5;
+        [
+            center[0] + tex_x[0] * x + tex_y[0] * y,
+            center[1] + tex_x[1] * x + tex_y[1] * y,
+        ]
+    }
+}
+
+/// The shaders' `Globals` uniform.
+pub(crate) type GlobalsUniform = [f32; 10];
+
+/// The shaders' `Globals` uniform for `state`.
+pub(crate) fn globals_bytes(state: &ScalingState) -> GlobalsUniform {
+    let (center, [tex_x, tex_y]) = state.tex_transform();
+    [
+        state.ndc_scale[0],
+        state.ndc_scale[1],
+        state.ndc_offset[0],
+        state.ndc_offset[1],
+        tex_x[0],
+        tex_x[1],
+        tex_y[0],
+        tex_y[1],
+        center[0],
+        center[1],
+    ]
 }
 
+/// The part of a `buffer_size` buffer that `crop` displays: `crop` clamped to the buffer, or
+/// the whole buffer if there is no crop or nothing of it is left.
+fn displayed_rect(buffer_size: (u32, u32), crop: Option<Rect>) -> Rect {
+    crop.and_then(|crop| crop.clamp_to(buffer_size.0, buffer_size.1))
+        .unwrap_or(Rect {
+            x: 0,
+            y: 0,
+            width: buffer_size.0,
+            height: buffer_size.1,
+        })
+}
+
+/// Lay out the `crop` of a `buffer_size` buffer (or all of it) on a `surface_size` surface.
+///
+/// `PixelPerfect` scales by whole multiples of `pixel_step` physical pixels.
 pub(crate) fn compute_scaling(
     buffer_size: (u32, u32),
+    crop: Option<Rect>,
     surface_size: (u32, u32),
     mode: ScalingMode,
+    pixel_step: f32,
+    viewport: &Viewport,
 ) -> ScalingState {
-    let (buffer_width, buffer_height) = buffer_size;
+    let crop = displayed_rect(buffer_size, crop);
+    let (buffer_width, buffer_height) = if viewport.rotation.swaps_axes() {
+        (crop.height, crop.width)
+    } else {
+        (crop.width, crop.height)
+    };
     let (surface_width, surface_height) = surface_size;
 
     let buffer_width_f = buffer_width as f32;
@@ -30,31 +299,43 @@ pub(crate) fn compute_scaling(
     let surface_width_f = surface_width as f32;
     let surface_height_f = surface_height as f32;
 
-    let (scaled_width, scaled_height, buffer_to_surface_scale) = match mode {
+    let fitted_scale = match mode {
         ScalingMode::PixelPerfect => {
-            let width_ratio = (surface_width_f / buffer_width_f).max(1.0);
-            let height_ratio = (surface_height_f / buffer_height_f).max(1.0);
-            let scale = width_ratio.min(height_ratio).floor().max(1.0);
-            (buffer_width_f * scale, buffer_height_f * scale, scale)
+            let pixel_step = if pixel_step.is_finite() && pixel_step > 0.0 {
+                pixel_step
+            } else {
+                1.0
+            };
+            let width_ratio = (surface_width_f / (buffer_width_f * pixel_step)).max(1.0);
+            let height_ratio = (surface_height_f / (buffer_height_f * pixel_step)).max(1.0);
+            width_ratio.min(height_ratio).floor().max(1.0) * pixel_step
         }
         ScalingMode::Fill => {
             let width_ratio = surface_width_f / buffer_width_f;
             let height_ratio = surface_height_f / buffer_height_f;
-            let scale = width_ratio.min(height_ratio);
-            (buffer_width_f * scale, buffer_height_f * scale, scale)
+            width_ratio.min(height_ratio)
         }
     };
+    let buffer_to_surface_scale = fitted_scale * viewport.zoom;
+    let scaled_width = buffer_width_f * buffer_to_surface_scale;
+    let scaled_height = buffer_height_f * buffer_to_surface_scale;
 
-    let clip_width = scaled_width.min(surface_width_f).max(1.0);
-    let clip_height = scaled_height.min(surface_height_f).max(1.0);
+    let image_x = (surface_width_f - scaled_width) / 2.0 + viewport.pan[0];
+    let image_y = (surface_height_f - scaled_height) / 2.0 + viewport.pan[1];
 
-    let clip_x = ((surface_width_f - clip_width) / 2.0).max(0.0) as u32;
-    let clip_y = ((surface_height_f - clip_height) / 2.0).max(0.0) as u32;
+    // Pixels whose centers the image covers.
+    let covered = |start: f32, end: f32, limit: f32| {
+        let first = (start - 0.5).ceil().clamp(0.0, limit);
+        let last = (end - 0.5).ceil().clamp(0.0, limit);
+        (first as u32, (last - first).max(0.0) as u32)
+    };
+    let (clip_x, clip_width) = covered(image_x, image_x + scaled_width, surface_width_f);
+    let (clip_y, clip_height) = covered(image_y, image_y + scaled_height, surface_height_f);
     let clip_rect = Rect {
         x: clip_x,
         y: clip_y,
-        width: clip_width as u32,
-        height: clip_height as u32,
+        width: clip_width,
+        height: clip_height,
     };
 
     ScalingState {
@@ -62,7 +343,76 @@ pub(crate) fn compute_scaling(
             scaled_width / surface_width_f.max(1.0),
             scaled_height / surface_height_f.max(1.0),
         ],
+        ndc_offset: [
+            2.0 * viewport.pan[0] / surface_width_f.max(1.0),
+            -2.0 * viewport.pan[1] / surface_height_f.max(1.0),
+        ],
         clip_rect,
         buffer_to_surface_scale,
+        image_origin: [image_x, image_y],
+        rotation: viewport.rotation,
+        flip: [viewport.flip_x, viewport.flip_y],
+        buffer_size,
+        crop,
+    }
+}
+
+/// Map a position relative to the scaled area's surface (or viewport) to a buffer pixel.
+///
+/// Positions outside the clip rect, or past the edges of the displayed (cropped) buffer, are
+/// returned as `Err`.
+pub(crate) fn pos_to_pixel(
+    state: &ScalingState,
+    position: (f32, f32),
+) -> std::result::Result<(usize, usize), (isize, isize)> {
+    let clip = state.clip_rect;
+    let crop = state.crop;
+
+    let x = position.0.floor() as i32;
+    let y = position.1.floor() as i32;
+    let clip_x = clip.x as i32;
+    let clip_y = clip.y as i32;
+
+    if x < clip_x
+        || y < clip_y
+        || x >= clip_x + clip.width as i32
+        || y >= clip_y + clip.height as i32
+    {
+        return Err((x as isize, y as isize));
+    }
+
+    // Position on the displayed (rotated) image, in buffer pixels.
+    let scale = state.buffer_to_surface_scale.max(f32::MIN_POSITIVE);
+    let u = (position.0 - state.image_origin[0]) / scale;
+    let v = (position.1 - state.image_origin[1]) / scale;
+    let (width, height) = (crop.width as f32, crop.height as f32);
+    let (buffer_x, buffer_y) = match state.rotation {
+        Rotation::Deg0 => (u, v),
+        Rotation::Deg90 => (v, height - u),
+        Rotation::Deg180 => (width - u, height - v),
+        Rotation::Deg270 => (width - v, u),
+    };
+    let buffer_x = if state.flip[0] {
+        width - buffer_x
+    } else {
+        buffer_x
+    };
+    let buffer_y = if state.flip[1] {
+        height - buffer_y
+    } else {
+        buffer_y
+    };
+
+    let pixel_x = buffer_x.floor() as isize + crop.x as isize;
+    let pixel_y = buffer_y.floor() as isize + crop.y as isize;
+
+    if pixel_x < crop.x as isize
+        || pixel_y < crop.y as isize
+        || pixel_x >= crop.right() as isize
+        || pixel_y >= crop.bottom() as isize
+    {
+        Err((pixel_x, pixel_y))
+    } else {
+        Ok((pixel_x as usize, pixel_y as usize))
     }
 }
@@ -0,0 +1,54 @@
+struct Params {
+    // Texture coordinates at the surface center, and across the surface's width and height.
+    center: vec2<f32>,
+    axis_x: vec2<f32>,
+    axis_y: vec2<f32>,
+    // Glow blur radius in texture coordinates.
+    blur: vec2<f32>,
+    brightness: f32,
+    // 1.0 for the glow, 0.0 for an image.
+    glow: f32,
+}
+
+struct VertexOutput {
+    @location(0) tex_coord: vec2<f32>,
+    @builtin(position) position: vec4<f32>,
+}
+
+@group(0) @binding(0) var r_tex_color: texture_2d<f32>;
+@group(0) @binding(1) var r_tex_sampler: sampler;
+@group(0) @binding(2) var<uniform> r_params: Params;
+
+const BLUR_TAPS: i32 = 4;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    // A triangle covering the whole target.
+    let position = vec2<f32>(f32(vertex_index & 1u) * 4.0 - 1.0, f32(vertex_index >> 1u) * 4.0 - 1.0);
+    let centered = position * vec2<f32>(0.5, -0.5);
+    var out: VertexOutput;
+    out.tex_coord = r_params.center + r_params.axis_x * centered.x + r_params.axis_y * centered.y;
+    out.position = vec4<f32>(position, 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    if r_params.glow < 0.5 {
+        return textureSampleLevel(r_tex_color, r_tex_sampler, in.tex_coord, 0.0);
+    }
+
+    // Gaussian-weighted grid of taps spanning the blur radius.
+    var sum = vec3<f32>(0.0);
+    var weight_sum = 0.0;
+    for (var y = -BLUR_TAPS; y <= BLUR_TAPS; y++) {
+        for (var x = -BLUR_TAPS; x <= BLUR_TAPS; x++) {
+            let offset = vec2<f32>(f32(x), f32(y)) / f32(BLUR_TAPS);
+            let weight = exp(-2.0 * dot(offset, offset));
+            let coord = in.tex_coord + offset * r_params.blur;
+            sum += textureSampleLevel(r_tex_color, r_tex_sampler, coord, 0.0).rgb * weight;
+            weight_sum += weight;
+        }
+    }
+    return vec4<f32>(sum / weight_sum * r_params.brightness, 1.0);
+}
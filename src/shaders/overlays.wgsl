@@ -0,0 +1,72 @@
+// Guides drawn over the scaled buffer; see `overlay.rs` for the layout.
+struct Overlays {
+    // xy: buffer size, z: checkerboard cell size (surface pixels), w: pixel grid min scale.
+    buffer: vec4<f32>,
+    // Enabled flags: pixel grid, checkerboard, tile grid, crosshair.
+    enabled: vec4<f32>,
+    // x: selection enabled.
+    enabled_2: vec4<f32>,
+    pixel_grid_color: vec4<f32>,
+    checker_a: vec4<f32>,
+    checker_b: vec4<f32>,
+    // xy: tile size, zw: offset (buffer pixels).
+    tile: vec4<f32>,
+    tile_color: vec4<f32>,
+    // xy: crosshair center (buffer pixels).
+    crosshair: vec4<f32>,
+    crosshair_color: vec4<f32>,
+    // x, y, width, height (buffer pixels).
+    selection: vec4<f32>,
+    selection_outline: vec4<f32>,
+    selection_fill: vec4<f32>,
+}
+
+fn over(dst: vec4<f32>, src: vec4<f32>) -> vec4<f32> {
+    return vec4<f32>(mix(dst.rgb, src.rgb, src.a), src.a + dst.a * (1.0 - src.a));
+}
+
+// Whether `pixel` lies within one surface pixel (`step`) past a multiple of `spacing`.
+fn on_line(pixel: vec2<f32>, spacing: vec2<f32>, step: vec2<f32>) -> bool {
+    let distance = pixel - floor(pixel / spacing) * spacing;
+    return any(distance < step);
+}
+
+fn apply_overlays(color: vec4<f32>, tex_coord: vec2<f32>, frag_position: vec2<f32>) -> vec4<f32> {
+    let o = r_overlays;
+    let pixel = tex_coord * o.buffer.xy;
+    // Buffer pixels per surface pixel, along each buffer axis.
+    let step = fwidth(pixel);
+
+    var out = color;
+    if o.enabled.y > 0.5 {
+        let cell = floor(frag_position / o.buffer.z);
+        let odd = (i32(cell.x) + i32(cell.y)) % 2 != 0;
+        let checker = select(o.checker_a, o.checker_b, odd);
+        out = vec4<f32>(mix(checker.rgb, out.rgb, out.a), 1.0);
+    }
+    if o.enabled.x > 0.5 && 1.0 / max(max(step.x, step.y), 1e-6) >= o.buffer.w {
+        if on_line(pixel, vec2<f32>(1.0), step) {
+            out = over(out, o.pixel_grid_color);
+        }
+    }
+    if o.enabled.z > 0.5 && on_line(pixel - o.tile.zw, o.tile.xy, step) {
+        out = over(out, o.tile_color);
+    }
+    if o.enabled_2.x > 0.5 {
+        let start = o.selection.xy;
+        let end = start + o.selection.zw;
+        if all(pixel >= start) && all(pixel < end) {
+            out = over(out, o.selection_fill);
+            if any(pixel - start < step) || any(end - pixel < step) {
+                out = over(out, o.selection_outline);
+            }
+        }
+    }
+    if o.enabled.w > 0.5 {
+        let distance = pixel - o.crosshair.xy;
+        if any(distance >= vec2<f32>(0.0) & distance < step) {
+            out = over(out, o.crosshair_color);
+        }
+    }
+    return out;
+}
@@ -0,0 +1,382 @@
+//! Writing every rendered frame to a raw video stream or an image sequence.
+//!
+//! A [`FrameSink`] writes either a Y4M (YUV4MPEG2, 4:2:0) stream that external encoders such as
+//! `ffmpeg` can read from a file or pipe, or a numbered PNG/BMP/PPM sequence. Y4M frames are
+//! full-range BT.601, flagged as such with `XCOLORRANGE=FULL` in the stream header.
+//!
+//! An optional timestamp file (Matroska "timestamp format v2", one millisecond value per frame)
+//! records each frame's presentation time. Frames written by a stage, and by
+//! [`FrameSink::write_frame`], are spaced evenly at the sink's frame rate; use
+//! [`FrameSink::write_frame_at`] to record other times.
+//!
+//! A stage's sink takes either its CPU-side buffer or, with `CaptureSource::Output`, the scaled
+//! output it renders, read back from the GPU. If writing fails, the stage logs the error,
+//! detaches the sink and keeps rendering; `take_sink_error` returns what went wrong.
+
+use crate::image::{ImageFormat, IndexedImage, RgbaImage};
+use crate::{CaptureSource, Error, Result};
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+enum SinkTarget {
+    Y4m {
+        writer: Box<dyn Write + Send>,
+        size: Option<(u32, u32)>,
+    },
+    Sequence {
+        directory: PathBuf,
+        format: ImageFormat,
+    },
+}
+
+/// Destination for every rendered frame; see the module docs.
+pub struct FrameSink {
+    target: SinkTarget,
+    frame_rate: (u32, u32),
+    source: CaptureSource,
+    scale: u32,
+    frames: u64,
+    timestamps: Option<Box<dyn Write + Send>>,
+    yuv_buffer: Vec<u8>,
+}
+
+impl fmt::Debug for FrameSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let target = match &self.target {
+            SinkTarget::Y4m { size, .. } => format!("Y4m {{ size: {size:?} }}"),
+            SinkTarget::Sequence { directory, format } => {
+                format!("Sequence {{ directory: {directory:?}, format: {format:?} }}")
+            }
+        };
+        f.debug_struct("FrameSink")
+            .field("target", &target)
+            .field("frame_rate", &self.frame_rate)
+            .field("source", &self.source)
+            .field("scale", &self.scale)
+            .field("frames", &self.frames)
+            .field("timestamps", &self.timestamps.is_some())
+            .finish()
+    }
+}
+
+impl FrameSink {
+    /// Write a Y4M stream to `writer` (e.g. a child process stdin).
+    ///
+    /// `frame_rate` is a `(numerator, denominator)` pair, e.g. `(60, 1)` or `(60000, 1001)`.
+    pub fn y4m(writer: impl Write + Send + 'static, frame_rate: (u32, u32)) -> Self {
+        Self::with_target(
+            SinkTarget::Y4m {
+                writer: Box::new(writer),
+                size: None,
+            },
+            frame_rate,
+        )
+    }
+
+    /// Create (or truncate) a `.y4m` file.
+    pub fn create_y4m(path: impl AsRef<Path>, frame_rate: (u32, u32)) -> Result<Self> {
+        let file = std::fs::File::create(path)?;
+        Ok(Self::y4m(std::io::BufWriter::new(file), frame_rate))
+    }
+
+    /// Write numbered images (`000000.png`, `000001.png`, ...) into `directory`, creating it
+    /// if needed.
+    pub fn image_sequence(
+        directory: impl AsRef<Path>,
+        format: ImageFormat,
+        frame_rate: (u32, u32),
+    ) -> Result<Self> {
+        if !matches!(
+            format,
+            ImageFormat::Png | ImageFormat::Bmp | ImageFormat::Ppm
+        ) {
+            return Err(Error::UnsupportedImage(format!(
+                "encoding {format:?} is not supported"
+            )));
+        }
+        let directory = directory.as_ref().to_path_buf();
+        std::fs::create_dir_all(&directory)?;
+        Ok(Self::with_target(
+            SinkTarget::Sequence { directory, format },
+            frame_rate,
+        ))
+    }
+
+    fn with_target(target: SinkTarget, frame_rate: (u32, u32)) -> Self {
+        Self {
+            target,
+            frame_rate: (frame_rate.0.max(1), frame_rate.1.max(1)),
+            source: CaptureSource::Buffer,
+            scale: 1,
+            frames: 0,
+            timestamps: None,
+            yuv_buffer: Vec::new(),
+        }
+    }
+
+    /// Take frames from the stage's buffer (the default) or its rendered output.
+    pub fn with_source(mut self, source: CaptureSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Upscale each frame by an integer factor (nearest neighbor, on the CPU) before writing.
+    ///
+    /// This only enlarges the frames it is given; use `CaptureSource::Output` to write what the
+    /// stage shows on screen.
+    pub fn with_scale(mut self, scale: u32) -> Self {
+        self.scale = scale.max(1);
+        self
+    }
+
+    /// Also write a timestamp file (Matroska "timestamp format v2") to `writer`.
+    pub fn with_timestamps(mut self, writer: impl Write + Send + 'static) -> Result<Self> {
+        let mut writer: Box<dyn Write + Send> = Box::new(writer);
+        writeln!(writer, "# timestamp format v2")?;
+        self.timestamps = Some(writer);
+        Ok(self)
+    }
+
+    /// Create (or truncate) a timestamp file at `path`.
+    pub fn with_timestamps_file(self, path: impl AsRef<Path>) -> Result<Self> {
+        let file = std::fs::File::create(path)?;
+        self.with_timestamps(std::io::BufWriter::new(file))
+    }
+
+    pub fn frame_rate(&self) -> (u32, u32) {
+        self.frame_rate
+    }
+
+    pub fn source(&self) -> CaptureSource {
+        self.source
+    }
+
+    pub fn frames_written(&self) -> u64 {
+        self.frames
+    }
+
+    /// Timestamp of the next frame when frames are spaced evenly at the frame rate.
+    pub fn next_timestamp(&self) -> Duration {
+        let (numerator, denominator) = self.frame_rate;
+        let nanos = self.frames as u128 * denominator as u128 * 1_000_000_000 / numerator as u128;
+        Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+    }
+
+    /// Write an RGBA8 frame at the next evenly spaced timestamp.
+    pub fn write_frame(&mut self, image: &RgbaImage) -> Result<()> {
+        self.write_frame_at(image, self.next_timestamp())
+    }
+
+    /// Write an RGBA8 frame with an explicit timestamp.
+    pub fn write_frame_at(&mut self, image: &RgbaImage, timestamp: Duration) -> Result<()> {
+        if image.pixels.len() != image.width as usize * image.height as usize * 4 {
+            return Err(Error::InvalidImage(
+                "pixel data does not match image size".to_string(),
+            ));
+        }
+        let scaled;
+        let image = if self.scale > 1 {
+            scaled = upscale(image, self.scale);
+            &scaled
+        } else {
+            image
+        };
+
+        match &mut self.target {
+            SinkTarget::Y4m { writer, size } => {
+                let frame_size = (image.width, image.height);
+                match size {
+                    None => {
+                        let (numerator, denominator) = self.frame_rate;
+                        writeln!(
+                            writer,
+                            "YUV4MPEG2 W{} H{} F{numerator}:{denominator} Ip A1:1 C420jpeg XCOLORRANGE=FULL",
+                            image.width, image.height
+                        )?;
+                        *size = Some(frame_size);
+                    }
+                    Some(size) if *size != frame_size => {
+                        return Err(Error::InvalidImage(format!(
+                            "Y4M frame size changed from {}x{} to {}x{}",
+                            size.0, size.1, frame_size.0, frame_size.1
+                        )));
+                    }
+                    Some(_) => {}
+                }
+                rgba8_to_yuv420(image, &mut self.yuv_buffer);
+                writer.write_all(b"FRAME\n")?;
+                writer.write_all(&self.yuv_buffer)?;
+            }
+            SinkTarget::Sequence { directory, format } => {
+                let bytes = image.encode(*format)?;
+                std::fs::write(sequence_path(directory, self.frames, *format), bytes)?;
+            }
+        }
+        self.finish_frame(timestamp)
+    }
+
+    /// Write an indexed frame at the next evenly spaced timestamp.
+    ///
+    /// Image sequences keep the palette (PNG/BMP); Y4M resolves it to color.
+    pub fn write_indexed_frame(&mut self, image: &IndexedImage) -> Result<()> {
+        let timestamp = self.next_timestamp();
+        match &self.target {
+            SinkTarget::Sequence { directory, format } if self.scale == 1 => {
+                let bytes = image.encode(*format)?;
+                std::fs::write(sequence_path(directory, self.frames, *format), bytes)?;
+                self.finish_frame(timestamp)
+            }
+            _ => self.write_frame_at(&image.to_rgba8(), timestamp),
+        }
+    }
+
+    /// Flush all pending output.
+    pub fn flush(&mut self) -> Result<()> {
+        if let SinkTarget::Y4m { writer, .. } = &mut self.target {
+            writer.flush()?;
+        }
+        if let Some(timestamps) = self.timestamps.as_mut() {
+            timestamps.flush()?;
+        }
+        Ok(())
+    }
+
+    fn finish_frame(&mut self, timestamp: Duration) -> Result<()> {
+        if let Some(timestamps) = self.timestamps.as_mut() {
+            writeln!(timestamps, "{:.3}", timestamp.as_secs_f64() * 1000.0)?;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+fn sequence_path(directory: &Path, index: u64, format: ImageFormat) -> PathBuf {
+    let extension = match format {
+        ImageFormat::Png => "png",
+        ImageFormat::Bmp => "bmp",
+        ImageFormat::Ppm => "ppm",
+        ImageFormat::Gif => "gif",
+        ImageFormat::Tga => "tga",
+    };
+    directory.join(format!("{index:06}.{extension}"))
+}
+
+fn upscale(image: &RgbaImage, scale: u32) -> RgbaImage {
+    let width = image.width * scale;
+    let height = image.height * scale;
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in image.pixels.chunks_exact(image.width as usize * 4) {
+        let start = pixels.len();
+        for pixel in row.chunks_exact(4) {
+            for _ in 0..scale {
+                pixels.extend_from_slice(pixel);
+            }
+        }
+        let end = pixels.len();
+        for _ in 1..scale {
+            pixels.extend_from_within(start..end);
+        }
+    }
+    RgbaImage {
+        width,
+        height,
+        pixels,
+    }
+}
+
+/// Convert RGBA8 into planar full-range BT.601 YUV 4:2:0 (alpha is ignored).
+fn rgba8_to_yuv420(image: &RgbaImage, out: &mut Vec<u8>) {
+    let width = image.width as usize;
+    let height = image.height as usize;
+    let chroma_width = width.div_ceil(2);
+    let chroma_height = height.div_ceil(2);
+    out.clear();
+    out.reserve(width * height + chroma_width * chroma_height * 2);
+
+    let rgb = |x: usize, y: usize| {
+        let offset = (y * width + x) * 4;
+        let p = &image.pixels[offset..offset + 3];
+        (p[0] as i32, p[1] as i32, p[2] as i32)
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            let (r, g, b) = rgb(x, y);
+            out.push(((77 * r + 150 * g + 29 * b + 128) >> 8) as u8);
+        }
+    }
+
+    let mut u_plane = Vec::with_capacity(chroma_width * chroma_height);
+    let mut v_plane = Vec::with_capacity(chroma_width * chroma_height);
+    for cy in 0..chroma_height {
+        for cx in 0..chroma_width {
+            // Average the (up to) 2x2 block covered by this chroma sample.
+            let (mut r, mut g, mut b, mut count) = (0, 0, 0, 0);
+            for y in cy * 2..(cy * 2 + 2).min(height) {
+                for x in cx * 2..(cx * 2 + 2).min(width) {
+                    let (pr, pg, pb) = rgb(x, y);
+                    r += pr;
+                    g += pg;
+                    b += pb;
+                    count += 1;
+                }
+            }
+            let (r, g, b) = (r / count, g / count, b / count);
+            let u = ((-43 * r - 85 * g + 128 * b + 128) >> 8) + 128;
+            let v = ((128 * r - 107 * g - 21 * b + 128) >> 8) + 128;
+            u_plane.push(u.clamp(0, 255) as u8);
+            v_plane.push(v.clamp(0, 255) as u8);
+        }
+    }
+    out.extend_from_slice(&u_plane);
+    out.extend_from_slice(&v_plane);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// A writer whose output stays readable after the sink takes ownership of it.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn y4m_header_declares_full_range() {
+        let out = Shared::default();
+        let times = Shared::default();
+        let mut sink = FrameSink::y4m(out.clone(), (50, 1))
+            .with_timestamps(times.clone())
+            .unwrap();
+        let image = RgbaImage {
+            width: 2,
+            height: 2,
+            pixels: vec![255; 16],
+        };
+        sink.write_frame(&image).unwrap();
+        sink.write_frame(&image).unwrap();
+
+        let out = out.0.lock().unwrap();
+        let header = b"YUV4MPEG2 W2 H2 F50:1 Ip A1:1 C420jpeg XCOLORRANGE=FULL\n";
+        assert!(out.starts_with(header));
+        // Each frame: marker, 2x2 luma at full-range white, one neutral U and V sample.
+        let frame = [&b"FRAME\n"[..], &[255, 255, 255, 255, 128, 128]].concat();
+        assert_eq!(&out[header.len()..], [frame.clone(), frame].concat());
+
+        let times = String::from_utf8(times.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(times, "# timestamp format v2\n0.000\n20.000\n");
+    }
+}
@@ -0,0 +1,1760 @@
+//! State and logic shared by every stage; each format only adds its textures and conversion.
+
+use crate::background::BackgroundLayer;
+use crate::capture::{deliver_output, wants_output, write_to_sink, OutputCapture};
+use crate::convert::{convert_region, ConvertRow};
+use crate::dirty::DirtyTiles;
+use crate::embed::EmbedState;
+use crate::overlay::{overlay_bytes, OverlayUniform};
+use crate::pacing::PresentModeChoice;
+use crate::producer::{take_produced, FrameSlot};
+use crate::scaling::{compute_scaling, globals_bytes, pos_to_pixel, GlobalsUniform, ScalingState};
+use crate::stats::GpuTimer;
+use crate::upload::StagingUploader;
+use crate::{
+    Background, CaptureSource, Error, FrameProducer, FrameRecorder, FrameSink, FrameStats,
+    Overlays, PixelUnits, PixstageContext, PixstageOptions, RecorderOptions, Rect, Result,
+    ScalingMode, SurfaceTexture, UploadPath, Viewport,
+};
+use std::collections::VecDeque;
+use std::time::Duration;
+use web_time::Instant;
+use wgpu::util::DeviceExt;
+
+/// The format-specific half of a stage: the textures its buffer is uploaded into, how they are
+/// drawn, and how the buffer is captured.
+pub(crate) trait StageFormat: std::fmt::Debug + Sized {
+    /// Buffer element: RGBA8 bytes, packed 16-bit pixels or palette indices.
+    type Element: bytemuck::Pod + Default + PartialEq + Send + Sync + std::fmt::Debug;
+    /// One pixel of the buffer, compared to find the changed tiles.
+    type Pixel: bytemuck::Pod + PartialEq;
+
+    /// Prefix of the stage's GPU labels, e.g. `pixstage_rgba`.
+    const LABEL: &'static str;
+    const ELEMENTS_PER_PIXEL: usize;
+    /// Bytes per texel of `upload_texture`.
+    const TEXEL_BYTES: u32;
+    /// Expands buffer rows into `upload_texture` texels; `None` uploads the bytes as they are.
+    const CONVERT: Option<ConvertRow<Self::Element>>;
+
+    /// Create the textures, bind groups and pipeline for a `width` x `height` buffer drawn into
+    /// `target_format` views.
+    fn new(gpu: &StageGpu, width: u32, height: u32, target_format: wgpu::TextureFormat) -> Self;
+
+    /// Recreate the buffer-sized textures and the bind groups reading them.
+    fn resize(&mut self, gpu: &StageGpu, width: u32, height: u32);
+
+    /// The texture the buffer is uploaded into.
+    fn upload_texture(&self) -> &wgpu::Texture;
+
+    fn pipeline(&self) -> &wgpu::RenderPipeline;
+
+    fn bind_group(&self, scaling_mode: ScalingMode) -> &wgpu::BindGroup;
+
+    /// A pipeline like `pipeline` for `target_format` views, for `prepare`.
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        target_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline;
+
+    /// A bind group like `bind_group` that reads `globals_buffer`, for `prepare`.
+    fn create_bind_group(
+        &self,
+        gpu: &StageGpu,
+        scaling_mode: ScalingMode,
+        globals_buffer: &wgpu::Buffer,
+        label: &str,
+    ) -> wgpu::BindGroup;
+
+    /// The view a `Background` samples the frame from.
+    fn frame_view(&self) -> &wgpu::TextureView;
+
+    /// Make `frame_view` hold the frame, for a background that samples it.
+    fn sample_frame(&mut self, _gpu: &StageGpu, _width: u32, _height: u32) {}
+
+    /// A border taking precedence over `set_border_color`, e.g. a palette entry.
+    fn border_color(&self) -> Option<wgpu::Color> {
+        None
+    }
+
+    /// Drop the format's own border, as `set_border_color` replaces it.
+    fn reset_border(&mut self) {}
+
+    fn write_frame(
+        &self,
+        sink: &mut FrameSink,
+        pixels: &[Self::Element],
+        width: u32,
+        height: u32,
+    ) -> Result<()>;
+
+    fn capture(
+        &self,
+        recorder: &mut FrameRecorder,
+        pixels: &[Self::Element],
+        width: u32,
+        height: u32,
+    );
+
+    /// Upload the format's own state (e.g. the palette); returns whether it changed.
+    fn upload_state(&mut self, _queue: &wgpu::Queue) -> bool {
+        false
+    }
+
+    /// Update textures derived from the uploaded ones; `changed` says anything was uploaded.
+    fn finish_upload(
+        &mut self,
+        _gpu: &StageGpu,
+        _encoder: &mut wgpu::CommandEncoder,
+        _changed: bool,
+    ) {
+    }
+}
+
+/// GPU objects every stage format draws with.
+#[derive(Debug)]
+pub(crate) struct StageGpu {
+    pub(crate) device: wgpu::Device,
+    pub(crate) queue: wgpu::Queue,
+    pub(crate) vertex_buffer: wgpu::Buffer,
+    pub(crate) globals_buffer: wgpu::Buffer,
+    pub(crate) overlays_buffer: wgpu::Buffer,
+    pub(crate) sampler_nearest: wgpu::Sampler,
+    pub(crate) sampler_linear: wgpu::Sampler,
+}
+
+impl StageGpu {
+    pub(crate) fn sampler(&self, scaling_mode: ScalingMode) -> &wgpu::Sampler {
+        match scaling_mode {
+            ScalingMode::PixelPerfect => &self.sampler_nearest,
+            ScalingMode::Fill => &self.sampler_linear,
+        }
+    }
+}
+
+/// Pixel buffer (CPU) + incremental upload into the textures of format `F` (GPU).
+#[derive(Debug)]
+pub(crate) struct Stage<'win, F: StageFormat> {
+    pub(crate) surface: Option<wgpu::Surface<'win>>,
+    pub(crate) adapter: wgpu::Adapter,
+    pub(crate) gpu: StageGpu,
+    pub(crate) surface_config: wgpu::SurfaceConfiguration,
+
+    pub(crate) present_mode: PresentModeChoice,
+    pub(crate) scaling_mode: ScalingMode,
+    pub(crate) pixel_units: PixelUnits,
+    pub(crate) scale_factor: f64,
+    pub(crate) scaling_state: ScalingState,
+    pub(crate) viewport: Viewport,
+    pub(crate) overlays: Overlays,
+    pub(crate) clear_color: wgpu::Color,
+    pub(crate) crop: Option<Rect>,
+    pub(crate) border_color: Option<wgpu::Color>,
+    pub(crate) background: Option<BackgroundLayer>,
+
+    pub(crate) format: F,
+
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) pixels: Vec<F::Element>,
+    /// Back buffers in the order `swap()` presents them.
+    pub(crate) back_pixels: VecDeque<Vec<F::Element>>,
+    pub(crate) shadow_pixels: Option<Vec<F::Element>>,
+    pub(crate) changed_tiles: u32,
+    pub(crate) stats: FrameStats,
+    pub(crate) gpu_timer: Option<GpuTimer>,
+    pub(crate) staging: Option<StagingUploader>,
+    pub(crate) producer_slot: Option<FrameSlot<F::Element>>,
+    pub(crate) embed: Option<EmbedState>,
+    pub(crate) dirty: DirtyTiles,
+    pub(crate) upload_buffer: Vec<u8>,
+    pub(crate) recorder: Option<FrameRecorder>,
+    pub(crate) output_capture: Option<OutputCapture>,
+    pub(crate) frame_sink: Option<FrameSink>,
+    pub(crate) sink_error: Option<Error>,
+}
+
+impl<'win, F: StageFormat> Stage<'win, F> {
+    pub(crate) async fn new_async<W: wgpu::WindowHandle + 'win>(
+        width: u32,
+        height: u32,
+        surface_texture: SurfaceTexture<W>,
+        options: PixstageOptions,
+    ) -> Result<Self> {
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidBufferSize { width, height });
+        }
+
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: options.backends,
+            ..Default::default()
+        });
+        let surface = instance.create_surface(surface_texture.window)?;
+        let context = PixstageContext::with_instance(instance, Some(&surface), &options).await?;
+        Self::from_surface(
+            width,
+            height,
+            surface,
+            (surface_texture.width, surface_texture.height),
+            surface_texture.scale_factor,
+            &context,
+            options,
+        )
+    }
+
+    pub(crate) fn new_with_context<W: wgpu::WindowHandle + 'win>(
+        width: u32,
+        height: u32,
+        surface_texture: SurfaceTexture<W>,
+        context: &PixstageContext,
+        options: PixstageOptions,
+    ) -> Result<Self> {
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidBufferSize { width, height });
+        }
+
+        let surface = context.instance.create_surface(surface_texture.window)?;
+        if !context.adapter.is_surface_supported(&surface) {
+            return Err(Error::IncompatibleSurface);
+        }
+        Self::from_surface(
+            width,
+            height,
+            surface,
+            (surface_texture.width, surface_texture.height),
+            surface_texture.scale_factor,
+            context,
+            options,
+        )
+    }
+
+    fn from_surface(
+        width: u32,
+        height: u32,
+        surface: wgpu::Surface<'win>,
+        surface_size: (u32, u32),
+        scale_factor: f64,
+        context: &PixstageContext,
+        options: PixstageOptions,
+    ) -> Result<Self> {
+        let adapter = &context.adapter;
+        let device = &context.device;
+
+        let surface_capabilities = surface.get_capabilities(adapter);
+        let surface_format = surface_capabilities
+            .formats
+            .iter()
+            .copied()
+            .find(|format| format.is_srgb())
+            .unwrap_or(surface_capabilities.formats[0]);
+        let present_mode =
+            PresentModeChoice::negotiate(options.present_mode, &surface_capabilities.present_modes)
+                .chosen;
+        let alpha_mode = surface_capabilities.alpha_modes[0];
+
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: surface_format,
+            width: surface_size.0,
+            height: surface_size.1,
+            present_mode,
+            desired_maximum_frame_latency: 2,
+            alpha_mode,
+            view_formats: vec![],
+        };
+        surface.configure(device, &surface_config);
+
+        let mut stage = Self::from_config(
+            width,
+            height,
+            Some(surface),
+            surface_config,
+            context,
+            options,
+        )?;
+        stage.set_scale_factor(scale_factor);
+        Ok(stage)
+    }
+
+    pub(crate) fn new_headless(
+        width: u32,
+        height: u32,
+        context: &PixstageContext,
+        target_format: wgpu::TextureFormat,
+        options: PixstageOptions,
+    ) -> Result<Self> {
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidBufferSize { width, height });
+        }
+
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: target_format,
+            width,
+            height,
+            present_mode: options.present_mode,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+        };
+        Self::from_config(width, height, None, surface_config, context, options)
+    }
+
+    fn from_config(
+        width: u32,
+        height: u32,
+        surface: Option<wgpu::Surface<'win>>,
+        surface_config: wgpu::SurfaceConfiguration,
+        context: &PixstageContext,
+        options: PixstageOptions,
+    ) -> Result<Self> {
+        let adapter = context.adapter.clone();
+        let device = context.device.clone();
+        let queue = context.queue.clone();
+        let label = F::LABEL;
+
+        let present_mode = PresentModeChoice {
+            requested: options.present_mode,
+            chosen: surface_config.present_mode,
+        };
+        let scaling_mode = options.scaling_mode;
+        let scaling_state = compute_scaling(
+            (width, height),
+            None,
+            (surface_config.width, surface_config.height),
+            scaling_mode,
+            options.pixel_units.pixel_step(1.0),
+            &options.viewport(),
+        );
+
+        let globals = globals_bytes(&scaling_state);
+        let globals_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{label}_globals_buffer")),
+            contents: bytemuck::bytes_of(&globals),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let overlays_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{label}_overlays_buffer")),
+            contents: bytemuck::bytes_of(&overlay_bytes(&Overlays::default(), (width, height))),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let sampler_nearest = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(&format!("{label}_sampler_nearest")),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let sampler_linear = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(&format!("{label}_sampler_linear")),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let vertex_buffer = create_fullscreen_triangle(&device);
+        let gpu = StageGpu {
+            device,
+            queue,
+            vertex_buffer,
+            globals_buffer,
+            overlays_buffer,
+            sampler_nearest,
+            sampler_linear,
+        };
+        let format = F::new(&gpu, width, height, surface_config.format);
+
+        let mut dirty = DirtyTiles::new(width, height, options.dirty_tile_size);
+        dirty.set_merge(options.region_merge, options.max_dirty_regions);
+        let gpu_timer = options
+            .gpu_timing
+            .then(|| GpuTimer::new(&gpu.device, &gpu.queue, label))
+            .flatten();
+        let staging = (options.upload_path == UploadPath::StagingBelt)
+            .then(|| StagingUploader::new(width, height, F::TEXEL_BYTES));
+        dirty.mark_full();
+
+        Ok(Self {
+            surface,
+            adapter,
+            gpu,
+            surface_config,
+            present_mode,
+            scaling_mode,
+            pixel_units: options.pixel_units,
+            scale_factor: 1.0,
+            scaling_state,
+            viewport: options.viewport(),
+            overlays: Overlays::default(),
+            clear_color: options.clear_color,
+            crop: None,
+            border_color: None,
+            background: None,
+            format,
+            width,
+            height,
+            pixels: vec![F::Element::default(); Self::buffer_len(width, height)],
+            back_pixels: VecDeque::new(),
+            shadow_pixels: None,
+            changed_tiles: 0,
+            stats: FrameStats::default(),
+            gpu_timer,
+            staging,
+            producer_slot: None,
+            embed: None,
+            dirty,
+            upload_buffer: Vec::new(),
+            recorder: None,
+            output_capture: None,
+            frame_sink: None,
+            sink_error: None,
+        })
+    }
+
+    fn buffer_len(width: u32, height: u32) -> usize {
+        width as usize * height as usize * F::ELEMENTS_PER_PIXEL
+    }
+
+    pub(crate) fn logical_surface_size(&self) -> (f64, f64) {
+        (
+            self.surface_config.width as f64 / self.scale_factor,
+            self.surface_config.height as f64 / self.scale_factor,
+        )
+    }
+
+    pub(crate) fn set_scaling_mode(&mut self, scaling_mode: ScalingMode) {
+        if self.scaling_mode == scaling_mode {
+            return;
+        }
+        self.scaling_mode = scaling_mode;
+        self.recompute_scaling();
+        if let Some(embed) = self.embed.as_mut() {
+            embed.bind_group = None;
+        }
+    }
+
+    pub(crate) fn set_present_mode(
+        &mut self,
+        present_mode: wgpu::PresentMode,
+    ) -> PresentModeChoice {
+        let choice = match self.surface.as_ref() {
+            Some(surface) => PresentModeChoice::negotiate(
+                present_mode,
+                &surface.get_capabilities(&self.adapter).present_modes,
+            ),
+            None => PresentModeChoice {
+                requested: present_mode,
+                chosen: present_mode,
+            },
+        };
+        self.present_mode = choice;
+        self.surface_config.present_mode = choice.chosen;
+        if let Some(surface) = self.surface.as_ref() {
+            surface.configure(&self.gpu.device, &self.surface_config);
+        }
+        choice
+    }
+
+    pub(crate) fn set_pixel_units(&mut self, pixel_units: PixelUnits) {
+        if self.pixel_units == pixel_units {
+            return;
+        }
+        self.pixel_units = pixel_units;
+        self.recompute_scaling();
+    }
+
+    pub(crate) fn set_scale_factor(&mut self, scale_factor: f64) {
+        if !(scale_factor.is_finite() && scale_factor > 0.0) || self.scale_factor == scale_factor {
+            return;
+        }
+        self.scale_factor = scale_factor;
+        self.recompute_scaling();
+    }
+
+    pub(crate) fn set_viewport(&mut self, viewport: Viewport) {
+        if self.viewport == viewport {
+            return;
+        }
+        self.viewport = viewport;
+        self.recompute_scaling();
+    }
+
+    pub(crate) fn zoom_at(&mut self, physical_position: (f32, f32), factor: f32) {
+        let viewport = self.viewport.zoomed_at(
+            &self.scaling_state,
+            self.surface_size(),
+            physical_position,
+            factor,
+        );
+        self.set_viewport(viewport);
+    }
+
+    pub(crate) fn zoom_to_actual_size(&mut self) {
+        let viewport = self
+            .viewport
+            .actual_size(&self.scaling_state, self.surface_size());
+        self.set_viewport(viewport);
+    }
+
+    pub(crate) fn surface_size(&self) -> (u32, u32) {
+        (self.surface_config.width, self.surface_config.height)
+    }
+
+    pub(crate) fn set_overlays(&mut self, overlays: Overlays) {
+        self.overlays = overlays;
+        self.write_overlays();
+    }
+
+    pub(crate) fn set_crop(&mut self, crop: Option<Rect>) {
+        if self.crop == crop {
+            return;
+        }
+        self.crop = crop;
+        self.recompute_scaling();
+    }
+
+    pub(crate) fn set_border_color(&mut self, border_color: Option<wgpu::Color>) {
+        self.border_color = border_color;
+        self.format.reset_border();
+    }
+
+    pub(crate) fn set_background(&mut self, background: Option<Background>) {
+        self.background = None;
+        if background.as_ref().is_some_and(Background::samples_frame) {
+            self.format.sample_frame(&self.gpu, self.width, self.height);
+        }
+        self.background = background.map(|background| {
+            BackgroundLayer::new(
+                &self.gpu.device,
+                &self.gpu.queue,
+                &background,
+                self.surface_config.format,
+                self.format.frame_view(),
+                &format!("{}_background", F::LABEL),
+            )
+        });
+    }
+
+    pub(crate) fn resize_surface(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.surface_config.width = width;
+        self.surface_config.height = height;
+        if let Some(surface) = self.surface.as_ref() {
+            surface.configure(&self.gpu.device, &self.surface_config);
+        }
+        self.recompute_scaling();
+    }
+
+    pub(crate) fn resize_buffer(&mut self, width: u32, height: u32) -> Result<()> {
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidBufferSize { width, height });
+        }
+
+        let len = Self::buffer_len(width, height);
+        self.width = width;
+        self.height = height;
+        self.pixels.resize(len, F::Element::default());
+        if let Some(shadow_pixels) = self.shadow_pixels.as_mut() {
+            shadow_pixels.clear();
+        }
+        for back in self.back_pixels.iter_mut() {
+            back.resize(len, F::Element::default());
+        }
+        self.dirty.resize(width, height);
+
+        self.format.resize(&self.gpu, width, height);
+        if let Some(embed) = self.embed.as_mut() {
+            embed.bind_group = None;
+        }
+        if let Some(background) = self.background.as_mut() {
+            background.set_frame_view(&self.gpu.device, self.format.frame_view());
+        }
+
+        self.recompute_scaling();
+        self.write_overlays();
+        self.dirty.mark_full();
+        Ok(())
+    }
+
+    pub(crate) fn start_recording(&mut self, mut options: RecorderOptions) {
+        let format = self.surface_config.format;
+        if options.source == CaptureSource::Output && !OutputCapture::supports(format) {
+            log::warn!("cannot read back {format:?} output; recording the buffer instead");
+            options.source = CaptureSource::Buffer;
+        }
+        self.flush_output_capture();
+        self.recorder = Some(FrameRecorder::new(options));
+    }
+
+    pub(crate) fn stop_recording(&mut self) -> Option<FrameRecorder> {
+        self.flush_output_capture();
+        self.recorder.take()
+    }
+
+    pub(crate) fn set_frame_sink(&mut self, sink: Option<FrameSink>) -> Option<FrameSink> {
+        self.flush_output_capture();
+        let format = self.surface_config.format;
+        let sink = sink.map(|sink| {
+            if sink.source() == CaptureSource::Output && !OutputCapture::supports(format) {
+                log::warn!("cannot read back {format:?} output; writing the buffer instead");
+                sink.with_source(CaptureSource::Buffer)
+            } else {
+                sink
+            }
+        });
+        std::mem::replace(&mut self.frame_sink, sink)
+    }
+
+    pub(crate) fn set_back_buffer_count(&mut self, count: usize) {
+        self.back_pixels.truncate(count);
+        while self.back_pixels.len() < count {
+            self.back_pixels.push_back(self.pixels.clone());
+        }
+    }
+
+    pub(crate) fn swap(&mut self) -> u32 {
+        let Some(mut previous) = self.back_pixels.pop_front() else {
+            return 0;
+        };
+        std::mem::swap(&mut self.pixels, &mut previous);
+        let changed = self.dirty.mark_changed::<F::Pixel>(
+            bytemuck::cast_slice(&self.pixels),
+            bytemuck::cast_slice(&previous),
+        );
+        self.back_pixels.push_back(previous);
+        changed
+    }
+
+    pub(crate) fn set_auto_dirty(&mut self, enabled: bool) {
+        if !enabled {
+            self.shadow_pixels = None;
+        } else if self.shadow_pixels.is_none() {
+            // Empty until the first render, which then uploads the full frame.
+            self.shadow_pixels = Some(Vec::new());
+        }
+    }
+
+    pub(crate) fn set_upload_path(&mut self, upload_path: UploadPath) {
+        self.staging = (upload_path == UploadPath::StagingBelt)
+            .then(|| StagingUploader::new(self.width, self.height, F::TEXEL_BYTES));
+    }
+
+    pub(crate) fn producer(&mut self) -> FrameProducer<F::Element> {
+        let (producer, slot) = FrameProducer::new(
+            self.pixels.clone(),
+            self.width,
+            self.height,
+            F::ELEMENTS_PER_PIXEL,
+        );
+        self.producer_slot = Some(slot);
+        producer
+    }
+
+    pub(crate) fn window_pos_to_pixel(
+        &self,
+        physical_position: (f32, f32),
+    ) -> std::result::Result<(usize, usize), (isize, isize)> {
+        pos_to_pixel(&self.scaling_state, physical_position)
+    }
+
+    pub(crate) fn logical_pos_to_pixel(
+        &self,
+        logical_position: (f32, f32),
+    ) -> std::result::Result<(usize, usize), (isize, isize)> {
+        let scale_factor = self.scale_factor as f32;
+        self.window_pos_to_pixel((
+            logical_position.0 * scale_factor,
+            logical_position.1 * scale_factor,
+        ))
+    }
+
+    pub(crate) fn target_pos_to_pixel(
+        &self,
+        target_position: (f32, f32),
+    ) -> std::result::Result<(usize, usize), (isize, isize)> {
+        let Some((viewport, scaling_state)) = self.embed.as_ref().and_then(|embed| {
+            embed
+                .scaling_state
+                .as_ref()
+                .map(|state| (embed.viewport(), state))
+        }) else {
+            return Err((
+                target_position.0.floor() as isize,
+                target_position.1.floor() as isize,
+            ));
+        };
+        pos_to_pixel(
+            scaling_state,
+            (
+                target_position.0 - viewport.x as f32,
+                target_position.1 - viewport.y as f32,
+            ),
+        )
+    }
+
+    pub(crate) fn render(&mut self) -> Result<()> {
+        if self.surface_config.width == 0 || self.surface_config.height == 0 {
+            return Ok(());
+        }
+
+        let frame = {
+            let surface = self.surface.as_ref().ok_or(Error::NoSurface)?;
+            surface.get_current_texture().or_else(|_| {
+                surface.configure(&self.gpu.device, &self.surface_config);
+                surface.get_current_texture()
+            })?
+        };
+
+        let view = frame
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = self
+            .gpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some(&format!("{}_command_encoder", F::LABEL)),
+            });
+        self.prepare_frame(&mut encoder)?;
+        if let Some(background) = self.background.as_ref() {
+            background.prepare(
+                &self.gpu.queue,
+                (self.surface_config.width, self.surface_config.height),
+                &self.scaling_state,
+            );
+        }
+
+        self.encode_render_pass(
+            &mut encoder,
+            &view,
+            self.gpu_timer.as_ref().and_then(GpuTimer::timestamp_writes),
+        );
+        let mut output_capture = self.take_output_capture();
+        if let Some(capture) = output_capture.as_mut() {
+            capture.record(&mut encoder, |encoder, target| {
+                self.encode_render_pass(encoder, target, None)
+            });
+        }
+
+        if let Some(gpu_timer) = self.gpu_timer.as_mut() {
+            gpu_timer.resolve(&mut encoder);
+        }
+
+        if let Some(staging) = self.staging.as_mut() {
+            staging.finish();
+        }
+
+        self.gpu.queue.submit(Some(encoder.finish()));
+        if let Some(staging) = self.staging.as_mut() {
+            staging.recall();
+        }
+        if let Some(mut capture) = output_capture {
+            capture.after_submit();
+            deliver_output(
+                &mut capture,
+                &self.gpu.device,
+                false,
+                self.recorder.as_mut(),
+                &mut self.frame_sink,
+                &mut self.sink_error,
+            );
+            self.output_capture = Some(capture);
+        }
+        if let Some(gpu_timer) = self.gpu_timer.as_mut() {
+            if let Some(gpu_time) = gpu_timer.after_submit(&self.gpu.device) {
+                self.stats.gpu_time = Some(gpu_time);
+            }
+        }
+        frame.present();
+        Ok(())
+    }
+
+    /// Record what `render()` draws (clear, background, scaled stage) into `view`.
+    fn encode_render_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites<'_>>,
+    ) {
+        let border_color = self
+            .format
+            .border_color()
+            .or(self.border_color)
+            .unwrap_or(self.clear_color);
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(&format!("{}_render_pass", F::LABEL)),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                depth_slice: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(border_color),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes,
+            occlusion_query_set: None,
+        });
+
+        if let Some(background) = self.background.as_ref() {
+            background.draw(&mut pass);
+        }
+        if self.scaling_state.is_visible() {
+            let clip = self.scaling_state.clip_rect;
+            pass.set_scissor_rect(clip.x, clip.y, clip.width, clip.height);
+            pass.set_pipeline(self.format.pipeline());
+            pass.set_bind_group(0, self.format.bind_group(self.scaling_mode), &[]);
+            pass.set_vertex_buffer(0, self.gpu.vertex_buffer.slice(..));
+            pass.draw(0..3, 0..1);
+        }
+    }
+
+    /// The readback for this frame's output if the recorder or sink take it, recreated (after
+    /// finishing the old one) when the surface changed.
+    fn take_output_capture(&mut self) -> Option<OutputCapture> {
+        if !wants_output(self.recorder.as_ref(), self.frame_sink.as_ref()) {
+            self.output_capture = None;
+            return None;
+        }
+        let format = self.surface_config.format;
+        let size = (self.surface_config.width, self.surface_config.height);
+        match self.output_capture.take() {
+            Some(capture) if capture.matches(format, size) => Some(capture),
+            previous => {
+                if let Some(mut previous) = previous {
+                    deliver_output(
+                        &mut previous,
+                        &self.gpu.device,
+                        true,
+                        self.recorder.as_mut(),
+                        &mut self.frame_sink,
+                        &mut self.sink_error,
+                    );
+                }
+                Some(OutputCapture::new(
+                    &self.gpu.device,
+                    format,
+                    size,
+                    &format!("{}_output_capture", F::LABEL),
+                ))
+            }
+        }
+    }
+
+    /// Wait for the output frames still being read back and pass them on.
+    fn flush_output_capture(&mut self) {
+        if let Some(capture) = self.output_capture.as_mut() {
+            deliver_output(
+                capture,
+                &self.gpu.device,
+                true,
+                self.recorder.as_mut(),
+                &mut self.frame_sink,
+                &mut self.sink_error,
+            );
+        }
+    }
+
+    pub(crate) fn upload(&mut self, encoder: &mut wgpu::CommandEncoder) -> Result<()> {
+        if let Some(staging) = self.staging.as_mut() {
+            staging.recall();
+        }
+        self.prepare_frame(encoder)?;
+        if let Some(staging) = self.staging.as_mut() {
+            staging.finish();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn prepare(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        target_format: wgpu::TextureFormat,
+        viewport: Rect,
+    ) -> Result<()> {
+        self.upload(encoder)?;
+        self.prepare_embed(target_format, viewport);
+        Ok(())
+    }
+
+    pub(crate) fn draw(&self, pass: &mut wgpu::RenderPass<'_>) {
+        if let Some(embed) = self.embed.as_ref() {
+            embed.draw(pass, self.format.pipeline(), &self.gpu.vertex_buffer);
+        }
+    }
+
+    pub(crate) fn render_to(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        target_format: wgpu::TextureFormat,
+        viewport: Rect,
+    ) -> Result<()> {
+        self.prepare(encoder, target_format, viewport)?;
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(&format!("{}_embed_render_pass", F::LABEL)),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                depth_slice: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        self.draw(&mut pass);
+        Ok(())
+    }
+
+    /// Everything before the draw: producer pickup, capture, change detection and upload.
+    fn prepare_frame(&mut self, encoder: &mut wgpu::CommandEncoder) -> Result<()> {
+        self.take_produced_frame()?;
+
+        if self
+            .frame_sink
+            .as_ref()
+            .is_some_and(|sink| sink.source() == CaptureSource::Buffer)
+        {
+            let (format, pixels) = (&self.format, &self.pixels);
+            let (width, height) = (self.width, self.height);
+            write_to_sink(&mut self.frame_sink, &mut self.sink_error, |sink| {
+                format.write_frame(sink, pixels, width, height)
+            });
+        }
+        if let Some(recorder) = self
+            .recorder
+            .as_mut()
+            .filter(|recorder| recorder.options().source == CaptureSource::Buffer)
+        {
+            self.format
+                .capture(recorder, &self.pixels, self.width, self.height);
+        }
+
+        let state_changed = self.format.upload_state(&self.gpu.queue);
+        self.detect_changes();
+        self.upload_dirty_regions(encoder);
+        let changed = state_changed || self.stats.dirty_regions > 0;
+        self.format.finish_upload(&self.gpu, encoder, changed);
+        Ok(())
+    }
+
+    fn prepare_embed(&mut self, target_format: wgpu::TextureFormat, viewport: Rect) {
+        let embed = self.embed.get_or_insert_with(|| {
+            EmbedState::new(&self.gpu.device, &format!("{}_embed_globals", F::LABEL))
+        });
+        if embed.format != Some(target_format) {
+            embed.pipeline = (target_format != self.surface_config.format)
+                .then(|| self.format.create_pipeline(&self.gpu.device, target_format));
+            embed.format = Some(target_format);
+        }
+        if embed.bind_group.is_none() {
+            embed.bind_group = Some(self.format.create_bind_group(
+                &self.gpu,
+                self.scaling_mode,
+                &embed.globals_buffer,
+                &format!("{}_embed_bind_group", F::LABEL),
+            ));
+        }
+
+        let scaling_state = compute_scaling(
+            (self.width, self.height),
+            self.crop,
+            (viewport.width.max(1), viewport.height.max(1)),
+            self.scaling_mode,
+            self.pixel_units.pixel_step(self.scale_factor),
+            &self.viewport,
+        );
+        embed.set_viewport(
+            &self.gpu.queue,
+            viewport,
+            scaling_state,
+            globals_bytes(&scaling_state),
+        );
+    }
+
+    fn recompute_scaling(&mut self) {
+        self.scaling_state = compute_scaling(
+            (self.width, self.height),
+            self.crop,
+            (self.surface_config.width, self.surface_config.height),
+            self.scaling_mode,
+            self.pixel_units.pixel_step(self.scale_factor),
+            &self.viewport,
+        );
+        let globals = globals_bytes(&self.scaling_state);
+        self.gpu
+            .queue
+            .write_buffer(&self.gpu.globals_buffer, 0, bytemuck::bytes_of(&globals));
+    }
+
+    fn write_overlays(&self) {
+        let overlays = overlay_bytes(&self.overlays, (self.width, self.height));
+        self.gpu
+            .queue
+            .write_buffer(&self.gpu.overlays_buffer, 0, bytemuck::bytes_of(&overlays));
+    }
+
+    fn take_produced_frame(&mut self) -> Result<()> {
+        let Some(slot) = self.producer_slot.as_ref() else {
+            return Ok(());
+        };
+        let modified = self.dirty.dirty_tile_count() > 0;
+        let Some(taken) = take_produced(slot, &mut self.pixels, modified) else {
+            return Ok(());
+        };
+        if taken.width != self.width || taken.height != self.height {
+            return self.resize_buffer(taken.width, taken.height);
+        }
+        if taken.full {
+            self.dirty.mark_full();
+        } else {
+            for rect in taken.dirty {
+                self.dirty.mark_rect(rect);
+            }
+        }
+        Ok(())
+    }
+
+    fn detect_changes(&mut self) {
+        if let Some(shadow_pixels) = self.shadow_pixels.as_mut() {
+            self.dirty.clear();
+            self.changed_tiles = self.dirty.mark_changed::<F::Pixel>(
+                bytemuck::cast_slice(&self.pixels),
+                bytemuck::cast_slice(shadow_pixels),
+            );
+            shadow_pixels.clear();
+            shadow_pixels.extend_from_slice(&self.pixels);
+        }
+    }
+
+    fn upload_dirty_regions(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let tiles_dirtied = self.dirty.dirty_tile_count();
+        let regions = self.dirty.take_regions();
+        let pixels: u64 = regions
+            .iter()
+            .map(|region| region.width as u64 * region.height as u64)
+            .sum();
+        self.stats = FrameStats {
+            tiles_dirtied,
+            dirty_regions: regions.len() as u32,
+            bytes_uploaded: pixels * F::TEXEL_BYTES as u64,
+            full_upload: self.dirty.covers_buffer(&regions),
+            gpu_time: self.stats.gpu_time,
+            ..FrameStats::default()
+        };
+        if regions.is_empty() {
+            return;
+        }
+        let started = Instant::now();
+        let calls = regions.len();
+        let width = self.width as usize;
+        let mut convert_time = Duration::ZERO;
+
+        if let Some(staging) = self.staging.as_mut() {
+            let buffer = &self.pixels;
+            staging.upload(
+                &self.gpu.device,
+                encoder,
+                self.format.upload_texture(),
+                &regions,
+                F::TEXEL_BYTES,
+                |region, dst, pitch| {
+                    convert_time += pack_region::<F>(buffer, width, region, dst, pitch);
+                },
+            );
+        } else {
+            for region in regions {
+                let bytes_per_row = region.width * F::TEXEL_BYTES;
+                let full_frame = region.x == 0
+                    && region.y == 0
+                    && region.width == self.width
+                    && region.height == self.height;
+                // A full frame that needs no conversion is uploaded straight from the buffer.
+                let data: &[u8] = if full_frame && F::CONVERT.is_none() {
+                    bytemuck::cast_slice(&self.pixels)
+                } else {
+                    self.upload_buffer
+                        .resize(bytes_per_row as usize * region.height as usize, 0);
+                    convert_time += pack_region::<F>(
+                        &self.pixels,
+                        width,
+                        region,
+                        &mut self.upload_buffer,
+                        bytes_per_row as usize,
+                    );
+                    &self.upload_buffer
+                };
+
+                self.gpu.queue.write_texture(
+                    wgpu::TexelCopyTextureInfo {
+                        texture: self.format.upload_texture(),
+                        mip_level: 0,
+                        origin: wgpu::Origin3d {
+                            x: region.x,
+                            y: region.y,
+                            z: 0,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    data,
+                    wgpu::TexelCopyBufferLayout {
+                        offset: 0,
+                        bytes_per_row: Some(bytes_per_row),
+                        rows_per_image: Some(region.height),
+                    },
+                    wgpu::Extent3d {
+                        width: region.width,
+                        height: region.height,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+
+        let elapsed = started.elapsed();
+        self.stats.record_cpu_upload(elapsed, convert_time);
+        self.dirty.record_upload(calls, pixels, elapsed);
+    }
+}
+
+/// Pack `region` of a `width`-wide buffer into `dst` as texels, one row every `pitch` bytes.
+///
+/// Returns the time spent converting (zero for formats uploaded as they are).
+fn pack_region<F: StageFormat>(
+    pixels: &[F::Element],
+    width: usize,
+    region: Rect,
+    dst: &mut [u8],
+    pitch: usize,
+) -> Duration {
+    if let Some(convert) = F::CONVERT {
+        let started = Instant::now();
+        convert_region(pixels, width, region, dst, pitch, convert);
+        return started.elapsed();
+    }
+
+    let bytes: &[u8] = bytemuck::cast_slice(pixels);
+    let texel_bytes = F::TEXEL_BYTES as usize;
+    let row_bytes = region.width as usize * texel_bytes;
+    for (row, dst_row) in dst
+        .chunks_mut(pitch)
+        .take(region.height as usize)
+        .enumerate()
+    {
+        let start = ((region.y as usize + row) * width + region.x as usize) * texel_bytes;
+        dst_row[..row_bytes].copy_from_slice(&bytes[start..start + row_bytes]);
+    }
+    Duration::ZERO
+}
+
+fn create_fullscreen_triangle(device: &wgpu::Device) -> wgpu::Buffer {
+    let vertex_data: [[f32; 2]; 3] = [[-1.0, -1.0], [3.0, -1.0], [-1.0, 3.0]];
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("pixstage_fullscreen_triangle_vertex_buffer"),
+        contents: bytemuck::cast_slice(&vertex_data),
+        usage: wgpu::BufferUsages::VERTEX,
+    })
+}
+
+fn fullscreen_triangle_layout() -> wgpu::VertexBufferLayout<'static> {
+    wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &[wgpu::VertexAttribute {
+            offset: 0,
+            shader_location: 0,
+            format: wgpu::VertexFormat::Float32x2,
+        }],
+    }
+}
+
+/// A filterable 2D texture read by the fragment shader.
+pub(crate) fn texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    }
+}
+
+pub(crate) fn sampler_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    }
+}
+
+/// The `GlobalsUniform` (vertex stage) and `OverlayUniform` (fragment stage) entries at
+/// `binding` and `binding + 1`.
+pub(crate) fn uniform_entries(binding: u32) -> [wgpu::BindGroupLayoutEntry; 2] {
+    let uniform = |binding, visibility, size: usize| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: wgpu::BufferSize::new(size as u64),
+        },
+        count: None,
+    };
+    [
+        uniform(
+            binding,
+            wgpu::ShaderStages::VERTEX,
+            std::mem::size_of::<GlobalsUniform>(),
+        ),
+        uniform(
+            binding + 1,
+            wgpu::ShaderStages::FRAGMENT,
+            std::mem::size_of::<OverlayUniform>(),
+        ),
+    ]
+}
+
+/// The fullscreen-triangle pipeline of the WGSL `shader` drawing into `format` views.
+pub(crate) fn create_pipeline(
+    device: &wgpu::Device,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    label: &str,
+    shader: &'static str,
+    format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(&format!("{label}_shader")),
+        source: wgpu::ShaderSource::Wgsl(shader.into()),
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(&format!("{label}_pipeline_layout")),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format!("{label}_pipeline")),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[fullscreen_triangle_layout()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: Some("fs_main"),
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// The public API every stage shares, forwarding to its `stage: Stage<'win, _>` field.
+///
+/// `$name` names the format in the constructor docs ("an RGBA8 stage") and `$element` is the
+/// buffer element type.
+macro_rules! impl_stage {
+    ($stage:ident, $name:literal, $element:ty) => {
+        impl<'win> $stage<'win> {
+            #[doc = concat!("Asynchronously create an ", $name, " stage.")]
+            pub async fn new_async<W: wgpu::WindowHandle + 'win>(
+                width: u32,
+                height: u32,
+                surface_texture: $crate::SurfaceTexture<W>,
+            ) -> $crate::Result<Self> {
+                Self::new_async_with_options(
+                    width,
+                    height,
+                    surface_texture,
+                    $crate::PixstageOptions::default(),
+                )
+                .await
+            }
+
+            #[doc = concat!("Asynchronously create an ", $name, " stage with options.")]
+            pub async fn new_async_with_options<W: wgpu::WindowHandle + 'win>(
+                width: u32,
+                height: u32,
+                surface_texture: $crate::SurfaceTexture<W>,
+                options: $crate::PixstageOptions,
+            ) -> $crate::Result<Self> {
+                let stage =
+                    $crate::stage::Stage::new_async(width, height, surface_texture, options)
+                        .await?;
+                Ok(Self { stage })
+            }
+
+            #[doc = concat!(
+                "Create an ", $name, " stage on a shared [`PixstageContext`](crate::PixstageContext) ",
+                "instead of a new device.\n\n",
+                "The surface is created from `context.instance` and `options.backends` is ignored.\n",
+                "`options.gpu_timing` only takes effect if the context's device has `TIMESTAMP_QUERY`\n",
+                "(e.g. a context created with `gpu_timing` set)."
+            )]
+            pub fn new_with_context<W: wgpu::WindowHandle + 'win>(
+                width: u32,
+                height: u32,
+                surface_texture: $crate::SurfaceTexture<W>,
+                context: &$crate::PixstageContext,
+                options: $crate::PixstageOptions,
+            ) -> $crate::Result<Self> {
+                let stage = $crate::stage::Stage::new_with_context(
+                    width,
+                    height,
+                    surface_texture,
+                    context,
+                    options,
+                )?;
+                Ok(Self { stage })
+            }
+
+            #[doc = concat!(
+                "Create an ", $name, " stage without a window surface, to be drawn only with ",
+                "`render_to` or\n`draw` into views the caller presents.\n\n",
+                "`target_format` is the format of those views; others work too, at the cost of ",
+                "an extra\npipeline. `render()` returns `Error::NoSurface`."
+            )]
+            pub fn new_headless(
+                width: u32,
+                height: u32,
+                context: &$crate::PixstageContext,
+                target_format: wgpu::TextureFormat,
+                options: $crate::PixstageOptions,
+            ) -> $crate::Result<Self> {
+                let stage = $crate::stage::Stage::new_headless(
+                    width,
+                    height,
+                    context,
+                    target_format,
+                    options,
+                )?;
+                Ok(Self { stage })
+            }
+
+            #[doc = concat!("Synchronously create an ", $name, " stage (native only).")]
+            #[cfg(not(target_arch = "wasm32"))]
+            pub fn new<W: wgpu::WindowHandle + 'win>(
+                width: u32,
+                height: u32,
+                surface_texture: $crate::SurfaceTexture<W>,
+            ) -> $crate::Result<Self> {
+                pollster::block_on(Self::new_async(width, height, surface_texture))
+            }
+
+            #[doc = concat!("Synchronously create an ", $name, " stage with options (native only).")]
+            #[cfg(not(target_arch = "wasm32"))]
+            pub fn new_with_options<W: wgpu::WindowHandle + 'win>(
+                width: u32,
+                height: u32,
+                surface_texture: $crate::SurfaceTexture<W>,
+                options: $crate::PixstageOptions,
+            ) -> $crate::Result<Self> {
+                pollster::block_on(Self::new_async_with_options(
+                    width,
+                    height,
+                    surface_texture,
+                    options,
+                ))
+            }
+
+            pub fn adapter(&self) -> &wgpu::Adapter {
+                &self.stage.adapter
+            }
+
+            pub fn device(&self) -> &wgpu::Device {
+                &self.stage.gpu.device
+            }
+
+            pub fn queue(&self) -> &wgpu::Queue {
+                &self.stage.gpu.queue
+            }
+
+            pub fn buffer_size(&self) -> (u32, u32) {
+                (self.stage.width, self.stage.height)
+            }
+
+            pub fn surface_size(&self) -> (u32, u32) {
+                self.stage.surface_size()
+            }
+
+            /// Surface size in logical pixels (see `scale_factor`).
+            pub fn logical_surface_size(&self) -> (f64, f64) {
+                self.stage.logical_surface_size()
+            }
+
+            pub fn set_scaling_mode(&mut self, scaling_mode: $crate::ScalingMode) {
+                self.stage.set_scaling_mode(scaling_mode);
+            }
+
+            /// The present mode asked for (`PixstageOptions::present_mode` or
+            /// `set_present_mode`) and the one the surface uses.
+            pub fn present_mode(&self) -> $crate::PresentModeChoice {
+                self.stage.present_mode
+            }
+
+            /// Reconfigure the surface with `present_mode`, or the closest mode it supports (see
+            /// `PresentModeChoice::negotiate`). Stages without a surface only record it.
+            pub fn set_present_mode(
+                &mut self,
+                present_mode: wgpu::PresentMode,
+            ) -> $crate::PresentModeChoice {
+                self.stage.set_present_mode(present_mode)
+            }
+
+            pub fn scaling_mode(&self) -> $crate::ScalingMode {
+                self.stage.scaling_mode
+            }
+
+            pub fn pixel_units(&self) -> $crate::PixelUnits {
+                self.stage.pixel_units
+            }
+
+            /// Pick whole physical or logical pixel factors for `ScalingMode::PixelPerfect`.
+            pub fn set_pixel_units(&mut self, pixel_units: $crate::PixelUnits) {
+                self.stage.set_pixel_units(pixel_units);
+            }
+
+            /// Physical pixels per logical pixel, from `SurfaceTexture::with_scale_factor`
+            /// (`1.0` for headless stages unless set).
+            pub fn scale_factor(&self) -> f64 {
+                self.stage.scale_factor
+            }
+
+            /// Follow a HiDPI scale-factor change, e.g. winit's `ScaleFactorChanged`; pass the
+            /// new physical size to `resize_surface` as well. Invalid factors are ignored.
+            pub fn set_scale_factor(&mut self, scale_factor: f64) {
+                self.stage.set_scale_factor(scale_factor);
+            }
+
+            pub fn viewport(&self) -> $crate::Viewport {
+                self.stage.viewport
+            }
+
+            /// Zoom, pan and rotate the displayed buffer; `window_pos_to_pixel` follows it.
+            pub fn set_viewport(&mut self, viewport: $crate::Viewport) {
+                self.stage.set_viewport(viewport);
+            }
+
+            /// Rotate the displayed buffer, e.g. for vertical arcade monitors.
+            pub fn set_rotation(&mut self, rotation: $crate::Rotation) {
+                self.set_viewport(self.stage.viewport.with_rotation(rotation));
+            }
+
+            /// Mirror the displayed buffer horizontally and/or vertically (before the rotation).
+            pub fn set_flip(&mut self, flip_x: bool, flip_y: bool) {
+                self.set_viewport(self.stage.viewport.with_flip_x(flip_x).with_flip_y(flip_y));
+            }
+
+            /// Multiply the zoom by `factor`, keeping the buffer point under
+            /// `physical_position` (a window position, as for `window_pos_to_pixel`) in place,
+            /// e.g. for mouse-wheel zoom.
+            pub fn zoom_at(&mut self, physical_position: (f32, f32), factor: f32) {
+                self.stage.zoom_at(physical_position, factor);
+            }
+
+            /// Drop zoom and pan, so the `ScalingMode` fits the buffer again.
+            pub fn zoom_to_fit(&mut self) {
+                self.set_viewport(self.stage.viewport.fitted());
+            }
+
+            /// Zoom to one buffer pixel per surface pixel (1:1) around the surface center.
+            pub fn zoom_to_actual_size(&mut self) {
+                self.stage.zoom_to_actual_size();
+            }
+
+            /// Move the image by a delta in physical pixels, e.g. from a mouse drag.
+            pub fn pan_by(&mut self, dx: f32, dy: f32) {
+                let mut viewport = self.stage.viewport;
+                viewport.pan[0] += dx;
+                viewport.pan[1] += dy;
+                self.set_viewport(viewport);
+            }
+
+            pub fn overlays(&self) -> $crate::Overlays {
+                self.stage.overlays
+            }
+
+            /// Draw a pixel grid, checkerboard, tile grid, crosshair or selection over the
+            /// buffer.
+            ///
+            /// Only a uniform changes; the buffer and dirty state are left alone.
+            pub fn set_overlays(&mut self, overlays: $crate::Overlays) {
+                self.stage.set_overlays(overlays);
+            }
+
+            pub fn clear_color(&mut self, clear_color: wgpu::Color) {
+                self.stage.clear_color = clear_color;
+            }
+
+            pub fn crop(&self) -> Option<$crate::Rect> {
+                self.stage.crop
+            }
+
+            /// Display only `crop` of the buffer (e.g. to hide overscan), scaled as if it were
+            /// the whole buffer; `None` shows everything. The crop is clamped to the buffer.
+            pub fn set_crop(&mut self, crop: Option<$crate::Rect>) {
+                self.stage.set_crop(crop);
+            }
+
+            /// Fill the area around the image with `border_color` instead of `clear_color` in
+            /// `render`.
+            pub fn set_border_color(&mut self, border_color: Option<wgpu::Color>) {
+                self.stage.set_border_color(border_color);
+            }
+
+            /// Draw `background` behind the image in `render` (see `Background`).
+            pub fn set_background(&mut self, background: Option<$crate::Background>) {
+                self.stage.set_background(background);
+            }
+
+            pub fn resize_surface(&mut self, width: u32, height: u32) {
+                self.stage.resize_surface(width, height);
+            }
+
+            pub fn resize_buffer(&mut self, width: u32, height: u32) -> $crate::Result<()> {
+                self.stage.resize_buffer(width, height)
+            }
+
+            /// Start capturing the buffer (or the rendered output, see
+            /// `RecorderOptions::source`) on every `render()`, replacing any active recording.
+            pub fn start_recording(&mut self, options: $crate::RecorderOptions) {
+                self.stage.start_recording(options);
+            }
+
+            /// Stop capturing and return the recorded frames, waiting for output frames still
+            /// being read back.
+            pub fn stop_recording(&mut self) -> Option<$crate::FrameRecorder> {
+                self.stage.stop_recording()
+            }
+
+            pub fn recorder(&self) -> Option<&$crate::FrameRecorder> {
+                self.stage.recorder.as_ref()
+            }
+
+            pub fn recorder_mut(&mut self) -> Option<&mut $crate::FrameRecorder> {
+                self.stage.recorder.as_mut()
+            }
+
+            /// Write every frame passed to `render()` to `sink`; returns the previous sink once
+            /// the output frames still being read back for it were written.
+            ///
+            /// If writing a frame fails, the sink is detached and the error kept for
+            /// `take_sink_error`; `render()` carries on.
+            pub fn set_frame_sink(
+                &mut self,
+                sink: Option<$crate::FrameSink>,
+            ) -> Option<$crate::FrameSink> {
+                self.stage.set_frame_sink(sink)
+            }
+
+            /// The error that made the frame sink detach, if any since the last call.
+            pub fn take_sink_error(&mut self) -> Option<$crate::Error> {
+                self.stage.sink_error.take()
+            }
+
+            pub fn frame_sink_mut(&mut self) -> Option<&mut $crate::FrameSink> {
+                self.stage.frame_sink.as_mut()
+            }
+
+            /// Enable or disable a back buffer for `back_frame_mut()` + `swap()`; shorthand for
+            /// `set_back_buffer_count(1)` or `set_back_buffer_count(0)`.
+            pub fn set_back_buffer_enabled(&mut self, enabled: bool) {
+                self.set_back_buffer_count(usize::from(enabled));
+            }
+
+            /// Keep `count` back buffers that `swap()` rotates through: `1` for double
+            /// buffering, `2` for triple buffering, `0` to disable.
+            ///
+            /// New back buffers start as copies of the current frame.
+            pub fn set_back_buffer_count(&mut self, count: usize) {
+                self.stage.set_back_buffer_count(count);
+            }
+
+            pub fn back_buffer_count(&self) -> usize {
+                self.stage.back_pixels.len()
+            }
+
+            /// The back buffer the next `swap()` presents.
+            pub fn back_frame(&self) -> Option<&[$element]> {
+                self.stage.back_pixels.front().map(Vec::as_slice)
+            }
+
+            /// The back buffer the next `swap()` presents; writing to it does not mark anything
+            /// dirty.
+            pub fn back_frame_mut(&mut self) -> Option<&mut [$element]> {
+                self.stage.back_pixels.front_mut().map(Vec::as_mut_slice)
+            }
+
+            /// Present the next back buffer and mark the tiles whose pixels differ from the old
+            /// front buffer dirty. The old front buffer goes to the end of the queue, so with
+            /// `n` back buffers `back_frame_mut()` next returns the frame presented `n` swaps
+            /// ago.
+            ///
+            /// Returns the number of changed tiles (`0` if no back buffer is enabled).
+            pub fn swap(&mut self) -> u32 {
+                self.stage.swap()
+            }
+
+            /// Detect dirty tiles automatically by diffing against a shadow copy of the last
+            /// upload.
+            ///
+            /// With this enabled, `render()` ignores `frame_mut()`/`mark_dirty` bookkeeping and
+            /// uploads exactly the tiles whose pixels changed, at the cost of one extra buffer
+            /// copy per frame.
+            pub fn set_auto_dirty(&mut self, enabled: bool) {
+                self.stage.set_auto_dirty(enabled);
+            }
+
+            pub fn auto_dirty(&self) -> bool {
+                self.stage.shadow_pixels.is_some()
+            }
+
+            /// Number of tiles the last `render()` found changed in auto-dirty mode.
+            pub fn changed_tiles(&self) -> u32 {
+                self.stage.changed_tiles
+            }
+
+            /// Upload counters and timings of the last `render()`.
+            pub fn frame_stats(&self) -> $crate::FrameStats {
+                self.stage.stats
+            }
+
+            /// Switch how dirty regions are uploaded (see `UploadPath`).
+            pub fn set_upload_path(&mut self, upload_path: $crate::UploadPath) {
+                self.stage.set_upload_path(upload_path);
+            }
+
+            /// Create a `Send` [`FrameProducer`](crate::FrameProducer) for filling frames from
+            /// another thread.
+            ///
+            /// The latest published frame replaces this stage's buffer at `render()`. Creating a
+            /// new producer disconnects the previous one. While it is connected, draw through
+            /// the producer only: direct writes to this stage's buffer are replaced by the next
+            /// published frame.
+            pub fn producer(&mut self) -> $crate::FrameProducer<$element> {
+                self.stage.producer()
+            }
+
+            pub fn frame(&self) -> &[$element] {
+                &self.stage.pixels
+            }
+
+            pub fn frame_mut(&mut self) -> &mut [$element] {
+                self.stage.dirty.mark_full();
+                &mut self.stage.pixels
+            }
+
+            /// Mark a region as dirty (useful if you modify `frame_mut()` partially).
+            pub fn mark_dirty(&mut self, rect: $crate::Rect) {
+                self.stage.dirty.mark_rect(rect);
+            }
+
+            /// Convert a physical window position (e.g. from winit) into a buffer pixel
+            /// position.
+            pub fn window_pos_to_pixel(
+                &self,
+                physical_position: (f32, f32),
+            ) -> std::result::Result<(usize, usize), (isize, isize)> {
+                self.stage.window_pos_to_pixel(physical_position)
+            }
+
+            /// Like `window_pos_to_pixel`, for a logical window position (physical /
+            /// `scale_factor`). `Err` positions are physical, as for `window_pos_to_pixel`.
+            pub fn logical_pos_to_pixel(
+                &self,
+                logical_position: (f32, f32),
+            ) -> std::result::Result<(usize, usize), (isize, isize)> {
+                self.stage.logical_pos_to_pixel(logical_position)
+            }
+
+            /// Like `window_pos_to_pixel`, for a position in the target last given to `prepare`
+            /// or `render_to`.
+            pub fn target_pos_to_pixel(
+                &self,
+                target_position: (f32, f32),
+            ) -> std::result::Result<(usize, usize), (isize, isize)> {
+                self.stage.target_pos_to_pixel(target_position)
+            }
+
+            pub fn render(&mut self) -> $crate::Result<()> {
+                self.stage.render()
+            }
+
+            /// Upload pending changes into the stage's textures, recording the copies into
+            /// `encoder`, without drawing anything (e.g. when a texture is only sampled
+            /// elsewhere).
+            ///
+            /// Submit `encoder` before the next `upload`, `prepare`, `render_to` or `render`.
+            pub fn upload(&mut self, encoder: &mut wgpu::CommandEncoder) -> $crate::Result<()> {
+                self.stage.upload(encoder)
+            }
+
+            /// `upload`, then lay the stage out in `viewport` (in pixels of a `target_format`
+            /// view) for `draw`.
+            ///
+            /// Use this instead of `render()` to embed the stage in your own render pass.
+            pub fn prepare(
+                &mut self,
+                encoder: &mut wgpu::CommandEncoder,
+                target_format: wgpu::TextureFormat,
+                viewport: $crate::Rect,
+            ) -> $crate::Result<()> {
+                self.stage.prepare(encoder, target_format, viewport)
+            }
+
+            /// Record the stage into `pass`, as laid out by the last `prepare`.
+            ///
+            /// Only the scaled area inside the viewport is drawn; the pass viewport and scissor
+            /// rect are left set to it.
+            pub fn draw(&self, pass: &mut wgpu::RenderPass<'_>) {
+                self.stage.draw(pass);
+            }
+
+            /// `prepare`, then `draw` in a render pass of its own that keeps the contents of
+            /// `target`.
+            pub fn render_to(
+                &mut self,
+                encoder: &mut wgpu::CommandEncoder,
+                target: &wgpu::TextureView,
+                target_format: wgpu::TextureFormat,
+                viewport: $crate::Rect,
+            ) -> $crate::Result<()> {
+                self.stage.render_to(encoder, target, target_format, viewport)
+            }
+        }
+    };
+}
+
+pub(crate) use impl_stage;
@@ -0,0 +1,197 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Per-frame upload counters, available from `frame_stats()` after `render()`.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct FrameStats {
+    /// Dirty tiles going into this frame's upload (all tiles for a full upload).
+    pub tiles_dirtied: u32,
+    /// Texture uploads issued (one per merged dirty region).
+    pub dirty_regions: u32,
+    /// Bytes written to the GPU texture.
+    pub bytes_uploaded: u64,
+    /// Whether the whole buffer was uploaded.
+    pub full_upload: bool,
+    /// CPU time spent on the uploads: `cpu_convert_time` plus `cpu_pack_time`.
+    pub cpu_upload_time: Duration,
+    /// CPU time spent converting pixels to RGBA8 (zero on stages that upload their buffer
+    /// as is).
+    pub cpu_convert_time: Duration,
+    /// CPU time spent copying rows into upload memory and queueing the copies.
+    pub cpu_pack_time: Duration,
+    /// Duration of the stage's render pass on the GPU, from the most recent frame whose
+    /// timestamps have been read back (usually a frame or two behind).
+    ///
+    /// `None` unless `PixstageOptions::gpu_timing` is set and the adapter supports
+    /// `wgpu::Features::TIMESTAMP_QUERY`.
+    pub gpu_time: Option<Duration>,
+}
+
+impl FrameStats {
+    /// Record the CPU time of an upload that took `elapsed` in total, `convert` of it
+    /// converting pixels; the rest counts as packing.
+    pub(crate) fn record_cpu_upload(&mut self, elapsed: Duration, convert: Duration) {
+        self.cpu_convert_time = convert.min(elapsed);
+        self.cpu_pack_time = elapsed - self.cpu_convert_time;
+        self.cpu_upload_time = elapsed;
+    }
+}
+
+const READBACK_PENDING: u8 = 0;
+const READBACK_READY: u8 = 1;
+const READBACK_FAILED: u8 = 2;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum TimerState {
+    Idle,
+    Recorded,
+    Mapping,
+}
+
+/// Timestamp queries around the stage's render pass, read back without blocking.
+///
+/// Only one measurement is in flight at a time; frames rendered while a readback is pending
+/// are not timed.
+#[derive(Debug)]
+pub(crate) struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    period_ns: f32,
+    state: TimerState,
+    readback: Arc<AtomicU8>,
+}
+
+impl GpuTimer {
+    const SIZE: wgpu::BufferAddress = 2 * std::mem::size_of::<u64>() as wgpu::BufferAddress;
+
+    /// `None` if the device was created without `wgpu::Features::TIMESTAMP_QUERY`.
+    pub(crate) fn new(device: &wgpu::Device, queue: &wgpu::Queue, label: &str) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some(&format!("{label}_timestamp_queries")),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{label}_timestamp_resolve_buffer")),
+            size: Self::SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{label}_timestamp_readback_buffer")),
+            size: Self::SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period_ns: queue.get_timestamp_period(),
+            state: TimerState::Idle,
+            readback: Arc::new(AtomicU8::new(READBACK_PENDING)),
+        })
+    }
+
+    /// Timestamp writes for this frame's render pass, if no readback is pending.
+    pub(crate) fn timestamp_writes(&self) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        (self.state == TimerState::Idle).then_some(wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(1),
+        })
+    }
+
+    /// Resolve the timestamps written this frame; call after the render pass ends.
+    pub(crate) fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if self.state != TimerState::Idle {
+            return;
+        }
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            Self::SIZE,
+        );
+        self.state = TimerState::Recorded;
+    }
+
+    /// Start reading back this frame's timestamps and return any finished measurement.
+    ///
+    /// Call after the frame's command buffer has been submitted.
+    pub(crate) fn after_submit(&mut self, device: &wgpu::Device) -> Option<Duration> {
+        match self.state {
+            TimerState::Idle => None,
+            TimerState::Recorded => {
+                let readback = Arc::clone(&self.readback);
+                readback.store(READBACK_PENDING, Ordering::Release);
+                self.readback_buffer
+                    .slice(..)
+                    .map_async(wgpu::MapMode::Read, move |result| {
+                        let status = if result.is_ok() {
+                            READBACK_READY
+                        } else {
+                            READBACK_FAILED
+                        };
+                        readback.store(status, Ordering::Release);
+                    });
+                self.state = TimerState::Mapping;
+                None
+            }
+            TimerState::Mapping => {
+                let _ = device.poll(wgpu::PollType::Poll);
+                match self.readback.load(Ordering::Acquire) {
+                    READBACK_READY => {
+                        let elapsed = {
+                            let data = self.readback_buffer.slice(..).get_mapped_range();
+                            let start: u64 = bytemuck::pod_read_unaligned(&data[0..8]);
+                            let end: u64 = bytemuck::pod_read_unaligned(&data[8..16]);
+                            let ticks = end.saturating_sub(start);
+                            Duration::from_nanos((ticks as f64 * self.period_ns as f64) as u64)
+                        };
+                        self.readback_buffer.unmap();
+                        self.state = TimerState::Idle;
+                        Some(elapsed)
+                    }
+                    READBACK_FAILED => {
+                        self.state = TimerState::Idle;
+                        None
+                    }
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upload_time_is_convert_plus_pack() {
+        let ms = Duration::from_millis;
+        for (elapsed, convert) in [
+            (ms(5), ms(2)),
+            (ms(5), ms(0)),
+            (ms(5), ms(5)),
+            (ms(3), ms(4)),
+        ] {
+            let mut stats = FrameStats::default();
+            stats.record_cpu_upload(elapsed, convert);
+            assert_eq!(stats.cpu_upload_time, elapsed);
+            assert_eq!(
+                stats.cpu_upload_time,
+                stats.cpu_convert_time + stats.cpu_pack_time
+            );
+            assert!(stats.cpu_convert_time <= convert);
+        }
+    }
+}
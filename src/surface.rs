@@ -6,13 +6,15 @@ pub struct SurfaceTexture<W: wgpu::WindowHandle> {
     pub(crate) window: W,
     pub(crate) width: u32,
     pub(crate) height: u32,
+    pub(crate) scale_factor: f64,
 }
 
 impl<W: wgpu::WindowHandle> SurfaceTexture<W> {
     /// Create a logical surface texture.
     ///
-    /// It is recommended (but not required) that the `width` and `height` are the physical surface
-    /// dimensions (e.g. scaled by the HiDPI factor).
+    /// `width` and `height` are the physical surface dimensions (e.g. winit's `inner_size`). Pass
+    /// the HiDPI factor with `with_scale_factor` so stages can map logical positions and scale
+    /// in logical pixels (see `PixelUnits`).
     pub fn new(width: u32, height: u32, window: W) -> Result<Self, Error> {
         if width == 0 || height == 0 {
             return Err(Error::InvalidSurfaceSize { width, height });
@@ -21,10 +23,33 @@ impl<W: wgpu::WindowHandle> SurfaceTexture<W> {
             window,
             width,
             height,
+            scale_factor: 1.0,
         })
     }
 
+    /// Set the HiDPI scale factor (physical pixels per logical pixel, e.g. winit's
+    /// `scale_factor()`). Invalid factors are ignored.
+    pub fn with_scale_factor(mut self, scale_factor: f64) -> Self {
+        if scale_factor.is_finite() && scale_factor > 0.0 {
+            self.scale_factor = scale_factor;
+        }
+        self
+    }
+
+    /// Physical size.
     pub fn size(&self) -> (u32, u32) {
         (self.width, self.height)
     }
+
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    /// Size in logical pixels.
+    pub fn logical_size(&self) -> (f64, f64) {
+        (
+            self.width as f64 / self.scale_factor,
+            self.height as f64 / self.scale_factor,
+        )
+    }
 }
@@ -0,0 +1,117 @@
+use crate::Rect;
+
+/// How dirty regions reach the GPU texture.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum UploadPath {
+    /// One `Queue::write_texture` per dirty region, staged through a reusable CPU buffer.
+    #[default]
+    WriteTexture,
+    /// Pack all dirty regions into one mapped `wgpu::util::StagingBelt` allocation and copy them
+    /// with `copy_buffer_to_texture` in the frame's command encoder. Avoids the extra CPU copy
+    /// and per-call overhead when a frame has many small regions.
+    StagingBelt,
+}
+
+/// Uploads through a `StagingBelt`, with each row padded to `COPY_BYTES_PER_ROW_ALIGNMENT`.
+pub(crate) struct StagingUploader {
+    belt: wgpu::util::StagingBelt,
+}
+
+impl std::fmt::Debug for StagingUploader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StagingUploader").finish_non_exhaustive()
+    }
+}
+
+impl StagingUploader {
+    /// Chunks are sized to hold a full `width x height` upload, so a frame needs one chunk.
+    pub(crate) fn new(width: u32, height: u32, bytes_per_pixel: u32) -> Self {
+        let chunk_size = padded_bytes_per_row(width, bytes_per_pixel) as u64 * height as u64;
+        Self {
+            belt: wgpu::util::StagingBelt::new(chunk_size.max(1 << 16)),
+        }
+    }
+
+    /// Pack `regions` into one staging allocation and record their copies into `encoder`.
+    ///
+    /// `write_region(region, dst, pitch)` fills `dst` with the rows of `region`, one row every
+    /// `pitch` bytes (`region.width * bytes_per_pixel` of which are used).
+    pub(crate) fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        regions: &[Rect],
+        bytes_per_pixel: u32,
+        mut write_region: impl FnMut(Rect, &mut [u8], usize),
+    ) {
+        let total: u64 = regions
+            .iter()
+            .map(|region| {
+                padded_bytes_per_row(region.width, bytes_per_pixel) as u64 * region.height as u64
+            })
+            .sum();
+        let Some(size) = wgpu::BufferSize::new(total) else {
+            return;
+        };
+        let alignment =
+            wgpu::BufferSize::new(wgpu::COPY_BUFFER_ALIGNMENT).expect("alignment is non-zero");
+        let slice = self.belt.allocate(size, alignment, device);
+
+        {
+            let mut view = slice.get_mapped_range_mut();
+            let mut offset = 0usize;
+            for region in regions {
+                let pitch = padded_bytes_per_row(region.width, bytes_per_pixel) as usize;
+                let len = pitch * region.height as usize;
+                write_region(*region, &mut view[offset..offset + len], pitch);
+                offset += len;
+            }
+        }
+
+        let mut offset = slice.offset();
+        for region in regions {
+            let pitch = padded_bytes_per_row(region.width, bytes_per_pixel);
+            encoder.copy_buffer_to_texture(
+                wgpu::TexelCopyBufferInfo {
+                    buffer: slice.buffer(),
+                    layout: wgpu::TexelCopyBufferLayout {
+                        offset,
+                        bytes_per_row: Some(pitch),
+                        rows_per_image: Some(region.height),
+                    },
+                },
+                wgpu::TexelCopyTextureInfo {
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: region.x,
+                        y: region.y,
+                        z: 0,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::Extent3d {
+                    width: region.width,
+                    height: region.height,
+                    depth_or_array_layers: 1,
+                },
+            );
+            offset += pitch as u64 * region.height as u64;
+        }
+    }
+
+    /// Close this frame's staging buffers; call before submitting the encoder.
+    pub(crate) fn finish(&mut self) {
+        self.belt.finish();
+    }
+
+    /// Reclaim staging buffers once the GPU is done with them; call after submitting.
+    pub(crate) fn recall(&mut self) {
+        self.belt.recall();
+    }
+}
+
+pub(crate) fn padded_bytes_per_row(width: u32, bytes_per_pixel: u32) -> u32 {
+    (width * bytes_per_pixel).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+}